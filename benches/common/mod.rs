@@ -0,0 +1,44 @@
+//! Key generators shared by the benches.
+
+// ── Hand-rolled LCG (no external deps) ──────────────────────────────────────
+
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    pub fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        self.0
+    }
+    /// Returns a value in [0, bound).
+    pub fn next_range(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+// ── Hiragana char keys (50K) ────────────────────────────────────────────────
+
+/// 'あ' (U+3041) .. 'ん' (U+3093) — 83 hiragana codepoints
+pub const HIRAGANA_START: u32 = 0x3041;
+pub const HIRAGANA_COUNT: u64 = 83; // U+3041..=U+3093
+
+pub fn generate_char_keys(n: usize, seed: u64) -> Vec<Vec<char>> {
+    let mut rng = Lcg::new(seed);
+    let mut set = std::collections::BTreeSet::new();
+    while set.len() < n {
+        let len = (rng.next_range(7) + 2) as usize; // 2..=8
+        let key: Vec<char> = (0..len)
+            .map(|_| {
+                let cp = HIRAGANA_START + rng.next_range(HIRAGANA_COUNT) as u32;
+                char::from_u32(cp).unwrap()
+            })
+            .collect();
+        set.insert(key);
+    }
+    set.into_iter().collect() // already sorted & unique
+}
@@ -0,0 +1,173 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{sink, Write};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput,
+};
+use lexime_trie::DoubleArray;
+
+mod common;
+
+use common::generate_char_keys;
+
+// ── Heap accounting ─────────────────────────────────────────────────────────
+
+/// Global allocator that tracks live and peak heap bytes.
+struct CountingAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(bytes: usize) {
+    let live = LIVE.fetch_add(bytes, Relaxed) + bytes;
+    PEAK.fetch_max(live, Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            // A moving realloc briefly holds both blocks; count both.
+            grow(new_size);
+            LIVE.fetch_sub(layout.size(), Relaxed);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Criterion measurement of the peak heap usage above the level at start.
+struct PeakHeap;
+
+impl Measurement for PeakHeap {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        let live = LIVE.load(Relaxed);
+        PEAK.store(live, Relaxed);
+        live
+    }
+
+    fn end(&self, start: usize) -> usize {
+        PEAK.load(Relaxed).saturating_sub(start)
+    }
+
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &BytesFormatter
+    }
+}
+
+struct BytesFormatter;
+
+impl ValueFormatter for BytesFormatter {
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+        let (factor, unit) = if typical_value < 1024.0 {
+            (1.0, "B")
+        } else if typical_value < 1024.0 * 1024.0 {
+            (1024.0, "KiB")
+        } else {
+            (1024.0 * 1024.0, "MiB")
+        };
+        for v in values {
+            *v /= factor;
+        }
+        unit
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        _throughput: &Throughput,
+        _values: &mut [f64],
+    ) -> &'static str {
+        "B"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+// ── Benchmarks ──────────────────────────────────────────────────────────────
+
+/// Peak heap bytes of build + serialize, and of serializing a built trie:
+/// `as_bytes` holds a serialized copy while `write_to` and `build_to_writer`
+/// stream the sections. With a build, its working buffers set the peak.
+fn bench_peak_heap(c: &mut Criterion<PeakHeap>) {
+    let mut group = c.benchmark_group("peak_heap");
+    group.sampling_mode(SamplingMode::Flat).sample_size(10);
+    for n in [10_000, 50_000] {
+        let keys = generate_char_keys(n, 42);
+        let mut run = |name: &str, f: &dyn Fn()| {
+            group.bench_function(BenchmarkId::new(name, n), |b| {
+                b.iter_custom(|iters| {
+                    let mut peak = 0;
+                    for _ in 0..iters {
+                        let start = PeakHeap.start();
+                        f();
+                        peak = peak.max(PeakHeap.end(start));
+                    }
+                    // Criterion reports the total divided by `iters`.
+                    peak * iters as usize
+                });
+            });
+        };
+        run("build_then_as_bytes", &|| {
+            let encoded = DoubleArray::<char>::build(&keys).as_bytes();
+            sink().write_all(&encoded).unwrap();
+        });
+        run("build_to_writer", &|| {
+            DoubleArray::<char>::build_to_writer(&keys, &mut sink()).unwrap();
+        });
+
+        let da = DoubleArray::<char>::build(&keys);
+        run("as_bytes", &|| {
+            sink().write_all(&da.as_bytes()).unwrap();
+        });
+        run("write_to", &|| {
+            da.write_to(&mut sink()).unwrap();
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // Peaks repeat exactly between runs; the density plots need variance.
+    config = Criterion::default()
+        .with_measurement(PeakHeap)
+        .without_plots()
+        .warm_up_time(std::time::Duration::from_millis(100));
+    targets = bench_peak_heap
+}
+criterion_main!(benches);
@@ -1,48 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use lexime_trie::{DoubleArray, DoubleArrayRef};
+use lexime_trie::{CodeMapper, DoubleArray, DoubleArrayRef};
 
-// ── Hand-rolled LCG (no external deps) ──────────────────────────────────────
+mod common;
 
-struct Lcg(u64);
-
-impl Lcg {
-    fn new(seed: u64) -> Self {
-        Self(seed)
-    }
-    fn next(&mut self) -> u64 {
-        self.0 = self
-            .0
-            .wrapping_mul(6_364_136_223_846_793_005)
-            .wrapping_add(1_442_695_040_888_963_407);
-        self.0
-    }
-    /// Returns a value in [0, bound).
-    fn next_range(&mut self, bound: u64) -> u64 {
-        self.next() % bound
-    }
-}
-
-// ── Hiragana char keys (50K) ────────────────────────────────────────────────
-
-/// 'あ' (U+3041) .. 'ん' (U+3093) — 83 hiragana codepoints
-const HIRAGANA_START: u32 = 0x3041;
-const HIRAGANA_COUNT: u64 = 83; // U+3041..=U+3093
-
-fn generate_char_keys(n: usize, seed: u64) -> Vec<Vec<char>> {
-    let mut rng = Lcg::new(seed);
-    let mut set = std::collections::BTreeSet::new();
-    while set.len() < n {
-        let len = (rng.next_range(7) + 2) as usize; // 2..=8
-        let key: Vec<char> = (0..len)
-            .map(|_| {
-                let cp = HIRAGANA_START + rng.next_range(HIRAGANA_COUNT) as u32;
-                char::from_u32(cp).unwrap()
-            })
-            .collect();
-        set.insert(key);
-    }
-    set.into_iter().collect() // already sorted & unique
-}
+use common::{generate_char_keys, Lcg, HIRAGANA_COUNT, HIRAGANA_START};
 
 // ── Romaji u8 keys ──────────────────────────────────────────────────────────
 
@@ -72,6 +33,72 @@ fn bench_build(c: &mut Criterion) {
     });
 }
 
+/// The key sort dominates `build_unsorted` on large inputs; compare runs with
+/// and without `--features parallel`.
+fn bench_build_unsorted(c: &mut Criterion) {
+    let mut keys = generate_char_keys(500_000, 42);
+    let mut rng = Lcg::new(4242);
+    for i in (1..keys.len()).rev() {
+        keys.swap(i, rng.next_range(i as u64 + 1) as usize);
+    }
+    c.bench_function("build_unsorted_500k_char", |b| {
+        b.iter(|| DoubleArray::<char>::build_unsorted(black_box(&keys)));
+    });
+}
+
+/// The frequency pass dominates `CodeMapper::build`; compare runs with and
+/// without `--features parallel`.
+fn bench_code_map(c: &mut Criterion) {
+    let keys = generate_char_keys(500_000, 42);
+    c.bench_function("code_map_build_500k_char", |b| {
+        b.iter(|| CodeMapper::build(black_box(&keys)));
+    });
+}
+
+/// Keys over scattered CJK extension and private use codepoints, where a
+/// plain `char` code map is sized by the largest codepoint.
+fn generate_sparse_char_keys(n: usize, seed: u64) -> Vec<Vec<char>> {
+    let mut rng = Lcg::new(seed);
+    let mut set = std::collections::BTreeSet::new();
+    while set.len() < n {
+        let len = (rng.next_range(3) + 1) as usize; // 1..=3
+        let key: Vec<char> = (0..len)
+            .map(|_| {
+                let base = if rng.next_range(2) == 0 {
+                    0x2_0000
+                } else {
+                    0xF_0000
+                };
+                char::from_u32(base + rng.next_range(0xFFFD) as u32).unwrap()
+            })
+            .collect();
+        set.insert(key);
+    }
+    set.into_iter().collect()
+}
+
+fn bench_partitioned(c: &mut Criterion) {
+    let keys = generate_sparse_char_keys(10_000, 42);
+    let plain = DoubleArray::<char>::build(&keys);
+    let partitioned = DoubleArray::<char>::build_partitioned(&keys);
+
+    c.bench_function("exact_match_sparse_plain", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(plain.exact_match(black_box(key)));
+            }
+        });
+    });
+
+    c.bench_function("exact_match_sparse_partitioned", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(partitioned.exact_match(black_box(key)));
+            }
+        });
+    });
+}
+
 fn bench_serial(c: &mut Criterion) {
     let keys = generate_char_keys(50_000, 42);
     let da = DoubleArray::<char>::build(&keys);
@@ -133,6 +160,50 @@ fn bench_exact_match(c: &mut Criterion) {
     });
 }
 
+fn bench_constant_time(c: &mut Criterion) {
+    // 10K hex API tokens of 32 bytes.
+    let mut rng = Lcg::new(77);
+    let mut tokens: Vec<Vec<u8>> = (0..10_000)
+        .map(|_| {
+            (0..32)
+                .map(|_| b"0123456789abcdef"[rng.next_range(16) as usize])
+                .collect()
+        })
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    let da = DoubleArray::<u8>::build(&tokens);
+
+    // Guesses that differ from a real token at the first or the last byte.
+    let flip = |key: &[u8], at: usize| {
+        let mut key = key.to_vec();
+        key[at] = if key[at] == b'0' { b'1' } else { b'0' };
+        key
+    };
+    let picks: Vec<&Vec<u8>> = (0..1000)
+        .map(|_| &tokens[rng.next_range(tokens.len() as u64) as usize])
+        .collect();
+    let early: Vec<Vec<u8>> = picks.iter().map(|k| flip(k, 0)).collect();
+    let late: Vec<Vec<u8>> = picks.iter().map(|k| flip(k, 31)).collect();
+
+    for (name, keys) in [("early_miss", &early), ("late_miss", &late)] {
+        c.bench_function(&format!("exact_match_{name}_1k_token"), |b| {
+            b.iter(|| {
+                for key in keys {
+                    black_box(da.exact_match(black_box(key)));
+                }
+            });
+        });
+        c.bench_function(&format!("exact_match_constant_time_{name}_1k_token"), |b| {
+            b.iter(|| {
+                for key in keys {
+                    black_box(da.exact_match_constant_time(black_box(key)));
+                }
+            });
+        });
+    }
+}
+
 fn bench_common_prefix_search(c: &mut Criterion) {
     let keys = generate_char_keys(50_000, 42);
     let da = DoubleArray::<char>::build(&keys);
@@ -206,8 +277,12 @@ fn bench_probe(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_build,
+    bench_build_unsorted,
+    bench_code_map,
+    bench_partitioned,
     bench_serial,
     bench_exact_match,
+    bench_constant_time,
     bench_common_prefix_search,
     bench_predictive_search,
     bench_probe,
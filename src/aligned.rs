@@ -0,0 +1,251 @@
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::serial::Layout;
+use crate::view::TrieView;
+use crate::{
+    CodeMapper, DoubleArray, Label, Node, NodeInfo, PrefixMatch, ProbeResult, SearchMatch,
+    TrieError,
+};
+
+/// Backing storage of an [`OwnedAlignedTrie`].
+enum Storage {
+    /// The caller's buffer, already aligned for `Node` and `u32` access.
+    Bytes(Vec<u8>),
+    /// A 4-byte aligned copy of a misaligned buffer, and its length in bytes.
+    Words(Vec<u32>, usize),
+}
+
+impl Storage {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Storage::Bytes(bytes) => bytes,
+            // SAFETY: the Vec<u32> holds at least `len` initialized bytes.
+            Storage::Words(words, len) => unsafe {
+                std::slice::from_raw_parts(words.as_ptr() as *const u8, *len)
+            },
+        }
+    }
+}
+
+/// An owned serialized double-array trie (v2 or v4 format) searched in place.
+///
+/// The owned analog of [`DoubleArrayRef`](crate::DoubleArrayRef): it takes
+/// the `Vec<u8>` read from a file and views `nodes` and `siblings` directly
+/// from it, so there is no lifetime to manage. The buffer is copied once,
+/// into 4-byte aligned storage, only if it is not already aligned.
+///
+/// `code_map` is always heap-allocated since it is small and requires
+/// deserialization.
+pub struct OwnedAlignedTrie<L: Label> {
+    storage: Storage,
+    node_count: usize,
+    nodes_offset: usize,
+    siblings_offset: usize,
+    code_map: CodeMapper,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Label> OwnedAlignedTrie<L> {
+    /// Takes ownership of a serialized trie (v2 or v4 format).
+    ///
+    /// The buffer is kept as is when it is aligned to 4 bytes, which standard
+    /// allocators provide; otherwise it is copied once into aligned storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TrieError::InvalidMagic`] if the magic bytes don't match.
+    /// Returns [`TrieError::InvalidVersion`] if the version is not v2 or v4.
+    /// Returns [`TrieError::TruncatedData`] if the buffer is too short.
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Self, TrieError> {
+        let aligned = (bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<Node>());
+        Self::from_vec_inner(bytes, !aligned)
+    }
+
+    fn from_vec_inner(bytes: Vec<u8>, realign: bool) -> Result<Self, TrieError> {
+        let layout = Layout::parse(&bytes)?;
+        let (code_map, _) =
+            CodeMapper::from_bytes(&bytes[layout.code_map]).ok_or(TrieError::TruncatedData)?;
+
+        let storage = if realign {
+            let len = bytes.len();
+            let mut words = vec![0u32; len.div_ceil(4)];
+            // SAFETY: `words` holds at least `len` bytes; u32 has no invalid bit patterns.
+            unsafe {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), words.as_mut_ptr() as *mut u8, len);
+            }
+            Storage::Words(words, len)
+        } else {
+            Storage::Bytes(bytes)
+        };
+
+        Ok(Self {
+            storage,
+            node_count: layout.node_count,
+            nodes_offset: layout.nodes.start,
+            siblings_offset: layout.siblings.start,
+            code_map,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns a `TrieView` borrowing this trie's data.
+    #[inline]
+    fn view(&self) -> TrieView<'_, L> {
+        let bytes = self.storage.as_slice();
+        // SAFETY:
+        // - `Node` is `#[repr(C)]` with two `u32` fields, size 8, align 4, no padding
+        // - Layout::parse verified both sections lie within the buffer and hold
+        //   `node_count` entries each
+        // - The buffer start is 4-byte aligned (checked, or guaranteed by
+        //   Vec<u32>), and both section offsets are multiples of 4
+        // - The data is valid for any bit pattern (u32 fields), and the crate
+        //   only supports little-endian platforms
+        let nodes = unsafe {
+            std::slice::from_raw_parts(
+                bytes[self.nodes_offset..].as_ptr() as *const Node,
+                self.node_count,
+            )
+        };
+        let siblings = unsafe {
+            std::slice::from_raw_parts(
+                bytes[self.siblings_offset..].as_ptr() as *const u32,
+                self.node_count,
+            )
+        };
+        TrieView {
+            nodes,
+            siblings,
+            code_map: &self.code_map,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the serialized bytes this trie is read from.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.storage.as_slice()
+    }
+
+    /// Returns the number of nodes in the trie.
+    pub fn num_nodes(&self) -> usize {
+        self.node_count
+    }
+
+    /// Returns the number of nodes actually used by the trie (root plus
+    /// non-default nodes), excluding holes.
+    pub fn num_used_nodes(&self) -> usize {
+        self.view().num_used_nodes()
+    }
+
+    /// Returns the number of keys; see [`DoubleArray::num_keys`].
+    pub fn num_keys(&self) -> usize {
+        self.view().num_keys()
+    }
+
+    /// Exact match search. Returns the value_id if the key exists.
+    #[inline]
+    pub fn exact_match(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match(key.as_ref())
+    }
+
+    /// Common prefix search. Returns an iterator over all prefixes of `query`
+    /// that exist as keys in the trie.
+    pub fn common_prefix_search<'b, Q: AsRef<[L]> + ?Sized>(
+        &'b self,
+        query: &'b Q,
+    ) -> impl Iterator<Item = PrefixMatch> + 'b {
+        self.view().common_prefix_search(query.as_ref())
+    }
+
+    /// Predictive search. Returns an iterator over all keys that start with `prefix`.
+    pub fn predictive_search<'b, Q: AsRef<[L]> + ?Sized>(
+        &'b self,
+        prefix: &'b Q,
+    ) -> impl Iterator<Item = SearchMatch<L>> + 'b {
+        self.view().predictive_search(prefix.as_ref())
+    }
+
+    /// Probe a key. Returns whether the key exists and whether it has children.
+    #[inline]
+    pub fn probe(&self, key: impl AsRef<[L]>) -> ProbeResult {
+        self.view().probe(key.as_ref())
+    }
+
+    /// Inspects an arbitrary path. Returns `None` only if the path does not
+    /// exist structurally.
+    pub fn inspect(&self, path: impl AsRef<[L]>) -> Option<NodeInfo> {
+        self.view().inspect(path.as_ref())
+    }
+
+    /// Converts this trie to an owned [`DoubleArray`].
+    pub fn to_owned(&self) -> DoubleArray<L> {
+        let view = self.view();
+        DoubleArray::new(
+            view.nodes.to_vec(),
+            view.siblings.to_vec(),
+            self.code_map.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> (Vec<&'static [u8]>, DoubleArray<u8>) {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bc"];
+        let da = DoubleArray::build(&keys);
+        (keys, da)
+    }
+
+    fn assert_searches(trie: &OwnedAlignedTrie<u8>, keys: &[&[u8]]) {
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(trie.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(trie.exact_match(b"c"), None);
+        assert_eq!(trie.common_prefix_search(b"abcd").count(), 3);
+        assert_eq!(trie.predictive_search(b"b").count(), 2);
+    }
+
+    #[test]
+    fn aligned_vec_is_kept_without_copy() {
+        let (keys, da) = sample();
+        let bytes = da.as_bytes();
+        let ptr = bytes.as_ptr();
+        if !(ptr as usize).is_multiple_of(mem::align_of::<Node>()) {
+            // Not every allocator (e.g. Miri's) returns aligned Vec<u8> memory.
+            return;
+        }
+        let trie = OwnedAlignedTrie::<u8>::from_vec(bytes).unwrap();
+        assert!(matches!(trie.storage, Storage::Bytes(_)));
+        assert_eq!(trie.as_bytes().as_ptr(), ptr);
+        assert_searches(&trie, &keys);
+    }
+
+    #[test]
+    fn misaligned_vec_is_realigned() {
+        let (keys, da) = sample();
+        let bytes = da.as_bytes();
+        let trie = OwnedAlignedTrie::<u8>::from_vec_inner(bytes.clone(), true).unwrap();
+        assert!(matches!(trie.storage, Storage::Words(..)));
+        assert!((trie.as_bytes().as_ptr() as usize).is_multiple_of(4));
+        assert_eq!(trie.as_bytes(), bytes.as_slice());
+        assert_searches(&trie, &keys);
+        assert_eq!(trie.to_owned().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn from_vec_rejects_bad_data() {
+        let (_, da) = sample();
+        let mut bytes = da.as_bytes();
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(
+            OwnedAlignedTrie::<u8>::from_vec(bytes).err(),
+            Some(TrieError::TruncatedData)
+        );
+        assert_eq!(
+            OwnedAlignedTrie::<u8>::from_vec(b"NOPE".repeat(8)).err(),
+            Some(TrieError::InvalidMagic)
+        );
+    }
+}
@@ -1,10 +1,31 @@
-use crate::{CodeMapper, DoubleArray, Label, Node};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::node::MASK;
+use crate::{BuildError, CodeMapper, DoubleArray, Label, Node, MAX_NODES};
 
 /// Mutable state used during trie construction.
 struct BuildContext {
     nodes: Vec<Node>,
     siblings: Vec<u32>,
     free_list: FreeList,
+    /// Leaf node index for each key, indexed by key position.
+    leaves: Vec<u32>,
+    /// The node arrays never grow past this many slots.
+    max_nodes: usize,
+    /// No node may have more children than this, its terminal included.
+    max_fan_out: usize,
+    /// Counters for [`DoubleArray::build_with_metrics`].
+    metrics: BuildMetrics,
+    /// `(code, begin, end)` of the children of every node on the current
+    /// `build_rec` path, innermost last. Shared so small nodes, the bulk of
+    /// a trie, do not each allocate.
+    children: Vec<(u32, usize, usize)>,
+    /// Scratch for ordering one node's sibling chain.
+    chain: Vec<u32>,
+    /// Store each key's value on its last-label node instead of in a
+    /// terminal child. Only valid for prefix-free keys.
+    prefix_free: bool,
 }
 
 /// Doubly-linked circular free list for managing unused node slots.
@@ -92,37 +113,153 @@ impl FreeList {
     }
 }
 
+/// Statistics of the trie [`build`](DoubleArray::build) would produce, from
+/// [`DoubleArray::plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildPlan {
+    /// Number of keys.
+    pub num_keys: usize,
+    /// Number of used nodes: the root, one per distinct non-empty prefix and
+    /// one terminal per key. Exactly the built trie's
+    /// [`num_used_nodes`](DoubleArray::num_used_nodes).
+    pub used_nodes: usize,
+    /// Number of distinct codes including the terminal symbol.
+    pub alphabet_size: u32,
+    /// Length of the longest key, in labels.
+    pub max_depth: usize,
+    /// Largest number of children of any node, terminals included; see
+    /// [`build_with_fan_out_limit`](DoubleArray::build_with_fan_out_limit).
+    pub max_fan_out: usize,
+    /// Serialized size if the trie had no holes. The built trie's
+    /// [`serialized_size`](DoubleArray::serialized_size) is at least this.
+    ///
+    /// Base placement leaves holes, so [`num_nodes`](DoubleArray::num_nodes)
+    /// exceeds `used_nodes`, typically by a few percent and for ordinary key
+    /// sets by less than a factor of 1.5.
+    pub estimated_bytes: usize,
+}
+
+/// Counters describing how hard a build worked, from
+/// [`DoubleArray::build_with_metrics`]. Useful to tune the initial capacity
+/// and the code order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BuildMetrics {
+    /// Largest length of the node array during the build, before trailing
+    /// unused slots were trimmed.
+    pub peak_capacity: usize,
+    /// Number of times the node array grew past its initial capacity.
+    pub num_regrows: usize,
+    /// Total number of candidate slots `find_base` rejected, across all nodes.
+    /// High counts mean children rarely fit the free slots, e.g. with wide
+    /// nodes or scattered codes.
+    pub find_base_iterations: usize,
+    /// Whether the built trie [`is_sparse`](DoubleArray::is_sparse): its
+    /// load factor is below
+    /// [`SPARSE_LOAD_FACTOR`](DoubleArray::SPARSE_LOAD_FACTOR).
+    pub sparse: bool,
+}
+
+/// The [`BuildPlan`]s of a key set as a `char` trie and as a `u8` trie of
+/// its UTF-8 encoding, from [`DoubleArray::recommend_label_type`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelRecommendation {
+    /// Plan of the `DoubleArray<char>`.
+    pub char_plan: BuildPlan,
+    /// Plan of the `DoubleArray<u8>` over the keys' UTF-8 bytes.
+    pub utf8_plan: BuildPlan,
+}
+
+impl LabelRecommendation {
+    /// Whether the `u8` trie is estimated to be no larger than the `char`
+    /// one. Ties go to `u8`, whose queries are plain byte slices.
+    pub fn prefers_u8(&self) -> bool {
+        self.utf8_plan.estimated_bytes <= self.char_plan.estimated_bytes
+    }
+}
+
+/// Storage for a trie's node and sibling arrays, possibly reused from an
+/// earlier trie so that building does not allocate them afresh.
+#[derive(Default)]
+struct NodeBuffers {
+    nodes: Vec<Node>,
+    siblings: Vec<u32>,
+}
+
 impl BuildContext {
-    fn new(capacity: usize) -> Self {
-        let mut free_list = FreeList::new(capacity);
-        free_list.remove(0); // root is at index 0
+    /// Creates a context that builds into `buffers`, clearing their contents
+    /// but keeping their allocations.
+    ///
+    /// `capacity` must not exceed `max_nodes`.
+    fn with_buffers(
+        buffers: NodeBuffers,
+        capacity: usize,
+        num_keys: usize,
+        max_nodes: usize,
+        max_fan_out: usize,
+    ) -> Self {
+        debug_assert!(capacity <= max_nodes);
+        let NodeBuffers {
+            mut nodes,
+            mut siblings,
+        } = buffers;
+        nodes.clear();
+        nodes.resize(capacity, Node::default());
+        siblings.clear();
+        siblings.resize(capacity, 0);
+        // Index 0 is the root and doubles as the free list's sentinel, so it is
+        // never handed out. Removing it from the list would detach the sentinel
+        // and leave every initial slot unreachable to `first_free`.
         Self {
-            nodes: vec![Node::default(); capacity],
-            siblings: vec![0u32; capacity],
-            free_list,
+            nodes,
+            siblings,
+            free_list: FreeList::new(capacity),
+            leaves: vec![0u32; num_keys],
+            max_nodes,
+            max_fan_out,
+            metrics: BuildMetrics {
+                peak_capacity: capacity,
+                ..BuildMetrics::default()
+            },
+            children: Vec::new(),
+            chain: Vec::new(),
+            prefix_free: false,
         }
     }
 
-    /// Ensures all arrays cover at least `new_cap` indices.
-    fn ensure_capacity(&mut self, new_cap: usize) {
+    /// Ensures all arrays cover at least `needed` indices, growing them to
+    /// `new_cap` (clamped to the node budget).
+    ///
+    /// Returns [`BuildError::ExceededNodeBudget`] if `needed` exceeds the
+    /// budget, which is at most [`MAX_NODES`] since indices must fit in 31 bits.
+    fn ensure_capacity(&mut self, needed: usize, new_cap: usize) -> Result<(), BuildError> {
+        if needed > self.max_nodes {
+            return Err(BuildError::ExceededNodeBudget);
+        }
+        let new_cap = new_cap.max(needed).min(self.max_nodes);
         if new_cap > self.nodes.len() {
             self.nodes.resize(new_cap, Node::default());
             self.siblings.resize(new_cap, 0);
             self.free_list.grow(new_cap);
+            self.metrics.num_regrows += 1;
+            self.metrics.peak_capacity = new_cap;
         }
+        Ok(())
     }
 
     /// Recursively places children for keys[begin..end] at the given depth.
+    ///
+    /// The terminal of `coded_keys[i]` stores `values[i]` as its value_id.
     fn build_rec(
         &mut self,
         coded_keys: &[Vec<u32>],
+        values: &[u32],
         begin: usize,
         end: usize,
         depth: usize,
         parent: u32,
-    ) {
+    ) -> Result<(), BuildError> {
         // Collect distinct child labels and their key ranges
-        let mut children: Vec<(u32, usize, usize)> = Vec::new(); // (code, begin, end)
+        let first = self.children.len();
         let mut i = begin;
         while i < end {
             let code = coded_keys[i][depth];
@@ -131,46 +268,92 @@ impl BuildContext {
             while i < end && coded_keys[i][depth] == code {
                 i += 1;
             }
-            children.push((code, child_begin, i));
+            self.children.push((code, child_begin, i));
+        }
+        let fan_out = self.children.len() - first;
+        // Checked before placement: finding a base for a very wide node is
+        // exactly the cost the limit exists to avoid.
+        if fan_out > self.max_fan_out {
+            return Err(BuildError::ExceededFanOut { fan_out });
         }
 
         // Find a base such that base XOR code is free for all children
-        let base = self.find_base(&children);
-        self.nodes[parent as usize].set_base(base);
+        let children = std::mem::take(&mut self.children);
+        let found = self.find_base(&children[first..]);
+        self.children = children;
+        let base = found?;
+        self.set_base(parent, base)?;
 
         // Place child nodes
-        let mut child_indices: Vec<u32> = Vec::with_capacity(children.len());
-        for &(code, _, _) in &children {
+        self.chain.clear();
+        for &(code, _, _) in &self.children[first..] {
             let child_idx = base ^ code;
-            child_indices.push(child_idx);
             self.free_list.remove(child_idx);
             self.nodes[child_idx as usize].set_check(parent);
+            self.chain.push(code);
         }
 
-        // Build sibling chain
-        for w in child_indices.windows(2) {
-            self.siblings[w[0] as usize] = w[1];
+        // Build sibling chain in ascending code order. The terminal (code 0)
+        // heads the chain, and the head is the first child found by scanning
+        // codes upwards, which is what search relies on to enter the chain.
+        self.chain.sort_unstable();
+        for w in self.chain.windows(2) {
+            self.siblings[(base ^ w[0]) as usize] = base ^ w[1];
         }
         // Last child's sibling is 0 (no more siblings)
 
-        // Set leaf/has_leaf flags and recurse into non-terminal children
-        for (ci, &(code, child_begin, child_end)) in children.iter().enumerate() {
-            let child_idx = child_indices[ci];
+        // Set leaf/has_leaf flags and recurse into non-terminal children.
+        // Recursion pushes above this node's entries, so they stay in place.
+        for ci in first..first + fan_out {
+            let (code, child_begin, child_end) = self.children[ci];
+            let child_idx = base ^ code;
             if code == 0 {
                 // Terminal symbol — this is a leaf node
                 debug_assert_eq!(child_end - child_begin, 1);
-                let value_id = child_begin as u32;
-                self.nodes[child_idx as usize].set_leaf(value_id);
+                self.nodes[child_idx as usize].set_leaf(values[child_begin]);
                 self.nodes[parent as usize].set_has_leaf();
+                self.leaves[child_begin] = child_idx;
+            } else if self.prefix_free && coded_keys[child_begin].len() == depth + 2 {
+                // The key ends at this label, and being prefix-free it is
+                // the only key below it: the node becomes its leaf.
+                debug_assert_eq!(child_end - child_begin, 1);
+                self.nodes[child_idx as usize].set_leaf(values[child_begin]);
+                self.leaves[child_begin] = child_idx;
             } else {
                 // Non-terminal — recurse
-                self.build_rec(coded_keys, child_begin, child_end, depth + 1, child_idx);
+                self.build_rec(
+                    coded_keys,
+                    values,
+                    child_begin,
+                    child_end,
+                    depth + 1,
+                    child_idx,
+                )?;
             }
         }
+        self.children.truncate(first);
+        Ok(())
+    }
+
+    /// Sets the base of `parent`, failing if it does not fit in 31 bits.
+    ///
+    /// `base` shares its field with the IS_LEAF flag, so a larger value would
+    /// turn `parent` into a leaf. Bases are below the node budget, which is at
+    /// most [`MAX_NODES`], so this cannot fail for a budget-checked build;
+    /// unlike `Node::set_base`'s debug assertion, it holds in release builds.
+    fn set_base(&mut self, parent: u32, base: u32) -> Result<(), BuildError> {
+        if base > MASK {
+            return Err(BuildError::NodeIndexOverflow);
+        }
+        self.nodes[parent as usize].set_base(base);
+        Ok(())
     }
 
     /// Finds a base value such that `base XOR code` is a free slot for each child label.
-    fn find_base(&mut self, children: &[(u32, usize, usize)]) -> u32 {
+    ///
+    /// Bases whose children would land past the node budget are skipped; if
+    /// no base fits within it, returns [`BuildError::ExceededNodeBudget`].
+    fn find_base(&mut self, children: &[(u32, usize, usize)]) -> Result<u32, BuildError> {
         let first_code = children[0].0;
 
         // Start from the first free slot. We try: base = cursor XOR first_code,
@@ -178,9 +361,9 @@ impl BuildContext {
         let mut cursor = match self.free_list.first_free() {
             Some(f) => f,
             None => {
-                let new_cap = self.nodes.len() * 2;
-                self.ensure_capacity(new_cap);
-                (self.nodes.len() / 2) as u32 // first slot of newly grown region
+                let old_cap = self.nodes.len();
+                self.ensure_capacity(old_cap + 1, old_cap * 2)?;
+                old_cap as u32 // first slot of newly grown region
             }
         };
 
@@ -196,30 +379,31 @@ impl BuildContext {
                     .max()
                     .unwrap();
 
-                // Ensure capacity
-                if max_idx as usize >= self.nodes.len() {
-                    let new_cap = (max_idx as usize + 1).next_power_of_two();
-                    self.ensure_capacity(new_cap);
+                // Ensure capacity, unless this base does not fit the budget at all
+                let fits = (max_idx as usize) < self.max_nodes;
+                if fits && max_idx as usize >= self.nodes.len() {
+                    let needed = max_idx as usize + 1;
+                    self.ensure_capacity(needed, needed.next_power_of_two())?;
                 }
 
-                let all_free = children
-                    .iter()
-                    .all(|&(code, _, _)| self.free_list.is_free(base ^ code));
+                let all_free = fits
+                    && children
+                        .iter()
+                        .all(|&(code, _, _)| self.free_list.is_free(base ^ code));
 
                 if all_free {
-                    return base;
+                    return Ok(base);
                 }
             }
 
             // Advance cursor to the next free slot
+            self.metrics.find_base_iterations += 1;
             let next = self.free_list.next[cursor as usize];
             if next == 0 {
                 // Wrapped around to sentinel — all current free slots exhausted, grow
-                let new_cap = self.nodes.len() * 2;
-                let new_first = self.free_list.grow(new_cap);
-                self.nodes.resize(new_cap, Node::default());
-                self.siblings.resize(new_cap, 0);
-                cursor = new_first;
+                let old_cap = self.nodes.len();
+                self.ensure_capacity(old_cap + 1, old_cap * 2)?;
+                cursor = old_cap as u32;
             } else {
                 cursor = next;
             }
@@ -230,186 +414,2293 @@ impl BuildContext {
 impl<L: Label> DoubleArray<L> {
     /// Builds a double-array trie from sorted keys.
     ///
-    /// Each key `keys[i]` is assigned `value_id = i`.
+    /// Each key `keys[i]` is assigned `value_id = i`, so the value_ids are
+    /// exactly `0..keys.len()` (see [`num_keys`](Self::num_keys)).
     ///
     /// # Panics
     /// - If keys are not sorted in ascending order.
     /// - If duplicate keys are found.
     pub fn build(keys: &[impl AsRef<[L]>]) -> Self {
-        // Verify sorted and no duplicates
-        for w in keys.windows(2) {
-            assert!(
-                w[0].as_ref() < w[1].as_ref(),
-                "keys must be sorted in ascending order with no duplicates"
-            );
-        }
+        Self::build_with_values(keys, &sequential_values(keys.len()))
+    }
 
-        if keys.is_empty() {
-            let empty: &[Vec<L>] = &[];
-            return Self::new(vec![Node::default()], vec![0], CodeMapper::build(empty));
+    /// Builds a double-array trie from sorted keys, returning an error
+    /// instead of panicking on bad input, e.g. a user-supplied dictionary.
+    ///
+    /// Otherwise equivalent to [`build`](Self::build).
+    ///
+    /// # Errors
+    /// - [`BuildError::UnsortedKeys`] or [`BuildError::DuplicateKey`] with
+    ///   the index of the first key out of order.
+    /// - [`BuildError::ExceededNodeBudget`] if the trie would need more than
+    ///   [`MAX_NODES`] slots, which includes every key set
+    ///   too large to number in 31 bits.
+    pub fn try_build(keys: &[impl AsRef<[L]>]) -> Result<Self, BuildError> {
+        check_sorted(keys, false)?;
+        // Each key needs a node besides the root.
+        if keys.len() >= MAX_NODES {
+            return Err(BuildError::ExceededNodeBudget);
         }
+        let values = sequential_values(keys.len());
+        Self::try_build_unchecked(keys, &values, NodeBuffers::default(), MAX_NODES, usize::MAX)
+            .map(|(da, ..)| da)
+    }
 
+    /// Computes statistics of the trie that [`build`](Self::build) would
+    /// produce, without building it.
+    ///
+    /// Runs the code map pass and one scan over the keys, counting distinct
+    /// prefixes from the common prefix of adjacent keys; no node array is
+    /// allocated. Useful to warn about a huge build before starting it.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn plan(keys: &[impl AsRef<[L]>]) -> BuildPlan {
+        assert_sorted(keys, false);
         let code_map = CodeMapper::build(keys);
 
-        // Convert keys to code sequences with terminal symbol (0) appended
-        let coded_keys: Vec<Vec<u32>> = keys
-            .iter()
-            .map(|k| {
-                let mut codes: Vec<u32> = k.as_ref().iter().map(|&l| code_map.get(l)).collect();
-                codes.push(0); // terminal symbol
-                codes
-            })
-            .collect();
+        let mut prefixes = 0usize;
+        let mut max_depth = 0usize;
+        let mut max_fan_out = 0usize;
+        // fan_outs[d] counts the children seen so far of the current key's
+        // prefix of length d.
+        let mut fan_outs: Vec<usize> = Vec::new();
+        let mut prev: &[L] = &[];
+        for key in keys {
+            let key = key.as_ref();
+            let shared = prev.iter().zip(key).take_while(|(a, b)| a == b).count();
+            prefixes += key.len() - shared;
+            max_depth = max_depth.max(key.len());
+            // The key leaves the previous one's path at depth `shared`, adding
+            // a child there; each deeper prefix is new, with one child so far.
+            fan_outs.truncate(shared + 1);
+            if fan_outs.len() == shared {
+                fan_outs.push(0);
+            }
+            fan_outs[shared] += 1;
+            max_fan_out = max_fan_out.max(fan_outs[shared]);
+            fan_outs.resize(key.len() + 1, 1);
+            prev = key;
+        }
 
-        let initial_cap = 256.max(coded_keys.len() * 4);
-        let mut ctx = BuildContext::new(initial_cap);
+        let used_nodes = 1 + prefixes + keys.len();
+        let slot_size = std::mem::size_of::<Node>() + std::mem::size_of::<u32>();
+        BuildPlan {
+            num_keys: keys.len(),
+            used_nodes,
+            alphabet_size: code_map.alphabet_size(),
+            max_depth,
+            max_fan_out,
+            estimated_bytes: crate::serial::HEADER_SIZE
+                + used_nodes * slot_size
+                + code_map.serialized_size(),
+        }
+    }
 
-        ctx.build_rec(&coded_keys, 0, keys.len(), 0, 0);
+    /// Builds a double-array trie from sorted keys with explicit values.
+    ///
+    /// Each key `keys[i]` is assigned `value_id = values[i]`. Values need not
+    /// be distinct or dense, but must fit in 31 bits.
+    ///
+    /// A value is stored in the key's leaf as is, so it need not index
+    /// anything: a small payload such as a packed tag can live in the trie
+    /// itself, and [`exact_match`](Self::exact_match) returns it with no
+    /// side table.
+    ///
+    /// # Panics
+    /// - If `keys` and `values` have different lengths.
+    /// - If keys are not sorted in ascending order.
+    /// - If duplicate keys are found.
+    /// - If a value does not fit in 31 bits.
+    pub fn build_with_values(keys: &[impl AsRef<[L]>], values: &[u32]) -> Self {
+        assert_eq!(
+            keys.len(),
+            values.len(),
+            "keys and values must have the same length"
+        );
+        assert_sorted(keys, false);
+        Self::build_unchecked(keys, values, NodeBuffers::default()).0
+    }
 
-        // Trim trailing unused nodes
-        let last_used = ctx
-            .nodes
+    /// Builds a double-array trie from sorted keys and also returns the leaf
+    /// node index of every key.
+    ///
+    /// `leaf_map[value_id]` is the index of the terminal (leaf) node of
+    /// `keys[value_id]`; its `check` is the node reached by the key itself.
+    /// This gives O(1) `value_id → node` lookup for attaching side data keyed
+    /// by structure, without a post-build scan.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_with_leaf_map(keys: &[impl AsRef<[L]>]) -> (Self, Vec<u32>) {
+        assert_sorted(keys, false);
+        Self::build_unchecked(keys, &sequential_values(keys.len()), NodeBuffers::default())
+    }
+
+    /// Builds a double-array trie from sorted keys and also returns the
+    /// length in labels of every key, indexed by value_id.
+    ///
+    /// `lengths[value_id]` is `keys[value_id].len()`, so consumers can size
+    /// per-entry buffers without reconstructing keys.
+    ///
+    /// # Panics
+    /// - If a key is longer than `u16::MAX` labels.
+    /// - Otherwise the same as [`build`](Self::build).
+    pub fn build_with_lengths(keys: &[impl AsRef<[L]>]) -> (Self, Vec<u16>) {
+        let lengths: Vec<u16> = keys
             .iter()
             .enumerate()
-            .rev()
-            .find(|(_, n)| *n != &Node::default())
-            .map(|(i, _)| i)
-            .unwrap_or(0);
-        let final_len = last_used + 1;
-        ctx.nodes.truncate(final_len);
-        ctx.siblings.truncate(final_len);
+            .map(|(i, key)| {
+                u16::try_from(key.as_ref().len()).unwrap_or_else(|_| {
+                    panic!("key at index {i} is longer than {} labels", u16::MAX)
+                })
+            })
+            .collect();
+        (Self::build(keys), lengths)
+    }
 
-        Self::new(ctx.nodes, ctx.siblings, code_map)
+    /// Builds a double-array trie from a map, using its values as value_ids.
+    ///
+    /// A `BTreeMap` iterates its keys sorted and unique, so no order check
+    /// is done. Otherwise equivalent to [`build_with_values`](Self::build_with_values).
+    ///
+    /// # Panics
+    /// If a value does not fit in 31 bits.
+    pub fn build_from_map(map: &BTreeMap<Vec<L>, u32>) -> Self {
+        let (keys, values): (Vec<&[L]>, Vec<u32>) =
+            map.iter().map(|(k, &v)| (k.as_slice(), v)).unzip();
+        Self::build_unchecked(&keys, &values, NodeBuffers::default()).0
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Builds a trie from sorted keys, merging the values of duplicate keys.
+    ///
+    /// Adjacent equal keys are collapsed into a single entry whose value is
+    /// `values` folded left to right with `combine`. Keys that appear once
+    /// keep their value unchanged.
+    ///
+    /// # Panics
+    /// - If `keys` and `values` have different lengths.
+    /// - If keys are not sorted in ascending order.
+    /// - If a (combined) value does not fit in 31 bits.
+    pub fn build_combining(
+        keys: &[impl AsRef<[L]>],
+        values: &[u32],
+        combine: impl Fn(u32, u32) -> u32,
+    ) -> Self {
+        assert_eq!(
+            keys.len(),
+            values.len(),
+            "keys and values must have the same length"
+        );
+        assert_sorted(keys, true);
 
-    #[test]
-    fn build_empty() {
-        let keys: Vec<&[u8]> = vec![];
-        let da = DoubleArray::<u8>::build(&keys);
-        assert!(da.num_nodes() > 0); // at least root
-    }
+        let mut merged_keys: Vec<&[L]> = Vec::with_capacity(keys.len());
+        let mut merged_values: Vec<u32> = Vec::with_capacity(values.len());
+        for (key, &value) in keys.iter().zip(values) {
+            let key = key.as_ref();
+            match merged_keys.last() {
+                Some(&last) if last == key => {
+                    let acc = merged_values.last_mut().unwrap();
+                    *acc = combine(*acc, value);
+                }
+                _ => {
+                    merged_keys.push(key);
+                    merged_values.push(value);
+                }
+            }
+        }
 
-    #[test]
-    fn build_single_key() {
-        let da = DoubleArray::<u8>::build(&[b"abc"]);
-        assert!(da.num_nodes() > 1);
+        Self::build_unchecked(&merged_keys, &merged_values, NodeBuffers::default()).0
     }
 
-    #[test]
-    fn build_shared_prefix() {
-        let da = DoubleArray::<u8>::build(&[b"abc", b"abd", b"xyz"]);
-        assert!(da.num_nodes() > 1);
+    /// Builds a trie from keys whose labels are first mapped through `normalize`.
+    ///
+    /// Keys need not be sorted. Keys that become equal after normalization
+    /// collapse into one entry whose value_id is the smallest index among
+    /// them in `keys`; other keys keep their index. Query with
+    /// [`exact_match_normalized`](Self::exact_match_normalized) and the same
+    /// function.
+    ///
+    /// This is also how to match case-insensitively beyond ASCII: the crate
+    /// embeds no Unicode tables, so pass a per-label fold from the caller,
+    /// e.g. one mapping `'Ä'` to `'ä'` and both `'Σ'` and `'ς'` to `'σ'`.
+    /// Folds that change a key's length, such as `'ß'` to `"ss"`, cannot be
+    /// expressed per label.
+    ///
+    /// # Panics
+    /// If there are more than 2^31 keys.
+    pub fn build_normalized(keys: &[impl AsRef<[L]>], normalize: impl Fn(L) -> L) -> Self {
+        let values = sequential_values(keys.len());
+        let mut entries: Vec<(Vec<L>, u32)> = keys
+            .iter()
+            .zip(values)
+            .map(|(k, v)| (k.as_ref().iter().map(|&l| normalize(l)).collect(), v))
+            .collect();
+        // Stable, so colliding keys stay in index order.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let (keys, values): (Vec<Vec<L>>, Vec<u32>) = entries.into_iter().unzip();
+        Self::build_combining(&keys, &values, u32::min)
     }
 
-    #[test]
-    fn build_char_keys() {
-        let keys: Vec<Vec<char>> = vec![
-            "あい".chars().collect(),
-            "あう".chars().collect(),
-            "かき".chars().collect(),
-        ];
-        let da = DoubleArray::<char>::build(&keys);
-        assert!(da.num_nodes() > 1);
+    /// Builds a double-array trie from keys in any order.
+    ///
+    /// Each key `keys[i]` is assigned `value_id = i`, as with
+    /// [`build`](Self::build); the keys are sorted internally first.
+    ///
+    /// # Panics
+    /// - If duplicate keys are found.
+    /// - If there are more than 2^31 keys.
+    pub fn build_unsorted(keys: &[impl AsRef<[L]>]) -> Self {
+        let values = sequential_values(keys.len());
+        let order = sorted_order(keys);
+        for w in order.windows(2) {
+            if keys[w[0]].as_ref() == keys[w[1]].as_ref() {
+                panic!("duplicate keys at indices {} and {}", w[0], w[1]);
+            }
+        }
+        let sorted_keys: Vec<&[L]> = order.iter().map(|&i| keys[i].as_ref()).collect();
+        let sorted_values: Vec<u32> = order.iter().map(|&i| values[i]).collect();
+        Self::build_unchecked(&sorted_keys, &sorted_values, NodeBuffers::default()).0
     }
 
-    #[test]
-    #[should_panic(expected = "sorted")]
-    fn build_unsorted_panics() {
-        DoubleArray::<u8>::build(&[b"bbb", b"aaa"]);
+    /// Builds a trie of the reversed keys, for matching right to left with
+    /// [`longest_suffix_match_at`](Self::longest_suffix_match_at).
+    ///
+    /// The keys may come in any order; `keys[i]` reversed is assigned
+    /// `value_id = i`, as with [`build_unsorted`](Self::build_unsorted).
+    ///
+    /// # Panics
+    /// Same as [`build_unsorted`](Self::build_unsorted).
+    pub fn build_reversed(keys: &[impl AsRef<[L]>]) -> Self {
+        let reversed: Vec<Vec<L>> = keys
+            .iter()
+            .map(|k| k.as_ref().iter().rev().copied().collect())
+            .collect();
+        Self::build_unsorted(&reversed)
     }
 
-    #[test]
-    #[should_panic(expected = "sorted")]
-    fn build_duplicates_panics() {
-        DoubleArray::<u8>::build(&[b"aaa", b"aaa"]);
+    /// Builds a trie from keys in any order, possibly repeated, and also
+    /// returns the input position of every key, indexed by value_id.
+    ///
+    /// The distinct keys are numbered `0..n` in ascending order, as
+    /// [`build`](Self::build) numbers sorted keys, and `source[value_id]` is
+    /// the index in `keys` of its first occurrence, e.g. to report the
+    /// original line of a dictionary entry.
+    ///
+    /// # Panics
+    /// If there are more than 2^31 keys.
+    pub fn build_with_source_order(keys: &[impl AsRef<[L]>]) -> (Self, Vec<u32>) {
+        let mut order = sorted_order(keys);
+        // Stable, so each run of equal keys starts at its first occurrence.
+        order.dedup_by(|a, b| keys[*a].as_ref() == keys[*b].as_ref());
+        let sorted_keys: Vec<&[L]> = order.iter().map(|&i| keys[i].as_ref()).collect();
+        let values = sequential_values(sorted_keys.len());
+        let da = Self::build_unchecked(&sorted_keys, &values, NodeBuffers::default()).0;
+        (da, order.into_iter().map(|i| i as u32).collect())
     }
 
-    #[test]
-    fn check_points_to_parent() {
-        let da = DoubleArray::<u8>::build(&[b"ab", b"ac"]);
-        for (i, node) in da.nodes.iter().enumerate() {
-            if i == 0 || *node == Node::default() {
-                continue;
+    /// Builds a trie from keys in any order, e.g. drawn from a `HashMap`,
+    /// and returns the value_id assigned to each input key, indexed like
+    /// `keys`.
+    ///
+    /// The distinct keys are numbered `0..n` in ascending order, so
+    /// `ids[i]` is where the payload of `keys[i]` goes in an array sorted
+    /// like the trie. With `dedup`, repeated keys share one value_id; the
+    /// inverse map is [`build_with_source_order`](Self::build_with_source_order).
+    ///
+    /// # Panics
+    /// - If `dedup` is false and a key repeats.
+    /// - If there are more than 2^31 keys.
+    pub fn build_from_unsorted(keys: &[impl AsRef<[L]>], dedup: bool) -> (Self, Vec<u32>) {
+        let order = sorted_order(keys);
+        let mut ids = vec![0u32; keys.len()];
+        let mut sorted_keys: Vec<&[L]> = Vec::with_capacity(keys.len());
+        for (pos, &i) in order.iter().enumerate() {
+            let key = keys[i].as_ref();
+            if sorted_keys.last() == Some(&key) {
+                if !dedup {
+                    panic!("duplicate keys at indices {} and {i}", order[pos - 1]);
+                }
+            } else {
+                sorted_keys.push(key);
             }
-            let parent_idx = node.check() as usize;
-            assert!(parent_idx < da.nodes.len());
+            ids[i] = (sorted_keys.len() - 1) as u32;
         }
+        let values = sequential_values(sorted_keys.len());
+        let da = Self::build_unchecked(&sorted_keys, &values, NodeBuffers::default()).0;
+        (da, ids)
     }
 
-    #[test]
-    fn leaf_and_has_leaf_consistency() {
-        let keys: Vec<&[u8]> = vec![b"ab", b"ac", b"b"];
-        let da = DoubleArray::<u8>::build(&keys);
+    /// Builds a trie from sorted keys, reusing the allocations of
+    /// `nodes_buf` and `siblings_buf` for its node arrays.
+    ///
+    /// The buffers are cleared first; only their capacity matters. Pair with
+    /// [`into_buffers`](Self::into_buffers) to build many transient tries
+    /// without reallocating. Otherwise equivalent to [`build`](Self::build).
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_into(
+        keys: &[impl AsRef<[L]>],
+        nodes_buf: Vec<Node>,
+        siblings_buf: Vec<u32>,
+    ) -> Self {
+        assert_sorted(keys, false);
+        let buffers = NodeBuffers {
+            nodes: nodes_buf,
+            siblings: siblings_buf,
+        };
+        Self::build_unchecked(keys, &sequential_values(keys.len()), buffers).0
+    }
 
-        let mut leaf_count = 0;
+    /// Consumes the trie and returns its node and sibling arrays, for reuse
+    /// with [`build_into`](Self::build_into).
+    pub fn into_buffers(self) -> (Vec<Node>, Vec<u32>) {
+        (self.nodes, self.siblings)
+    }
 
-        for node in &da.nodes {
-            if node.is_leaf() {
-                leaf_count += 1;
-                // A leaf's parent should have has_leaf set
-                let parent = &da.nodes[node.check() as usize];
-                assert!(parent.has_leaf());
-            }
-        }
+    /// Builds a trie from sorted keys, failing instead of growing the node
+    /// array past `max_nodes` slots.
+    ///
+    /// Useful when the key set comes from an untrusted or unbounded source and
+    /// memory must stay predictable. The budget counts every slot of the node
+    /// array, holes included, i.e. it bounds [`num_nodes`](Self::num_nodes).
+    /// Otherwise equivalent to [`build`](Self::build).
+    ///
+    /// # Errors
+    /// Returns [`BuildError::ExceededNodeBudget`] if the trie cannot be laid
+    /// out within `max_nodes` slots.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_bounded(keys: &[impl AsRef<[L]>], max_nodes: usize) -> Result<Self, BuildError> {
+        assert_sorted(keys, false);
+        let values = sequential_values(keys.len());
+        Self::try_build_unchecked(
+            keys,
+            &values,
+            NodeBuffers::default(),
+            max_nodes.min(MAX_NODES),
+            usize::MAX,
+        )
+        .map(|(da, ..)| da)
+    }
 
-        // We have 3 keys, so 3 terminal nodes (leaves)
-        assert_eq!(leaf_count, 3);
+    /// Builds a trie from sorted keys, failing if any node would have more
+    /// than `max_fan_out` children.
+    ///
+    /// A node has one child per distinct label following its prefix, plus one
+    /// for the terminal if the prefix is itself a key. Placing a node means
+    /// finding a base at which all of its children land on free slots, and
+    /// the wider the node, the more candidate bases are rejected: a node with
+    /// thousands of children scans far into the free list, and the slots it
+    /// skips stay as holes, so the build slows down and the array turns
+    /// sparse. Natural dictionaries rarely exceed a few thousand children at
+    /// the root and far fewer below it; a much wider node usually means
+    /// malformed input, such as keys split at the wrong length. Use
+    /// [`plan`](Self::plan) to see the widest node without building.
+    /// Otherwise equivalent to [`build`](Self::build).
+    ///
+    /// # Errors
+    /// Returns [`BuildError::ExceededFanOut`] with the first offending node's
+    /// fan-out, before that node is placed.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_with_fan_out_limit(
+        keys: &[impl AsRef<[L]>],
+        max_fan_out: usize,
+    ) -> Result<Self, BuildError> {
+        assert_sorted(keys, false);
+        let values = sequential_values(keys.len());
+        Self::try_build_unchecked(
+            keys,
+            &values,
+            NodeBuffers::default(),
+            MAX_NODES,
+            max_fan_out,
+        )
+        .map(|(da, ..)| da)
     }
 
-    #[test]
-    fn sibling_chain_no_cycle() {
-        let keys: Vec<&[u8]> = vec![b"a", b"b", b"c"];
-        let da = DoubleArray::<u8>::build(&keys);
+    /// Builds a trie from sorted keys like [`build`](Self::build), and also
+    /// returns counters of the work the build did.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_with_metrics(keys: &[impl AsRef<[L]>]) -> (Self, BuildMetrics) {
+        assert_sorted(keys, false);
+        let values = sequential_values(keys.len());
+        let (da, _, metrics) = expect_built(Self::try_build_unchecked(
+            keys,
+            &values,
+            NodeBuffers::default(),
+            MAX_NODES,
+            usize::MAX,
+        ));
+        let sparse = da.is_sparse();
+        (da, BuildMetrics { sparse, ..metrics })
+    }
 
-        for i in 0..da.siblings.len() {
-            let mut visited = std::collections::HashSet::new();
-            let mut cur = i as u32;
-            while cur != 0 {
-                assert!(visited.insert(cur), "cycle detected in sibling chain");
-                cur = da.siblings[cur as usize];
-            }
+    /// Builds a trie from sorted keys of which none is a prefix of another,
+    /// without a terminal node per key.
+    ///
+    /// In a prefix-free set every key ends at a node with no other children,
+    /// so its value_id is stored on that node, which becomes a leaf, rather
+    /// than in a terminal child. This saves one node per key, and search
+    /// recognises both layouts: the trie answers every query like one from
+    /// [`build`](Self::build), and `keys[i]` gets value_id `i`.
+    ///
+    /// # Panics
+    /// - Same as [`build`](Self::build).
+    /// - If a key is a prefix of another key.
+    pub fn build_prefix_free(keys: &[impl AsRef<[L]>]) -> Self {
+        assert_sorted(keys, false);
+        // Once sorted, a key that prefixes any other key prefixes the next one.
+        for (i, w) in keys.windows(2).enumerate() {
+            assert!(
+                !w[1].as_ref().starts_with(w[0].as_ref()),
+                "key at index {i} is a prefix of the next key"
+            );
         }
+        if keys.is_empty() {
+            return Self::build(keys);
+        }
+        let code_map = CodeMapper::build(keys);
+        let coded_keys = encode_keys(keys, &code_map);
+        let (da, ..) = expect_built(Self::build_coded(
+            &coded_keys,
+            &sequential_values(keys.len()),
+            code_map,
+            NodeBuffers::default(),
+            MAX_NODES,
+            usize::MAX,
+            true,
+        ));
+        da
     }
 
-    #[test]
-    fn sibling_chain_links_same_parent() {
-        let da = DoubleArray::<u8>::build(&[b"ab", b"ac", b"ad"]);
-
-        // Find node for 'a' from root
-        let root_base = da.nodes[0].base();
-        let code_a = da.code_map.get(b'a');
-        let node_a_idx = root_base ^ code_a;
-        assert_eq!(da.nodes[node_a_idx as usize].check(), 0);
+    /// Builds one trie per run of `shard_size` consecutive sorted keys.
+    ///
+    /// Each shard is an ordinary trie that can be serialized on its own, so a
+    /// very large dictionary can be built and saved a shard at a time. Value
+    /// ids stay global: `keys[i]` gets value_id `i` in whichever shard holds
+    /// it, the same as in [`build`](Self::build).
+    ///
+    /// To query, search every shard and combine the results. Shards cover
+    /// disjoint key ranges, so an exact match hits at most one of them, and
+    /// the matches of a common prefix or predictive search are the union of
+    /// the per-shard matches:
+    ///
+    /// ```
+    /// use lexime_trie::DoubleArray;
+    ///
+    /// let keys = ["a", "ab", "abc", "b", "bc"];
+    /// let shards = DoubleArray::<u8>::build_sharded(&keys, 2);
+    /// assert_eq!(shards.len(), 3);
+    ///
+    /// let id = shards.iter().find_map(|s| s.exact_match(b"bc"));
+    /// assert_eq!(id, Some(4));
+    ///
+    /// let mut prefixes: Vec<_> = shards
+    ///     .iter()
+    ///     .flat_map(|s| s.common_prefix_search(b"abcd"))
+    ///     .collect();
+    /// prefixes.sort_by_key(|m| m.len);
+    /// assert_eq!(prefixes.iter().map(|m| m.value_id).collect::<Vec<_>>(), [0, 1, 2]);
+    /// ```
+    ///
+    /// # Panics
+    /// - If `shard_size` is 0.
+    /// - Otherwise the same as [`build`](Self::build).
+    pub fn build_sharded(keys: &[impl AsRef<[L]>], shard_size: usize) -> Vec<Self> {
+        assert!(shard_size > 0, "shard_size must be positive");
+        assert_sorted(keys, false);
+        let values = sequential_values(keys.len());
+        keys.chunks(shard_size)
+            .zip(values.chunks(shard_size))
+            .map(|(keys, values)| Self::build_unchecked(keys, values, NodeBuffers::default()).0)
+            .collect()
+    }
 
-        // Count children of node_a via sibling chain
-        let a_base = da.nodes[node_a_idx as usize].base();
+    /// Builds from keys already known to be sorted and unique.
+    ///
+    /// Also returns the leaf node index of each key, by key position.
+    ///
+    /// # Panics
+    /// If the trie would exceed [`MAX_NODES`] nodes.
+    fn build_unchecked(
+        keys: &[impl AsRef<[L]>],
+        values: &[u32],
+        buffers: NodeBuffers,
+    ) -> (Self, Vec<u32>) {
+        let (da, leaves, _) = expect_built(Self::try_build_unchecked(
+            keys,
+            values,
+            buffers,
+            MAX_NODES,
+            usize::MAX,
+        ));
+        (da, leaves)
+    }
 
-        // Find any child of node_a to start the chain
-        let mut first_child = None;
-        for code in 0..da.code_map.alphabet_size() {
-            let idx = a_base ^ code;
-            if (idx as usize) < da.nodes.len() && da.nodes[idx as usize].check() == node_a_idx {
-                first_child = Some(idx);
-                break;
-            }
+    /// Like [`build_unchecked`](Self::build_unchecked), but with a node
+    /// budget and a fan-out limit.
+    fn try_build_unchecked(
+        keys: &[impl AsRef<[L]>],
+        values: &[u32],
+        buffers: NodeBuffers,
+        max_nodes: usize,
+        max_fan_out: usize,
+    ) -> Result<(Self, Vec<u32>, BuildMetrics), BuildError> {
+        debug_assert_eq!(keys.len(), values.len());
+        assert!(
+            values.iter().all(|&v| v <= MASK),
+            "value_id must fit in 31 bits"
+        );
+        // Even an empty trie needs its root.
+        if max_nodes == 0 {
+            return Err(BuildError::ExceededNodeBudget);
         }
 
-        let first = first_child.expect("node_a should have children");
-        let mut count = 1;
-        let mut cur = da.siblings[first as usize];
-        while cur != 0 {
-            assert_eq!(
-                da.nodes[cur as usize].check(),
-                node_a_idx,
-                "sibling should have same parent"
-            );
-            count += 1;
-            cur = da.siblings[cur as usize];
+        if keys.is_empty() {
+            let empty: &[Vec<L>] = &[];
+            let ctx = BuildContext::with_buffers(buffers, 1, 0, max_nodes, max_fan_out);
+            return Ok((
+                Self::new(ctx.nodes, ctx.siblings, CodeMapper::build(empty)),
+                Vec::new(),
+                ctx.metrics,
+            ));
+        }
+
+        let code_map = CodeMapper::build(keys);
+        let coded_keys = encode_keys(keys, &code_map);
+        Self::build_coded(
+            &coded_keys,
+            values,
+            code_map,
+            buffers,
+            max_nodes,
+            max_fan_out,
+            false,
+        )
+    }
+
+    /// Builds a trie with a caller-supplied code map, reporting keys it
+    /// cannot tell apart.
+    ///
+    /// With a lossy map such as [`CodeMapper::build_capped`], keys that
+    /// differ only in labels sharing a code would collide, and keys with a
+    /// label the map does not know (code 0) cannot be stored at all. If any
+    /// such keys exist, returns `Err` with their indices in ascending order
+    /// (every key of a colliding group is listed). Otherwise each key's
+    /// value_id is its index in `keys`.
+    ///
+    /// # Panics
+    /// - If keys are not sorted in ascending order.
+    /// - If duplicate keys are found.
+    /// - If there are more than 2^31 keys.
+    pub fn build_checked(
+        keys: &[impl AsRef<[L]>],
+        code_map: CodeMapper,
+    ) -> Result<Self, Vec<usize>> {
+        assert_sorted(keys, false);
+        let values = sequential_values(keys.len());
+        if keys.is_empty() {
+            return Ok(Self::new(vec![Node::default()], vec![0], code_map));
+        }
+
+        let coded_keys = encode_keys(keys, &code_map);
+        let mut unreachable: Vec<usize> = coded_keys
+            .iter()
+            .enumerate()
+            .filter(|(_, codes)| codes[..codes.len() - 1].contains(&0))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Lossy codes can reorder keys, so sort by code sequence to keep every
+        // shared prefix contiguous, then look for equal neighbours.
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| coded_keys[a].cmp(&coded_keys[b]));
+        for group in order.chunk_by(|&a, &b| coded_keys[a] == coded_keys[b]) {
+            if group.len() > 1 {
+                unreachable.extend_from_slice(group);
+            }
+        }
+        if !unreachable.is_empty() {
+            unreachable.sort_unstable();
+            unreachable.dedup();
+            return Err(unreachable);
+        }
+
+        let sorted_keys: Vec<Vec<u32>> = order.iter().map(|&i| coded_keys[i].clone()).collect();
+        let sorted_values: Vec<u32> = order.iter().map(|&i| values[i]).collect();
+        let (da, ..) = expect_built(Self::build_coded(
+            &sorted_keys,
+            &sorted_values,
+            code_map,
+            NodeBuffers::default(),
+            MAX_NODES,
+            usize::MAX,
+            false,
+        ));
+        Ok(da)
+    }
+
+    /// Builds a trie from sorted keys after checking each one against a
+    /// domain rule.
+    ///
+    /// If `validate` returns `false` for any key, returns `Err` with the
+    /// indices of all such keys in ascending order and builds nothing.
+    /// Otherwise equivalent to [`build`](Self::build).
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build), checked only once every key is valid.
+    pub fn build_validated(
+        keys: &[impl AsRef<[L]>],
+        validate: impl Fn(&[L]) -> bool,
+    ) -> Result<Self, Vec<usize>> {
+        let invalid: Vec<usize> = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !validate(key.as_ref()))
+            .map(|(i, _)| i)
+            .collect();
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        Ok(Self::build(keys))
+    }
+
+    /// Builds a trie like [`build`](Self::build), then checks that every key
+    /// resolves to its value_id and that the trie holds no other keys.
+    ///
+    /// A self-check for pipelines where a wrong trie is worse than a failed
+    /// build: it guards against builder bugs, not against bad input. The
+    /// check runs [`exact_match`](Self::exact_match) on every key and scans
+    /// the node array once, roughly doubling the build's cost.
+    ///
+    /// # Panics
+    /// - Same as [`build`](Self::build).
+    /// - If the check fails, naming the first key that does not resolve.
+    pub fn build_verified(keys: &[impl AsRef<[L]>]) -> Self {
+        let da = Self::build(keys);
+        if let Err(i) = da.verify(keys) {
+            panic!("built trie failed verification at key index {i}");
+        }
+        da
+    }
+
+    /// Checks that `keys[i]` resolves to value_id `i` for every `i` and that
+    /// there are no other keys. Returns the first failing index, or
+    /// `keys.len()` if only the key count is off.
+    fn verify(&self, keys: &[impl AsRef<[L]>]) -> Result<(), usize> {
+        for (i, key) in keys.iter().enumerate() {
+            if self.exact_match(key) != Some(i as u32) {
+                return Err(i);
+            }
+        }
+        if self.num_keys() != keys.len() {
+            return Err(keys.len());
+        }
+        Ok(())
+    }
+
+    /// Builds from terminal-terminated code sequences in which keys sharing a
+    /// prefix are contiguous. Returns the leaf node index of each key.
+    ///
+    /// `max_nodes` must be at least 1.
+    fn build_coded(
+        coded_keys: &[Vec<u32>],
+        values: &[u32],
+        code_map: CodeMapper,
+        buffers: NodeBuffers,
+        max_nodes: usize,
+        max_fan_out: usize,
+        prefix_free: bool,
+    ) -> Result<(Self, Vec<u32>, BuildMetrics), BuildError> {
+        // Search trusts a code map built here to map every code back to an L.
+        debug_assert!(
+            (1..code_map.alphabet_size()).all(|code| L::try_from(code_map.reverse(code)).is_ok())
+        );
+
+        let initial_cap = 256.max(coded_keys.len() * 4).min(max_nodes);
+        let mut ctx = BuildContext::with_buffers(
+            buffers,
+            initial_cap,
+            coded_keys.len(),
+            max_nodes,
+            max_fan_out,
+        );
+        ctx.prefix_free = prefix_free;
+
+        ctx.build_rec(coded_keys, values, 0, coded_keys.len(), 0, 0)?;
+
+        // Trim trailing unused nodes
+        let last_used = ctx
+            .nodes
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, n)| *n != &Node::default())
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        let final_len = last_used + 1;
+        ctx.nodes.truncate(final_len);
+        ctx.siblings.truncate(final_len);
+        // Search starts every traversal at nodes[0]; the root must survive the trim.
+        debug_assert!(!ctx.nodes.is_empty(), "trimmed trie has no root");
+        debug_assert_eq!(ctx.nodes.len(), ctx.siblings.len());
+
+        Ok((
+            Self::new(ctx.nodes, ctx.siblings, code_map),
+            ctx.leaves,
+            ctx.metrics,
+        ))
+    }
+
+    /// Reserves capacity for at least `additional_nodes` more nodes, with the
+    /// same semantics as [`Vec::reserve`] on both `nodes` and `siblings`.
+    ///
+    /// Search results are unchanged; only the allocation grows, so later
+    /// growth of the node array within the reserved space does not reallocate.
+    pub fn reserve(&mut self, additional_nodes: usize) {
+        self.nodes.reserve(additional_nodes);
+        self.siblings.reserve(additional_nodes);
+    }
+
+    /// Returns the number of nodes the trie can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity().min(self.siblings.capacity())
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Inserts `key` with `value_id` into the built trie, returning the
+    /// key's previous value_id if it was already stored.
+    ///
+    /// Missing nodes are added in place, without a rebuild. A new child
+    /// whose slot `base XOR code` is taken moves all its siblings to a base
+    /// where every one of them fits, re-pointing their own children to the
+    /// new slots; the parent's sibling chain is then relinked in code order.
+    /// Labels the code map does not know get new codes after the existing
+    /// ones. A leaf of a [`build_prefix_free`](Self::build_prefix_free) trie
+    /// that gains an extension turns back into a node with a terminal child.
+    ///
+    /// Bases are searched among the unused slots in index order, reusing
+    /// those left by the build, by relocations and by
+    /// [`remove`](Self::remove) before growing the array. The first insert
+    /// or remove collects them in O(num_nodes), and a base search may visit
+    /// all of them: fine for a few keys at runtime, e.g. a user dictionary,
+    /// while large batches are better rebuilt.
+    ///
+    /// # Panics
+    /// - If `value_id` does not fit in 31 bits.
+    /// - If the trie would exceed [`MAX_NODES`] nodes.
+    pub fn insert(&mut self, key: impl AsRef<[L]>, value_id: u32) -> Option<u32> {
+        assert!(value_id <= MASK, "value_id must fit in 31 bits");
+        self.length_range.take();
+        self.subtree_keys.take();
+        self.collect_free_slots();
+
+        let mut node_idx = 0u32;
+        for &label in key.as_ref() {
+            let mut code = self.code_map.get(label);
+            if code == 0 {
+                code = self.code_map.add_label(label.into());
+            }
+            if self.nodes[node_idx as usize].is_leaf() {
+                self.split_leaf(node_idx);
+            }
+            node_idx = match self.view().child(node_idx, code) {
+                Some(child) => child,
+                None => self.insert_child(node_idx, code),
+            };
+        }
+
+        let node = self.nodes[node_idx as usize];
+        if node.is_leaf() {
+            self.nodes[node_idx as usize].set_leaf(value_id);
+            return Some(node.value_id());
+        }
+        if let Some((terminal, old)) = self.view().terminal(node_idx) {
+            self.nodes[terminal as usize].set_leaf(value_id);
+            return Some(old);
+        }
+        let terminal = self.insert_child(node_idx, 0);
+        self.nodes[terminal as usize].set_leaf(value_id);
+        self.nodes[node_idx as usize].set_has_leaf();
+        None
+    }
+
+    /// Removes `key` from the trie, returning its value_id if it was stored.
+    ///
+    /// The key's terminal node is cleared and unlinked from its sibling
+    /// chain, and so is every ancestor left without children, up to but not
+    /// including the root. The cleared slots are kept for reuse by
+    /// [`insert`](Self::insert), and unused slots at the end of the array
+    /// are dropped, so a trie that sees as many removals as insertions stays
+    /// about the same size. The code map keeps labels that no key uses any
+    /// more.
+    pub fn remove(&mut self, key: impl AsRef<[L]>) -> Option<u32> {
+        let node_idx = self.view().traverse(key.as_ref())?;
+        let node = self.nodes[node_idx as usize];
+        let (mut slot, value_id) = if node.is_leaf() {
+            (node_idx, node.value_id())
+        } else {
+            self.view().terminal(node_idx)?
+        };
+        self.length_range.take();
+        self.subtree_keys.take();
+        self.collect_free_slots();
+
+        loop {
+            let parent = self.nodes[slot as usize].check();
+            let base = self.nodes[parent as usize].base();
+            let mut codes = self.child_codes(parent);
+            codes.retain(|&code| base ^ code != slot);
+            if slot == base {
+                self.nodes[parent as usize].clear_has_leaf();
+            }
+            self.release_slot(slot);
+            self.link_siblings(base, &codes);
+            if parent == 0 || !codes.is_empty() {
+                break;
+            }
+            slot = parent;
+        }
+
+        let free = self.free_slots.as_mut().expect("free slots collected");
+        while self.nodes.len() > 1 && free.remove(&(self.nodes.len() as u32 - 1)) {
+            self.nodes.pop();
+            self.siblings.pop();
+        }
+        Some(value_id)
+    }
+
+    /// Turns a leaf that stores its key's value itself into an internal node
+    /// whose terminal child holds the value.
+    fn split_leaf(&mut self, node_idx: u32) {
+        let node = self.nodes[node_idx as usize];
+        // A leaf has no children, so its check carries no HAS_LEAF flag.
+        self.nodes[node_idx as usize] = Node::from_raw(0, node.raw_check());
+        let terminal = self.insert_child(node_idx, 0);
+        self.nodes[terminal as usize].set_leaf(node.value_id());
+        self.nodes[node_idx as usize].set_has_leaf();
+    }
+
+    /// Adds a child with `code` to `parent`, relocating the existing
+    /// children if its slot is taken, and returns its index. The child is
+    /// left with base 0; the caller gives it a leaf value or children.
+    fn insert_child(&mut self, parent: u32, code: u32) -> u32 {
+        let mut codes = self.child_codes(parent);
+        let old_base = self.nodes[parent as usize].base();
+        let base = if !codes.is_empty() && self.is_free_slot(old_base ^ code) {
+            old_base
+        } else {
+            codes.push(code);
+            let base = self.find_insert_base(&codes);
+            codes.pop();
+            self.relocate_children(parent, &codes, base);
+            base
+        };
+
+        let child = base ^ code;
+        self.claim_slot(child);
+        self.nodes[child as usize] = Node::from_raw(0, parent);
+        codes.push(code);
+        codes.sort_unstable();
+        self.link_siblings(base, &codes);
+        child
+    }
+
+    /// Returns the codes of the children of `node_idx`, in chain order,
+    /// i.e. ascending.
+    fn child_codes(&self, node_idx: u32) -> Vec<u32> {
+        let view = self.view();
+        let base = self.nodes[node_idx as usize].base();
+        let mut codes = Vec::new();
+        let mut cursor = view.first_child(node_idx);
+        while let Some(child) = cursor {
+            codes.push(child ^ base);
+            let sib = self.siblings[child as usize];
+            cursor = (sib != 0).then_some(sib);
+        }
+        codes
+    }
+
+    /// Moves the children of `parent` with `codes` from its current base to
+    /// `new_base`, and points their own children at the new slots.
+    fn relocate_children(&mut self, parent: u32, codes: &[u32], new_base: u32) {
+        let old_base = self.nodes[parent as usize].base();
+        for &code in codes {
+            let (from, to) = (old_base ^ code, new_base ^ code);
+            self.claim_slot(to);
+            let node = self.nodes[from as usize];
+            if !node.is_leaf() {
+                for grandchild_code in self.child_codes(from) {
+                    let grandchild = node.base() ^ grandchild_code;
+                    self.nodes[grandchild as usize].set_check(to);
+                }
+            }
+            self.nodes[to as usize] = node;
+            self.release_slot(from);
+        }
+        self.nodes[parent as usize].set_base(new_base);
+    }
+
+    /// Links the children of a node at `base` into a chain in `codes` order.
+    fn link_siblings(&mut self, base: u32, codes: &[u32]) {
+        for w in codes.windows(2) {
+            self.siblings[(base ^ w[0]) as usize] = base ^ w[1];
+        }
+        if let Some(&last) = codes.last() {
+            self.siblings[(base ^ last) as usize] = 0;
+        }
+    }
+
+    /// Finds a nonzero base at which every slot `base XOR code` is free,
+    /// trying the unused slots for the first code in index order, then the
+    /// slots past the end of the array.
+    fn find_insert_base(&self, codes: &[u32]) -> u32 {
+        let first = codes[0];
+        let free = self.free_slots.as_ref().expect("free slots collected");
+        free.iter()
+            .copied()
+            .chain(self.nodes.len() as u32..=MASK)
+            .map(|slot| slot ^ first)
+            .find(|&base| base != 0 && codes.iter().all(|&code| self.is_free_slot(base ^ code)))
+            .unwrap_or_else(|| panic!("trie exceeds the maximum of {MAX_NODES} nodes"))
+    }
+
+    /// Whether `slot` can take a new node: unused, or past the end of the
+    /// array.
+    fn is_free_slot(&self, slot: u32) -> bool {
+        slot as usize >= self.nodes.len()
+            || self
+                .free_slots
+                .as_ref()
+                .is_some_and(|free| free.contains(&slot))
+    }
+
+    /// Collects the unused slots on the first insert or remove. The root is
+    /// never unused, even when it has no children.
+    fn collect_free_slots(&mut self) {
+        if self.free_slots.is_none() {
+            let unused = (1..self.nodes.len() as u32)
+                .filter(|&i| self.nodes[i as usize] == Node::default())
+                .collect();
+            self.free_slots = Some(unused);
+        }
+    }
+
+    /// Takes `slot` for a new node, growing the node arrays to include it.
+    fn claim_slot(&mut self, slot: u32) {
+        let free = self.free_slots.as_mut().expect("free slots collected");
+        let len = self.nodes.len();
+        if slot as usize >= len {
+            // Bases come from find_insert_base, so `slot` is below MAX_NODES.
+            free.extend(len as u32..slot);
+            self.nodes.resize(slot as usize + 1, Node::default());
+            self.siblings.resize(slot as usize + 1, 0);
+        } else {
+            free.remove(&slot);
+        }
+    }
+
+    /// Clears `slot` and keeps it for reuse.
+    fn release_slot(&mut self, slot: u32) {
+        self.nodes[slot as usize] = Node::default();
+        self.siblings[slot as usize] = 0;
+        let free = self.free_slots.as_mut().expect("free slots collected");
+        free.insert(slot);
+    }
+}
+
+impl DoubleArray<char> {
+    /// Builds a char trie from sorted string keys.
+    ///
+    /// Keys must be in char order, comparing `chars()` sequences. Plain
+    /// `sort()` on `&str` or `String` sorts by UTF-8 bytes, which is the same
+    /// order, so its output can be passed as is. Strings sorted by UTF-16 code
+    /// units, as Java, JavaScript and C# sort them, are not in char order when
+    /// they mix supplementary-plane chars with U+E000..U+FFFF; the panic then
+    /// names the offending index and says so. Each key `keys[i]` is assigned
+    /// `value_id = i`.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_from_sorted_strs(keys: &[&str]) -> Self {
+        let keys: Vec<Vec<char>> = keys.iter().map(|k| k.chars().collect()).collect();
+        Self::build(&keys)
+    }
+
+    /// Compares the planned size of a `char` trie of `keys` with that of a
+    /// `u8` trie of their UTF-8 encoding, to pick the label type.
+    ///
+    /// A UTF-8 trie needs up to four nodes per char, but its code map is at
+    /// most 256 entries, while a `char` code map has one entry per code point
+    /// up to the largest label: mostly ASCII keys with a stray high code
+    /// point are cheaper as `u8`, CJK-heavy keys as `char`. The keys may come
+    /// in any order and may repeat. Nothing is built; see
+    /// [`plan`](Self::plan) for what the estimates cover.
+    pub fn recommend_label_type(keys: &[impl AsRef<[char]>]) -> LabelRecommendation {
+        let mut chars: Vec<&[char]> = keys.iter().map(|k| k.as_ref()).collect();
+        chars.sort_unstable();
+        chars.dedup();
+        // Char order is UTF-8 byte order, so the encodings stay sorted.
+        let utf8: Vec<Vec<u8>> = chars
+            .iter()
+            .map(|k| k.iter().collect::<String>().into_bytes())
+            .collect();
+        LabelRecommendation {
+            char_plan: Self::plan(&chars),
+            utf8_plan: DoubleArray::<u8>::plan(&utf8),
+        }
+    }
+}
+
+/// Converts keys to code sequences with the terminal symbol (0) appended.
+fn encode_keys<L: Label>(keys: &[impl AsRef<[L]>], code_map: &CodeMapper) -> Vec<Vec<u32>> {
+    keys.iter()
+        .map(|k| {
+            let k = k.as_ref();
+            // Sized for the terminal up front so the push does not reallocate.
+            let mut codes = Vec::with_capacity(k.len() + 1);
+            codes.extend(k.iter().map(|&l| code_map.get(l)));
+            codes.push(0); // terminal symbol
+            codes
+        })
+        .collect()
+}
+
+/// Panics unless `keys` are in ascending order, naming the first offending index.
+///
+/// Keys are compared as label slices, so `&str` keys given to a `u8` builder
+/// are compared bytewise; for UTF-8 this is the same as comparing by char.
+fn assert_sorted<L: Label>(keys: &[impl AsRef<[L]>], allow_duplicates: bool) {
+    let rule = if allow_duplicates {
+        "keys must be sorted in ascending order"
+    } else {
+        "keys must be sorted in ascending order with no duplicates"
+    };
+    match check_sorted(keys, allow_duplicates) {
+        Ok(()) => {}
+        Err(BuildError::DuplicateKey { index }) => {
+            panic!("duplicate key at index {index}: {rule}")
+        }
+        Err(BuildError::UnsortedKeys { index }) => {
+            match misorder_hint(keys[index - 1].as_ref(), keys[index].as_ref()) {
+                Some(hint) => {
+                    panic!("key at index {index} sorts before the previous key: {rule} ({hint})")
+                }
+                None => panic!("key at index {index} sorts before the previous key: {rule}"),
+            }
+        }
+        Err(e) => unreachable!("check_sorted returned {e:?}"),
+    }
+}
+
+/// Explains a likely cause when `prev` sorts after `next` although the caller
+/// passed them in that order. Returns `None` when there is nothing specific to
+/// say.
+///
+/// Keys sorted by UTF-8 bytes are always in char order, but keys sorted by
+/// UTF-16 code units (as in Java, JavaScript or C# strings) are not:
+/// surrogates (U+D800..U+DFFF) sort supplementary-plane chars before
+/// U+E000..U+FFFF. Only labels that are all Unicode scalar values are
+/// compared that way, and below U+10000 the two orders agree, so in practice
+/// the hint only fires for `char` keys.
+fn misorder_hint<L: Label>(prev: &[L], next: &[L]) -> Option<&'static str> {
+    let utf16 = |key: &[L]| -> Option<Vec<u16>> {
+        let mut units = Vec::with_capacity(key.len());
+        let mut buf = [0u16; 2];
+        for &label in key {
+            let c = char::from_u32(label.into())?;
+            units.extend_from_slice(c.encode_utf16(&mut buf));
+        }
+        Some(units)
+    };
+    (utf16(prev)? < utf16(next)?).then_some(
+        "the keys are in UTF-16 code unit order; char keys must be sorted by \
+         Unicode scalar value (UTF-8 byte order)",
+    )
+}
+
+/// Checks that `keys` are in ascending order, returning the first offending
+/// index as [`BuildError::UnsortedKeys`] or [`BuildError::DuplicateKey`].
+fn check_sorted<L: Label>(
+    keys: &[impl AsRef<[L]>],
+    allow_duplicates: bool,
+) -> Result<(), BuildError> {
+    for (i, w) in keys.windows(2).enumerate() {
+        match w[0].as_ref().cmp(w[1].as_ref()) {
+            Ordering::Less => {}
+            Ordering::Equal if allow_duplicates => {}
+            Ordering::Equal => return Err(BuildError::DuplicateKey { index: i + 1 }),
+            Ordering::Greater => return Err(BuildError::UnsortedKeys { index: i + 1 }),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the indices of `keys` in ascending key order; equal keys keep
+/// their relative order.
+///
+/// With the `parallel` feature, large inputs are sorted on several threads,
+/// with the same result.
+fn sorted_order<L: Label>(keys: &[impl AsRef<[L]>]) -> Vec<usize> {
+    #[cfg(feature = "parallel")]
+    if let Some(order) = crate::parallel::sorted_order(keys) {
+        return order;
+    }
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|&a, &b| keys[a].as_ref().cmp(keys[b].as_ref()));
+    order
+}
+
+/// Unwraps the result of a build whose only budget is [`MAX_NODES`],
+/// panicking with the error's message.
+fn expect_built<T>(result: Result<T, BuildError>) -> T {
+    result.unwrap_or_else(|e| match e {
+        BuildError::ExceededNodeBudget => {
+            panic!("trie exceeds the maximum of {MAX_NODES} nodes")
+        }
+        e => panic!("{e}"),
+    })
+}
+
+/// Returns `0..n` as value_ids, checking that `n` keys can be numbered in 31 bits.
+fn sequential_values(n: usize) -> Vec<u32> {
+    assert!(
+        n <= MASK as usize + 1,
+        "too many keys: value_id must fit in 31 bits"
+    );
+    (0..n as u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::test_util::lcg;
+
+    #[test]
+    fn values_carry_inline_payloads() {
+        // Pack a 4-bit part-of-speech tag and a 27-bit cost into each leaf.
+        let pack = |tag: u32, cost: u32| (tag << 27) | cost;
+        let keys: Vec<&[u8]> = vec![b"run", b"runner", b"running"];
+        let values = [pack(2, 1200), pack(1, 0x7FF_FFFF), pack(0xF, 7)];
+        let da = DoubleArray::<u8>::build_with_values(&keys, &values);
+
+        let payload = da.exact_match(b"runner").unwrap();
+        assert_eq!((payload >> 27, payload & 0x7FF_FFFF), (1, 0x7FF_FFFF));
+        for (key, &value) in keys.iter().zip(&values) {
+            assert_eq!(da.exact_match(key), Some(value));
+        }
+        let restored = DoubleArray::<u8>::from_bytes(&da.as_bytes()).unwrap();
+        assert_eq!(restored.exact_match(b"running"), Some(pack(0xF, 7)));
+    }
+
+    #[test]
+    fn build_verified_catches_broken_tries() {
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"b"];
+        let da = DoubleArray::<u8>::build_verified(&keys);
+        assert_eq!(da.exact_match(b"ab"), Some(2));
+        let empty: &[&[u8]] = &[];
+        assert_eq!(DoubleArray::<u8>::build_verified(empty).num_keys(), 0);
+
+        // Stand in for a builder bug: swap two leaves' value_ids.
+        let mut broken = DoubleArray::<u8>::build(&keys);
+        let (_, leaves) = DoubleArray::<u8>::build_with_leaf_map(&keys);
+        broken.nodes[leaves[1] as usize].set_leaf(2);
+        broken.nodes[leaves[2] as usize].set_leaf(1);
+        assert_eq!(broken.verify(&keys), Err(1));
+
+        // Or an extra key the input does not have.
+        assert_eq!(da.verify(&keys[..3]), Err(3));
+    }
+
+    #[test]
+    fn build_empty() {
+        let keys: Vec<&[u8]> = vec![];
+        let da = DoubleArray::<u8>::build(&keys);
+        assert!(da.num_nodes() > 0); // at least root
+    }
+
+    #[test]
+    fn build_single_key() {
+        let da = DoubleArray::<u8>::build(&[b"abc"]);
+        assert!(da.num_nodes() > 1);
+    }
+
+    #[test]
+    fn build_with_leaf_map_points_at_leaves() {
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"abc", b"b", b"bcd", b"c"];
+        let (da, leaf_map) = DoubleArray::<u8>::build_with_leaf_map(&keys);
+        assert_eq!(leaf_map.len(), keys.len());
+        assert_eq!(da.as_bytes(), DoubleArray::<u8>::build(&keys).as_bytes());
+        for (value_id, (&leaf, key)) in leaf_map.iter().zip(&keys).enumerate() {
+            let node = da.nodes[leaf as usize];
+            assert!(node.is_leaf(), "{key:?}");
+            assert_eq!(node.value_id(), value_id as u32, "{key:?}");
+            assert_eq!(
+                Some(node.check()),
+                da.inspect(key).map(|info| info.index),
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn build_with_leaf_map_empty() {
+        let keys: Vec<&[u8]> = vec![];
+        let (_, leaf_map) = DoubleArray::<u8>::build_with_leaf_map(&keys);
+        assert!(leaf_map.is_empty());
+    }
+
+    #[test]
+    fn capacity_beyond_max_nodes_errors() {
+        // Fails before allocating anything.
+        let mut ctx =
+            BuildContext::with_buffers(NodeBuffers::default(), 4, 0, MAX_NODES, usize::MAX);
+        assert_eq!(
+            ctx.ensure_capacity(MAX_NODES + 1, MAX_NODES + 1),
+            Err(BuildError::ExceededNodeBudget)
+        );
+        assert_eq!(ctx.nodes.len(), 4);
+    }
+
+    #[test]
+    fn base_beyond_mask_errors() {
+        let mut ctx =
+            BuildContext::with_buffers(NodeBuffers::default(), 4, 0, MAX_NODES, usize::MAX);
+        assert_eq!(
+            ctx.set_base(1, MASK + 1),
+            Err(BuildError::NodeIndexOverflow)
+        );
+        assert_eq!(
+            ctx.set_base(1, u32::MAX),
+            Err(BuildError::NodeIndexOverflow)
+        );
+        // The node is untouched, not silently turned into a leaf.
+        assert_eq!(ctx.nodes[1], Node::default());
+        assert_eq!(ctx.set_base(1, MASK), Ok(()));
+        assert_eq!(ctx.nodes[1].base(), MASK);
+        assert!(!ctx.nodes[1].is_leaf());
+    }
+
+    #[test]
+    fn build_metrics_are_populated() {
+        // Few short keys fit the initial capacity of 256 slots.
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bc"];
+        let (da, metrics) = DoubleArray::<u8>::build_with_metrics(&keys);
+        assert_eq!(metrics.num_regrows, 0);
+        assert_eq!(metrics.peak_capacity, 256);
+        assert!(metrics.peak_capacity >= da.num_nodes());
+        assert_eq!(da.as_bytes(), DoubleArray::<u8>::build(&keys).as_bytes());
+
+        // Ten keys of 200 distinct labels each need far more than 256 slots.
+        let long: Vec<Vec<char>> = (0..10u32)
+            .map(|k| {
+                (0..200)
+                    .map(|i| char::from_u32(0x4E00 + k * 200 + i).unwrap())
+                    .collect()
+            })
+            .collect();
+        let (da, metrics) = DoubleArray::<char>::build_with_metrics(&long);
+        assert!(metrics.num_regrows > 0);
+        assert!(metrics.peak_capacity >= da.num_nodes());
+        assert!(metrics.peak_capacity >= da.num_used_nodes());
+        assert!(metrics.find_base_iterations > 0);
+        assert!(!metrics.sparse);
+    }
+
+    #[test]
+    fn foreign_code_map_makes_a_sparse_trie() {
+        // The map of a 5000-label dictionary gives these labels codes near
+        // 2000 and 5000, so the root's children land thousands of slots apart.
+        let dictionary: Vec<Vec<u16>> = (0..5000).map(|i| vec![i]).collect();
+        let shared = CodeMapper::build(&dictionary);
+        let keys: Vec<&[u16]> = vec![&[2000], &[2000, 4999], &[4999]];
+        let da = DoubleArray::<u16>::build_checked(&keys, shared).unwrap();
+        assert!(da.load_factor() < 0.01, "{}", da.load_factor());
+        assert!(da.is_sparse());
+
+        // Its own code map packs the same keys.
+        let (own, metrics) = DoubleArray::<u16>::build_with_metrics(&keys);
+        assert!(!metrics.sparse);
+        assert!(!own.is_sparse());
+        assert_eq!(own.num_used_nodes(), da.num_used_nodes());
+        assert_eq!(own.load_factor(), 1.0);
+    }
+
+    #[test]
+    fn plan_matches_built_trie() {
+        let mut next = lcg(7);
+        let mut hiragana: Vec<Vec<char>> = (0..5000)
+            .map(|_| {
+                (0..next(7) + 1)
+                    .map(|_| char::from_u32(0x3041 + next(83) as u32).unwrap())
+                    .collect()
+            })
+            .collect();
+        hiragana.sort();
+        hiragana.dedup();
+        let numbers: Vec<String> = {
+            let mut v: Vec<String> = (0..3000).map(|i| (i * 7).to_string()).collect();
+            v.sort();
+            v
+        };
+        let words: Vec<Vec<char>> = ["", "か", "かな", "かなし", "な", "漢字", "\u{10FFFF}"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+
+        fn check<L: Label>(keys: &[impl AsRef<[L]>]) {
+            let plan = DoubleArray::<L>::plan(keys);
+            let da = DoubleArray::<L>::build(keys);
+            assert_eq!(plan.num_keys, keys.len());
+            assert_eq!(plan.used_nodes, da.num_used_nodes());
+            assert_eq!(plan.alphabet_size, da.code_map().alphabet_size());
+            let max_depth = keys.iter().map(|k| k.as_ref().len()).max().unwrap_or(0);
+            assert_eq!(plan.max_depth, max_depth);
+            // Holes add node slots on top of the used ones, but at most 50%.
+            assert!(da.num_nodes() >= plan.used_nodes);
+            assert!(da.num_nodes() * 2 <= plan.used_nodes * 3);
+            assert!(da.serialized_size() >= plan.estimated_bytes);
+            // The widest node fits a limit of exactly its fan-out.
+            assert!(DoubleArray::<L>::build_with_fan_out_limit(keys, plan.max_fan_out).is_ok());
+            if plan.max_fan_out > 0 {
+                assert_eq!(
+                    DoubleArray::<L>::build_with_fan_out_limit(keys, plan.max_fan_out - 1).err(),
+                    Some(BuildError::ExceededFanOut {
+                        fan_out: plan.max_fan_out
+                    })
+                );
+            }
+        }
+        check::<char>(&hiragana);
+        check::<u8>(&numbers);
+        check::<char>(&words);
+        check::<u8>(&Vec::<&[u8]>::new());
+    }
+
+    #[test]
+    fn build_unsorted_keeps_input_indices() {
+        let keys = ["さくら", "あい", "さく", "う"];
+        let chars: Vec<Vec<char>> = keys.iter().map(|k| k.chars().collect()).collect();
+        let da = DoubleArray::<char>::build_unsorted(&chars);
+        for (i, key) in chars.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(
+            DoubleArray::<char>::build_unsorted(&Vec::<Vec<char>>::new()).num_nodes(),
+            1
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate keys at indices 0 and 2")]
+    fn build_unsorted_rejects_duplicates() {
+        DoubleArray::<u8>::build_unsorted(&["b", "a", "b"]);
+    }
+
+    #[test]
+    fn build_with_lengths_indexes_by_value_id() {
+        let keys: Vec<Vec<char>> = ["", "か", "かな", "かなしい", "漢字"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let (da, lengths) = DoubleArray::<char>::build_with_lengths(&keys);
+        assert_eq!(lengths, vec![0, 1, 2, 4, 2]);
+        for key in &keys {
+            let id = da.exact_match(key).unwrap();
+            assert_eq!(lengths[id as usize] as usize, key.len());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "key at index 1 is longer than 65535 labels")]
+    fn build_with_lengths_rejects_long_keys() {
+        let keys = vec![vec![b'a'], vec![b'b'; 65_536]];
+        DoubleArray::<u8>::build_with_lengths(&keys);
+    }
+
+    #[test]
+    fn build_from_map_uses_map_values() {
+        let map: BTreeMap<Vec<char>, u32> = [("東京", 7), ("東", 3), ("大阪", 42), ("", 9)]
+            .into_iter()
+            .map(|(k, v)| (k.chars().collect(), v))
+            .collect();
+        let da = DoubleArray::<char>::build_from_map(&map);
+        for (key, &value) in &map {
+            assert_eq!(da.exact_match(key), Some(value));
+        }
+        assert_eq!(da.exact_match(['京']), None);
+
+        let from_vecs = DoubleArray::<char>::build_with_values(
+            &map.keys().collect::<Vec<_>>(),
+            &map.values().copied().collect::<Vec<_>>(),
+        );
+        assert_eq!(da.as_bytes(), from_vecs.as_bytes());
+    }
+
+    #[test]
+    fn try_build_reports_bad_input() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"b"];
+        let da = DoubleArray::<u8>::try_build(&keys).unwrap();
+        assert_eq!(da.as_bytes(), DoubleArray::<u8>::build(&keys).as_bytes());
+
+        assert_eq!(
+            DoubleArray::<u8>::try_build(&[b"a" as &[u8], b"c", b"b"]).unwrap_err(),
+            BuildError::UnsortedKeys { index: 2 }
+        );
+        let err = DoubleArray::<u8>::try_build(&[b"a" as &[u8], b"b", b"b", b"a"]).unwrap_err();
+        assert_eq!(err, BuildError::DuplicateKey { index: 2 });
+        assert_eq!(err.to_string(), "duplicate key at index 2");
+        assert!(DoubleArray::<u8>::try_build(&[] as &[&[u8]]).is_ok());
+    }
+
+    #[test]
+    fn build_bounded_respects_node_budget() {
+        let keys: Vec<String> = (0..200).map(|i| format!("key{i:03}")).collect();
+        let needed = DoubleArray::<u8>::build(&keys).num_nodes();
+
+        assert_eq!(
+            DoubleArray::<u8>::build_bounded(&keys, needed / 2).err(),
+            Some(BuildError::ExceededNodeBudget)
+        );
+        assert_eq!(
+            DoubleArray::<u8>::build_bounded(&keys, 0).err(),
+            Some(BuildError::ExceededNodeBudget)
+        );
+
+        let da = DoubleArray::<u8>::build_bounded(&keys, needed).unwrap();
+        assert!(da.num_nodes() <= needed);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key.as_bytes()), Some(i as u32));
+        }
+    }
+
+    #[test]
+    fn sharded_queries_match_single_trie() {
+        let keys: Vec<&[u8]> = vec![
+            b"a", b"ab", b"abc", b"abd", b"b", b"ba", b"bab", b"c", b"ca", b"cab",
+        ];
+        let single = DoubleArray::<u8>::build(&keys);
+        for shard_size in [1, 3, 4, keys.len(), keys.len() + 5] {
+            let shards = DoubleArray::<u8>::build_sharded(&keys, shard_size);
+            assert_eq!(shards.len(), keys.len().div_ceil(shard_size));
+
+            let queries: [&[u8]; 8] = [b"", b"a", b"abc", b"abcd", b"bab", b"cabx", b"d", b"ca"];
+            for q in queries {
+                let hits: Vec<u32> = shards.iter().filter_map(|s| s.exact_match(q)).collect();
+                assert_eq!(hits, single.exact_match(q).into_iter().collect::<Vec<_>>());
+
+                let mut prefixes: Vec<_> = shards
+                    .iter()
+                    .flat_map(|s| s.common_prefix_search(q))
+                    .collect();
+                prefixes.sort_by_key(|m| m.len);
+                assert_eq!(prefixes, single.common_prefix_search(q).collect::<Vec<_>>());
+
+                let mut predicted: Vec<u32> = shards
+                    .iter()
+                    .flat_map(|s| s.predictive_search(q).map(|m| m.value_id))
+                    .collect();
+                let mut expected: Vec<u32> =
+                    single.predictive_search(q).map(|m| m.value_id).collect();
+                predicted.sort_unstable();
+                expected.sort_unstable();
+                assert_eq!(predicted, expected);
+            }
+        }
+        assert!(DoubleArray::<u8>::build_sharded(&[] as &[&[u8]], 4).is_empty());
+    }
+
+    #[test]
+    fn fan_out_limit_rejects_wide_nodes() {
+        // `n` distinct CJK labels under the prefix "x", plus "x" itself.
+        let wide = |n: u32| -> Vec<Vec<char>> {
+            let mut keys = vec![vec!['x']];
+            keys.extend((0..n).map(|i| vec!['x', char::from_u32(0x4E00 + i).unwrap()]));
+            keys
+        };
+        // Rejected before the node is placed, so this stays fast.
+        let keys = wide(20_000);
+        assert_eq!(DoubleArray::<char>::plan(&keys).max_fan_out, 20_001);
+        assert_eq!(
+            DoubleArray::<char>::build_with_fan_out_limit(&keys, 10_000).err(),
+            Some(BuildError::ExceededFanOut { fan_out: 20_001 })
+        );
+
+        let keys = wide(300);
+        let da = DoubleArray::<char>::build_with_fan_out_limit(&keys, 301).unwrap();
+        assert_eq!(da.exact_match(['x', '\u{4E00}']), Some(1));
+        assert_eq!(da.exact_match(['x']), Some(0));
+        assert_eq!(
+            DoubleArray::<char>::build_with_fan_out_limit(&keys, 300).err(),
+            Some(BuildError::ExceededFanOut { fan_out: 301 })
+        );
+    }
+
+    #[test]
+    fn build_with_source_order_maps_back_to_input() {
+        let lines: Vec<&[u8]> = vec![b"pear", b"apple", b"fig", b"apple", b"", b"fig", b"kiwi"];
+        let (da, source) = DoubleArray::<u8>::build_with_source_order(&lines);
+        assert_eq!(da.num_keys(), 5);
+        assert_eq!(source, vec![4, 1, 2, 6, 0]);
+        for (i, key) in lines.iter().enumerate() {
+            let value_id = da.exact_match(key).unwrap();
+            let first = lines.iter().position(|k| k == key).unwrap();
+            assert_eq!(source[value_id as usize], first as u32, "line {i}");
+        }
+        let sorted: Vec<Vec<u8>> = da.keys_sorted().collect();
+        for (value_id, key) in sorted.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(value_id as u32));
+        }
+
+        let (empty, source) = DoubleArray::<u8>::build_with_source_order(&[] as &[&[u8]]);
+        assert_eq!(empty.num_keys(), 0);
+        assert!(source.is_empty());
+    }
+
+    #[test]
+    fn build_from_unsorted_maps_input_to_value_ids() {
+        let keys: Vec<&str> = vec!["kiwi", "apple", "fig", "apple", "banana"];
+        let (da, ids) = DoubleArray::<u8>::build_from_unsorted(&keys, true);
+        assert_eq!(da.num_keys(), 4);
+        assert_eq!(ids, vec![3, 0, 2, 0, 1]);
+        for (key, &id) in keys.iter().zip(&ids) {
+            assert_eq!(da.exact_match(key), Some(id));
+        }
+
+        let distinct = ["pear", "fig", "kiwi"];
+        let (da, ids) = DoubleArray::<u8>::build_from_unsorted(&distinct, false);
+        assert_eq!(ids, vec![2, 0, 1]);
+        let sorted = DoubleArray::<u8>::build(&["fig", "kiwi", "pear"]);
+        assert_eq!(da.as_bytes(), sorted.as_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate keys at indices 1 and 4")]
+    fn build_from_unsorted_without_dedup_rejects_repeats() {
+        DoubleArray::<u8>::build_from_unsorted(&["kiwi", "fig", "apple", "pear", "fig"], false);
+    }
+
+    #[test]
+    fn recommend_label_type_by_script() {
+        let ascii: Vec<Vec<char>> = (0..500)
+            .map(|i| format!("word{i:03}").chars().collect())
+            .collect();
+        let rec = DoubleArray::<char>::recommend_label_type(&ascii);
+        assert!(rec.prefers_u8());
+        assert_eq!(rec.utf8_plan.used_nodes, rec.char_plan.used_nodes);
+
+        // A stray emoji inflates the char code map to every code point below it.
+        let mut mixed = ascii.clone();
+        mixed.push(vec!['😀']);
+        let rec = DoubleArray::<char>::recommend_label_type(&mixed);
+        assert!(rec.prefers_u8());
+        assert!(rec.char_plan.estimated_bytes > 4 * rec.utf8_plan.estimated_bytes);
+
+        // Three bytes per kana or kanji; the code map ends at 漢 (U+6F22).
+        let kana: Vec<char> = ('あ'..='ん').collect();
+        let cjk: Vec<Vec<char>> = (0..3000)
+            .map(|i| vec![kana[i % 82], kana[i / 82 % 82], '漢', kana[i % 7]])
+            .collect();
+        let rec = DoubleArray::<char>::recommend_label_type(&cjk);
+        assert!(!rec.prefers_u8());
+        assert_eq!(rec.char_plan.num_keys, rec.utf8_plan.num_keys);
+        assert!(rec.utf8_plan.used_nodes > 2 * rec.char_plan.used_nodes);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn node_indices_widen_losslessly_on_32bit() {
+        let last = (MAX_NODES - 1) as u32;
+        assert_eq!(last, MASK);
+        assert_eq!(last as usize, MAX_NODES - 1);
+        assert!(u32::MAX as usize >= MAX_NODES);
+    }
+
+    #[test]
+    fn build_normalized_folds_case() {
+        let keys: Vec<&[u8]> = vec![b"abc", b"XYZ", b"ABC", b"ab"];
+        let da = DoubleArray::<u8>::build_normalized(&keys, |l| l.to_ascii_lowercase());
+        let lower = |l: u8| l.to_ascii_lowercase();
+
+        // "abc" and "ABC" collide; the first index wins.
+        assert_eq!(da.exact_match_normalized(b"ABC", lower), Some(0));
+        assert_eq!(da.exact_match_normalized(b"aBc", lower), Some(0));
+        assert_eq!(da.exact_match_normalized(b"xyz", lower), Some(1));
+        assert_eq!(da.exact_match_normalized(b"AB", lower), Some(3));
+        assert_eq!(da.exact_match(b"ABC"), None);
+        assert_eq!(da.predictive_search(b"").count(), 3);
+    }
+
+    #[test]
+    fn build_normalized_folds_unicode_case() {
+        fn fold(c: char) -> char {
+            match c {
+                'Α' => 'α',
+                'Σ' | 'ς' => 'σ',
+                'Ω' => 'ω',
+                'Ä' => 'ä',
+                _ => c,
+            }
+        }
+        let keys: Vec<Vec<char>> = ["ΑΣΩ", "Ärger", "σοφός"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let da = DoubleArray::<char>::build_normalized(&keys, fold);
+        let q = |s: &str| s.chars().collect::<Vec<char>>();
+
+        assert_eq!(da.exact_match_normalized(q("ασω"), fold), Some(0));
+        assert_eq!(da.exact_match_normalized(q("ΑσΩ"), fold), Some(0));
+        assert_eq!(da.exact_match_normalized(q("ärger"), fold), Some(1));
+        // Final and medial sigma fold alike; capitals outside the fold do not.
+        assert_eq!(da.exact_match_normalized(q("σοφόσ"), fold), Some(2));
+        assert_eq!(da.exact_match_normalized(q("ΣΟΦΌΣ"), fold), None);
+        assert_eq!(da.exact_match_normalized(q("Σοφός"), fold), Some(2));
+        assert_eq!(da.exact_match(q("ΑΣΩ")), None);
+    }
+
+    #[test]
+    fn value_ids_are_dense_for_positional_builds() {
+        fn assert_dense<L: Label>(da: &DoubleArray<L>) {
+            let mut ids: Vec<u32> = da.predictive_search(&[]).map(|m| m.value_id).collect();
+            ids.sort_unstable();
+            let expected: Vec<u32> = (0..da.num_keys() as u32).collect();
+            assert_eq!(ids, expected);
+        }
+
+        let words: Vec<Vec<char>> = ["", "あ", "あい", "い", "いう", "漢字"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let numbers: Vec<String> = {
+            let mut v: Vec<String> = (0..500).map(|i| (i * 13).to_string()).collect();
+            v.sort();
+            v
+        };
+        let shuffled: Vec<&str> = vec!["kiwi", "apple", "fig", "banana", "cherry"];
+
+        let da = DoubleArray::<char>::build(&words);
+        assert_eq!(da.num_keys(), words.len());
+        assert_dense(&da);
+        assert_dense(&DoubleArray::<char>::from_bytes(&da.as_bytes()).unwrap());
+
+        let da = DoubleArray::<u8>::build(&numbers);
+        assert_eq!(da.num_keys(), numbers.len());
+        assert_dense(&da);
+        let (nodes, siblings) = da.into_buffers();
+        assert_dense(&DoubleArray::<u8>::build_into(&numbers, nodes, siblings));
+
+        let da = DoubleArray::<u8>::build_unsorted(&shuffled);
+        assert_eq!(da.num_keys(), shuffled.len());
+        assert_dense(&da);
+
+        assert_eq!(DoubleArray::<u8>::build(&Vec::<&[u8]>::new()).num_keys(), 0);
+    }
+
+    #[test]
+    fn num_used_nodes_excludes_holes() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bcd", b"xyz"];
+        let da = DoubleArray::<u8>::build(&keys);
+        // root + one node per distinct non-empty prefix + one terminal per key
+        let mut prefixes: Vec<&[u8]> = keys
+            .iter()
+            .flat_map(|k| (1..=k.len()).map(move |i| &k[..i]))
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+        assert_eq!(da.num_used_nodes(), 1 + prefixes.len() + keys.len());
+        assert!(da.num_used_nodes() <= da.num_nodes());
+
+        // Codes from a larger alphabet spread the children out, leaving holes.
+        let dictionary: Vec<Vec<u16>> = (0..5000).map(|i| vec![i]).collect();
+        let keys: Vec<&[u16]> = vec![&[7], &[7, 2000], &[2000, 4999], &[4999]];
+        let sparse =
+            DoubleArray::<u16>::build_checked(&keys, CodeMapper::build(&dictionary)).unwrap();
+        let mut by_hand = 1; // the root
+        for node in &sparse.nodes[1..] {
+            if *node != Node::default() {
+                by_hand += 1;
+            }
+        }
+        assert_eq!(sparse.num_used_nodes(), by_hand);
+        assert!(sparse.num_used_nodes() < sparse.num_nodes());
+        let restored = DoubleArray::<u16>::from_bytes(&sparse.as_bytes()).unwrap();
+        assert_eq!(restored.num_nodes(), sparse.num_nodes());
+        assert_eq!(restored.num_used_nodes(), sparse.num_used_nodes());
+
+        // With their own code map the same keys pack without holes.
+        let packed = DoubleArray::<u16>::build(&keys);
+        assert_eq!(packed.num_used_nodes(), sparse.num_used_nodes());
+        assert_eq!(packed.num_used_nodes(), packed.num_nodes());
+
+        // A root-only trie has no holes.
+        let empty = DoubleArray::<u8>::build(&Vec::<&[u8]>::new());
+        assert_eq!(empty.num_used_nodes(), 1);
+        assert_eq!(empty.num_used_nodes(), empty.num_nodes());
+    }
+
+    #[test]
+    fn tiny_tries_keep_root() {
+        let cases: Vec<Vec<&[u8]>> = vec![vec![], vec![b""], vec![b"a"], vec![b"", b"a"]];
+        for keys in &cases {
+            let da = DoubleArray::<u8>::build(keys);
+            assert!(da.num_nodes() >= 1, "{keys:?}");
+            assert_eq!(da.view().traverse(&[]), Some(0), "{keys:?}");
+            assert_eq!(
+                da.exact_match(b""),
+                keys.first().filter(|k| k.is_empty()).map(|_| 0)
+            );
+        }
+    }
+
+    #[test]
+    fn build_checked_reports_colliding_keys() {
+        // 'x' and 'y' are rare and share the bucket code of a 3-code map.
+        let keys: Vec<&[u8]> = vec![b"aa", b"ab", b"ax", b"ay", b"b", b"bx"];
+        let code_map = CodeMapper::build_capped(&keys, 3);
+        assert_eq!(code_map.get(b'x'), code_map.get(b'y'));
+        assert_eq!(
+            DoubleArray::<u8>::build_checked(&keys, code_map).unwrap_err(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn build_checked_accepts_distinguishable_keys() {
+        // a: 4, y: 3, then b, x, z share the bucket. Bucketed labels are fine
+        // as long as no two keys collide, even when the codes order "ax"
+        // after "ay" unlike the labels do.
+        let code_map = CodeMapper::build_capped(&[b"ax" as &[u8], b"ay", b"ayy", b"az", b"b"], 4);
+        assert_eq!(code_map.get(b'x'), code_map.get(b'z'));
+        assert!(code_map.get(b'y') < code_map.get(b'x'));
+        let keys: Vec<&[u8]> = vec![b"ax", b"ay", b"b"];
+        let da = DoubleArray::<u8>::build_checked(&keys, code_map).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    fn build_validated_reports_all_invalid_keys() {
+        let no_space = |key: &[u8]| !key.contains(&b' ');
+        let keys: Vec<&[u8]> = vec![b" a", b"a", b"a b", b"ab", b"b "];
+        assert_eq!(
+            DoubleArray::<u8>::build_validated(&keys, no_space).err(),
+            Some(vec![0, 2, 4])
+        );
+
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"b"];
+        let da = DoubleArray::<u8>::build_validated(&keys, no_space).unwrap();
+        assert_eq!(da.exact_match(b"ab"), Some(1));
+    }
+
+    #[test]
+    fn build_checked_reports_unmapped_labels() {
+        let code_map = CodeMapper::build(&[b"ab"]);
+        let keys: Vec<&[u8]> = vec![b"a", b"ac", b"b"];
+        assert_eq!(
+            DoubleArray::<u8>::build_checked(&keys, code_map).unwrap_err(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn reserve_grows_capacity_without_changing_results() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"b"];
+        let mut da = DoubleArray::<u8>::build(&keys);
+        let len = da.num_nodes();
+        da.reserve(100);
+        assert!(da.capacity() >= len + 100);
+        assert_eq!(da.num_nodes(), len);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32));
+        }
+
+        // Growing into the reserved space keeps the same allocation.
+        let (nodes_ptr, siblings_ptr) = (da.nodes.as_ptr(), da.siblings.as_ptr());
+        da.nodes.resize(len + 100, Node::default());
+        da.siblings.resize(len + 100, 0);
+        assert_eq!(da.nodes.as_ptr(), nodes_ptr);
+        assert_eq!(da.siblings.as_ptr(), siblings_ptr);
+        assert_eq!(da.exact_match(b"ab"), Some(1));
+    }
+
+    #[test]
+    fn build_into_reuses_buffers() {
+        let first: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b"];
+        let second: Vec<&[u8]> = vec![b"x", b"xy", b"z"];
+
+        let da = DoubleArray::<u8>::build_into(&first, Vec::with_capacity(1024), Vec::new());
+        assert_eq!(da.as_bytes(), DoubleArray::<u8>::build(&first).as_bytes());
+        let (nodes, siblings) = da.into_buffers();
+        let (nodes_ptr, siblings_ptr) = (nodes.as_ptr(), siblings.as_ptr());
+
+        let da = DoubleArray::<u8>::build_into(&second, nodes, siblings);
+        assert_eq!(da.as_bytes(), DoubleArray::<u8>::build(&second).as_bytes());
+        for (i, key) in second.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(da.exact_match(b"ab"), None);
+        // Both builds fit in the first allocation, so nothing was reallocated.
+        assert_eq!(da.nodes.as_ptr(), nodes_ptr);
+        assert_eq!(da.siblings.as_ptr(), siblings_ptr);
+
+        let (nodes, siblings) = da.into_buffers();
+        let empty = DoubleArray::<u8>::build_into(&Vec::<&[u8]>::new(), nodes, siblings);
+        assert_eq!(empty.num_nodes(), 1);
+    }
+
+    #[test]
+    fn build_uses_initial_capacity() {
+        // root + 3 labels + terminal; placement must start at the low free slots
+        // rather than skipping past the initial allocation.
+        let da = DoubleArray::<u8>::build(&[b"abc"]);
+        assert!(da.num_nodes() < 16, "num_nodes = {}", da.num_nodes());
+    }
+
+    #[test]
+    fn build_shared_prefix() {
+        let da = DoubleArray::<u8>::build(&[b"abc", b"abd", b"xyz"]);
+        assert!(da.num_nodes() > 1);
+    }
+
+    #[test]
+    fn build_char_keys() {
+        let keys: Vec<Vec<char>> = vec![
+            "あい".chars().collect(),
+            "あう".chars().collect(),
+            "かき".chars().collect(),
+        ];
+        let da = DoubleArray::<char>::build(&keys);
+        assert!(da.num_nodes() > 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "sorted")]
+    fn build_unsorted_panics() {
+        DoubleArray::<u8>::build(&[b"bbb", b"aaa"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "sorted")]
+    fn build_duplicates_panics() {
+        DoubleArray::<u8>::build(&[b"aaa", b"aaa"]);
+    }
+
+    #[test]
+    fn build_with_values_stores_given_values() {
+        let da = DoubleArray::<u8>::build_with_values(&[b"a", b"b", b"c"], &[10, 7, 10]);
+        assert_eq!(da.exact_match(b"a"), Some(10));
+        assert_eq!(da.exact_match(b"b"), Some(7));
+        assert_eq!(da.exact_match(b"c"), Some(10));
+    }
+
+    fn build_panic_message<L: Label>(keys: &[Vec<L>]) -> Option<String> {
+        let keys = keys.to_vec();
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            DoubleArray::<L>::build(&keys);
+        }))
+        .err()
+        .map(|e| match e.downcast::<String>() {
+            Ok(msg) => *msg,
+            Err(e) => e.downcast_ref::<&str>().unwrap().to_string(),
+        })
+    }
+
+    #[test]
+    fn duplicate_and_order_detection_matrix() {
+        let u8_cases: Vec<(Vec<&[u8]>, Option<&str>)> = vec![
+            (vec![b"", b""], Some("duplicate key at index 1")),
+            (vec![b"", b"a"], None),
+            (vec![b"a", b""], Some("index 1 sorts before")),
+            (vec![b"a", b"a"], Some("duplicate key at index 1")),
+            (vec![b"a", b"ab", b"ab"], Some("duplicate key at index 2")),
+            (vec![b"ab", b"a"], Some("index 1 sorts before")),
+            (vec![b"a", b"b", b"a"], Some("index 2 sorts before")),
+            (vec![b"a\0", b"a"], Some("index 1 sorts before")),
+            (vec![b"a", b"a\0"], None),
+        ];
+        for (keys, expected) in u8_cases {
+            let keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+            let msg = build_panic_message(&keys);
+            match expected {
+                Some(e) => assert!(
+                    msg.as_deref().unwrap_or("").contains(e),
+                    "{keys:?}: {msg:?}"
+                ),
+                None => assert_eq!(msg, None, "{keys:?}"),
+            }
+        }
+
+        let char_cases: Vec<(Vec<&str>, Option<&str>)> = vec![
+            (vec!["", ""], Some("duplicate key at index 1")),
+            (vec!["東京", "東京"], Some("duplicate key at index 1")),
+            (vec!["東", "東京", "東京"], Some("duplicate key at index 2")),
+            (vec!["東京", "東"], Some("index 1 sorts before")),
+            (vec!["あ", "東"], None),
+            (vec!["東", "あ"], Some("index 1 sorts before")),
+        ];
+        for (keys, expected) in char_cases {
+            let keys: Vec<Vec<char>> = keys.iter().map(|k| k.chars().collect()).collect();
+            let msg = build_panic_message(&keys);
+            match expected {
+                Some(e) => assert!(
+                    msg.as_deref().unwrap_or("").contains(e),
+                    "{keys:?}: {msg:?}"
+                ),
+                None => assert_eq!(msg, None, "{keys:?}"),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "UTF-16 code unit order")]
+    fn utf16_sorted_char_keys_get_a_hint() {
+        // Sorted as Java/JavaScript strings: "\u{1F600}" < "\u{FF61}".
+        let mut keys: Vec<Vec<char>> = vec![vec!['\u{FF61}'], vec!['\u{1F600}']];
+        keys.sort_by_key(|k| {
+            k.iter()
+                .collect::<String>()
+                .encode_utf16()
+                .collect::<Vec<_>>()
+        });
+        DoubleArray::<char>::build(&keys);
+    }
+
+    #[test]
+    #[should_panic(expected = "node index exceeds 31 bits")]
+    fn expect_built_reports_the_actual_error() {
+        expect_built::<()>(Err(BuildError::NodeIndexOverflow));
+    }
+
+    #[test]
+    fn misorder_hint_only_for_utf16_order() {
+        // UTF-16: U+1F600 is D83D DE00, which sorts before U+FF61.
+        assert!(misorder_hint(&['\u{1F600}'], &['\u{FF61}']).is_some());
+        assert!(misorder_hint(&['b'], &['a']).is_none());
+        assert!(misorder_hint(b"b", b"a").is_none());
+        // Surrogate values are not scalar values; no hint for u16 labels.
+        assert!(misorder_hint(&[0xD83Du16], &[0xFF61u16]).is_none());
+    }
+
+    #[test]
+    fn utf8_sorted_char_keys_build() {
+        let mut keys: Vec<String> = vec!["\u{FF61}".into(), "\u{1F600}".into(), "a".into()];
+        keys.sort(); // bytewise
+        let keys: Vec<Vec<char>> = keys.iter().map(|k| k.chars().collect()).collect();
+        DoubleArray::<char>::build(&keys);
+    }
+
+    #[test]
+    fn build_from_sorted_strs_accepts_byte_sorted_strs() {
+        let mut keys = vec!["東京", "\u{1F600}", "\u{FF61}", "あ", "a", "あい"];
+        keys.sort();
+        let da = DoubleArray::<char>::build_from_sorted_strs(&keys);
+        for (i, key) in keys.iter().enumerate() {
+            let key: Vec<char> = key.chars().collect();
+            assert_eq!(da.exact_match(&key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "key at index 2 sorts before the previous key: keys must be sorted \
+                               in ascending order with no duplicates (the keys are in UTF-16"
+    )]
+    fn build_from_sorted_strs_rejects_utf16_order() {
+        let mut keys = vec!["a", "\u{FF61}", "\u{1F600}"];
+        keys.sort_by_key(|k| k.encode_utf16().collect::<Vec<_>>());
+        assert_eq!(keys, ["a", "\u{1F600}", "\u{FF61}"]);
+        DoubleArray::<char>::build_from_sorted_strs(&keys);
+    }
+
+    #[test]
+    fn str_keys_use_byte_order_for_u8() {
+        // Byte order of UTF-8 matches char order, so sorted &str keys build.
+        let keys = ["あ", "あい", "東", "東京"];
+        let da = DoubleArray::<u8>::build(&keys);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key.as_bytes()), Some(i as u32));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "same length")]
+    fn build_with_values_length_mismatch_panics() {
+        DoubleArray::<u8>::build_with_values(&[b"a", b"b"], &[0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "31 bits")]
+    fn build_with_values_oversized_value_panics() {
+        DoubleArray::<u8>::build_with_values(&[b"a"], &[1 << 31]);
+    }
+
+    #[test]
+    fn build_combining_sums_duplicates() {
+        let keys: Vec<&[u8]> = vec![b"a", b"a", b"b"];
+        let da = DoubleArray::<u8>::build_combining(&keys, &[1, 2, 5], |x, y| x + y);
+        assert_eq!(da.exact_match(b"a"), Some(3));
+        assert_eq!(da.exact_match(b"b"), Some(5));
+        assert_eq!(da.predictive_search(b"").count(), 2);
+    }
+
+    #[test]
+    fn build_combining_folds_in_input_order() {
+        let keys: Vec<&[u8]> = vec![b"k", b"k", b"k"];
+        let da = DoubleArray::<u8>::build_combining(&keys, &[1, 2, 3], |acc, v| acc * 10 + v);
+        assert_eq!(da.exact_match(b"k"), Some(123));
+    }
+
+    #[test]
+    #[should_panic(expected = "sorted")]
+    fn build_combining_unsorted_panics() {
+        DoubleArray::<u8>::build_combining(&[b"b", b"a"], &[0, 1], |x, _| x);
+    }
+
+    #[test]
+    fn check_points_to_parent() {
+        let da = DoubleArray::<u8>::build(&[b"ab", b"ac"]);
+        for (i, node) in da.nodes.iter().enumerate() {
+            if i == 0 || *node == Node::default() {
+                continue;
+            }
+            let parent_idx = node.check() as usize;
+            assert!(parent_idx < da.nodes.len());
+        }
+    }
+
+    #[test]
+    fn leaf_and_has_leaf_consistency() {
+        let keys: Vec<&[u8]> = vec![b"ab", b"ac", b"b"];
+        let da = DoubleArray::<u8>::build(&keys);
+
+        let mut leaf_count = 0;
+
+        for node in &da.nodes {
+            if node.is_leaf() {
+                leaf_count += 1;
+                // A leaf's parent should have has_leaf set
+                let parent = &da.nodes[node.check() as usize];
+                assert!(parent.has_leaf());
+            }
+        }
+
+        // We have 3 keys, so 3 terminal nodes (leaves)
+        assert_eq!(leaf_count, 3);
+    }
+
+    #[test]
+    fn sibling_chain_no_cycle() {
+        let keys: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+        let da = DoubleArray::<u8>::build(&keys);
+
+        for i in 0..da.siblings.len() {
+            let mut visited = std::collections::HashSet::new();
+            let mut cur = i as u32;
+            while cur != 0 {
+                assert!(visited.insert(cur), "cycle detected in sibling chain");
+                cur = da.siblings[cur as usize];
+            }
+        }
+    }
+
+    #[test]
+    fn terminal_chain_reaches_every_child() {
+        // Romaji prefixes that are keys themselves ("n", "k"...) with label
+        // frequencies that give the terminal's siblings codes in any order.
+        let sets: [&[&[u8]]; 3] = [
+            &[b"n", b"na", b"ni", b"nn", b"nu", b"shi"],
+            &[
+                b"a", b"i", b"k", b"ka", b"ki", b"kk", b"kya", b"n", b"n'", b"nya", b"u",
+            ],
+            &[b"", b"z", b"za", b"zz", b"zzz", b"zzzz"],
+        ];
+        for keys in sets {
+            let da = DoubleArray::<u8>::build(keys);
+            for parent in 0..da.nodes.len() as u32 {
+                if !da.nodes[parent as usize].has_leaf() {
+                    continue;
+                }
+                let mut children: Vec<u32> = (1..da.nodes.len() as u32)
+                    .filter(|&i| {
+                        let n = da.nodes[i as usize];
+                        n != Node::default() && n.check() == parent
+                    })
+                    .collect();
+                let mut chain = vec![da.nodes[parent as usize].base()];
+                let mut next = da.siblings[chain[0] as usize];
+                while next != 0 {
+                    chain.push(next);
+                    next = da.siblings[next as usize];
+                }
+                assert!(da.nodes[chain[0] as usize].is_leaf(), "{keys:?}");
+                chain.sort_unstable();
+                children.sort_unstable();
+                assert_eq!(chain, children, "{keys:?} parent {parent}");
+            }
+            for key in keys {
+                let extends = keys
+                    .iter()
+                    .any(|k| k.len() > key.len() && k.starts_with(key));
+                assert_eq!(da.probe(key).has_children, extends, "{key:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn prefix_free_build_drops_terminals() {
+        let keys: Vec<&[u8]> = vec![
+            b"ba", b"be", b"chi", b"da", b"ka", b"kya", b"kyo", b"shi", b"tsu", b"zu",
+        ];
+        let plain = DoubleArray::<u8>::build(&keys);
+        let da = DoubleArray::<u8>::build_prefix_free(&keys);
+        assert!(da.num_nodes() < plain.num_nodes());
+        assert!(da.num_used_nodes() + keys.len() <= plain.num_used_nodes());
+        assert_eq!(da.num_keys(), keys.len());
+
+        let mut queries: Vec<Vec<u8>> = Vec::new();
+        for key in &keys {
+            for len in 0..=key.len() + 1 {
+                let mut q = key[..len.min(key.len())].to_vec();
+                if len > key.len() {
+                    q.push(b'a');
+                }
+                queries.push(q);
+            }
+        }
+        queries.push(b"x".to_vec());
+        for q in &queries {
+            assert_eq!(da.exact_match(q), plain.exact_match(q), "{q:?}");
+            assert_eq!(da.probe(q), plain.probe(q), "{q:?}");
+            assert_eq!(da.match_or_prefix(q), plain.match_or_prefix(q), "{q:?}");
+            assert_eq!(da.tokenize_step(q), plain.tokenize_step(q), "{q:?}");
+            assert_eq!(da.rank_of(q), plain.rank_of(q), "{q:?}");
+            assert_eq!(
+                da.common_prefix_search(q).collect::<Vec<_>>(),
+                plain.common_prefix_search(q).collect::<Vec<_>>()
+            );
+            let mut found: Vec<_> = da.predictive_search(q).collect();
+            let mut expected: Vec<_> = plain.predictive_search(q).collect();
+            found.sort_by(|a, b| a.key.cmp(&b.key));
+            expected.sort_by(|a, b| a.key.cmp(&b.key));
+            assert_eq!(found, expected, "{q:?}");
+            let mut rev: Vec<_> = da.predictive_search_rev(q).collect();
+            rev.reverse();
+            assert_eq!(rev, da.predictive_search(q).collect::<Vec<_>>());
+        }
+        for rank in 0..keys.len() as u32 {
+            assert_eq!(da.select(rank), plain.select(rank));
+        }
+
+        let restored = DoubleArray::<u8>::from_bytes(&da.as_bytes()).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(restored.exact_match(key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "key at index 1 is a prefix of the next key")]
+    fn prefix_free_build_rejects_prefixes() {
+        DoubleArray::<u8>::build_prefix_free(&[b"a" as &[u8], b"n", b"na"]);
+    }
+
+    #[test]
+    fn romaji_build_is_functionally_identical() {
+        let keys: Vec<&[u8]> = vec![
+            b"a", b"ba", b"be", b"bi", b"bo", b"bu", b"chi", b"da", b"de", b"di", b"do", b"du",
+            b"fu", b"ga", b"ge", b"gi", b"go", b"gu", b"ha", b"he", b"hi", b"ho", b"hu", b"i",
+            b"ja", b"ji", b"jo", b"ju", b"ka", b"ke", b"ki", b"ko", b"ku", b"ma", b"me", b"mi",
+            b"mo", b"mu", b"n", b"na", b"ne", b"ni", b"no", b"nu", b"o", b"pa", b"pe", b"pi",
+            b"po", b"pu", b"ra", b"re", b"ri", b"ro", b"ru", b"sa", b"se", b"sha", b"shi", b"sho",
+            b"shu", b"si", b"so", b"su", b"ta", b"te", b"ti", b"to", b"tsu", b"tu", b"u", b"wa",
+            b"wo", b"ya", b"yo", b"yu", b"za", b"ze", b"zi", b"zo", b"zu",
+        ];
+        let (da, metrics) = DoubleArray::<u8>::build_with_metrics(&keys);
+        // Same layout as before the build scratch buffers were shared.
+        assert_eq!(da.num_nodes(), 184);
+        assert_eq!(metrics.find_base_iterations, 41);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32), "{key:?}");
+            for len in 0..key.len() {
+                let prefix = &key[..len];
+                let expected = keys.iter().position(|k| *k == prefix);
+                assert_eq!(da.exact_match(prefix), expected.map(|i| i as u32));
+            }
+        }
+        let mut found: Vec<Vec<u8>> = da.predictive_search(b"").map(|m| m.key).collect();
+        found.sort();
+        assert_eq!(found, keys);
+
+        // Every sibling chain lists exactly its parent's children, by code.
+        for parent in 0..da.nodes.len() as u32 {
+            let mut children: Vec<u32> = (1..da.nodes.len() as u32)
+                .filter(|&i| {
+                    let n = da.nodes[i as usize];
+                    n != Node::default() && n.check() == parent
+                })
+                .collect();
+            if children.is_empty() {
+                continue;
+            }
+            let base = da.nodes[parent as usize].base();
+            children.sort_unstable_by_key(|&idx| idx ^ base);
+            let mut chain = vec![children[0]];
+            let mut next = da.siblings[children[0] as usize];
+            while next != 0 {
+                chain.push(next);
+                next = da.siblings[next as usize];
+            }
+            assert_eq!(chain, children, "parent {parent}");
+        }
+    }
+
+    #[test]
+    fn sibling_chain_links_same_parent() {
+        let da = DoubleArray::<u8>::build(&[b"ab", b"ac", b"ad"]);
+
+        // Find node for 'a' from root
+        let root_base = da.nodes[0].base();
+        let code_a = da.code_map.get(b'a');
+        let node_a_idx = root_base ^ code_a;
+        assert_eq!(da.nodes[node_a_idx as usize].check(), 0);
+
+        // Count children of node_a via sibling chain
+        let a_base = da.nodes[node_a_idx as usize].base();
+
+        // Find any child of node_a to start the chain
+        let mut first_child = None;
+        for code in 0..da.code_map.alphabet_size() {
+            let idx = a_base ^ code;
+            if (idx as usize) < da.nodes.len() && da.nodes[idx as usize].check() == node_a_idx {
+                first_child = Some(idx);
+                break;
+            }
+        }
+
+        let first = first_child.expect("node_a should have children");
+        let mut count = 1;
+        let mut cur = da.siblings[first as usize];
+        while cur != 0 {
+            assert_eq!(
+                da.nodes[cur as usize].check(),
+                node_a_idx,
+                "sibling should have same parent"
+            );
+            count += 1;
+            cur = da.siblings[cur as usize];
         }
 
         // "ab", "ac", "ad" share prefix "a", so node_a has children:
@@ -418,4 +2709,210 @@ mod tests {
         // Children are: code('b'), code('c'), code('d') = 3 children
         assert_eq!(count, 3);
     }
+
+    /// Checks that `da` holds exactly `expected` through every access path:
+    /// exact match, the node scan, the sorted walk and the sibling chains,
+    /// and that the free slot set lists exactly the unused slots.
+    fn assert_holds<L: Label + std::fmt::Debug>(da: &DoubleArray<L>, expected: &[(Vec<L>, u32)]) {
+        for (key, value) in expected {
+            assert_eq!(da.exact_match(key), Some(*value), "{key:?}");
+        }
+        assert_eq!(da.num_keys(), expected.len());
+        let mut sorted = expected.to_vec();
+        sorted.sort();
+        let keys: Vec<Vec<L>> = sorted.iter().map(|(k, _)| k.clone()).collect();
+        assert_eq!(da.keys_sorted().collect::<Vec<_>>(), keys);
+        let mut found: Vec<(Vec<L>, u32)> = da
+            .predictive_search(&[] as &[L])
+            .map(|m| (m.key, m.value_id))
+            .collect();
+        found.sort();
+        assert_eq!(found, sorted);
+        if let Some(free) = &da.free_slots {
+            let unused: BTreeSet<u32> = (1..da.nodes.len() as u32)
+                .filter(|&i| da.nodes[i as usize] == Node::default())
+                .collect();
+            assert_eq!(*free, unused);
+        }
+    }
+
+    #[test]
+    fn insert_matches_batch_build() {
+        let words: Vec<Vec<u8>> = [
+            "a", "ab", "abc", "abd", "b", "ba", "bad", "badge", "cab", "cabin", "cable", "dog",
+            "dot", "dote", "e", "ear", "earl", "early", "zebra", "zzz", "", "\x00", "\x00\x01",
+        ]
+        .iter()
+        .map(|w| w.as_bytes().to_vec())
+        .collect();
+
+        // Start from every other key and add the rest in a scrambled order.
+        let (initial, rest): (Vec<_>, Vec<_>) =
+            words.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+        let initial: Vec<Vec<u8>> = initial.into_iter().map(|(_, w)| w.clone()).collect();
+        let mut da = DoubleArray::<u8>::build_unsorted(&initial);
+        let mut expected: Vec<(Vec<u8>, u32)> = initial
+            .iter()
+            .map(|w| (w.clone(), da.exact_match(w).unwrap()))
+            .collect();
+
+        let mut rest: Vec<Vec<u8>> = rest.into_iter().map(|(_, w)| w.clone()).collect();
+        let mut state = 7u32;
+        for i in (1..rest.len()).rev() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            rest.swap(i, (state >> 16) as usize % (i + 1));
+        }
+        for (n, word) in rest.iter().enumerate() {
+            let value = 100 + n as u32;
+            assert_eq!(da.insert(word, value), None);
+            expected.push((word.clone(), value));
+            assert_holds(&da, &expected);
+        }
+    }
+
+    #[test]
+    fn insert_grows_empty_trie() {
+        let mut da = DoubleArray::<char>::build(&[] as &[Vec<char>]);
+        let keys = ["日本", "日本語", "", "にほん", "に", "🦀"];
+        let mut expected = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            let key: Vec<char> = key.chars().collect();
+            assert_eq!(da.insert(&key, i as u32), None);
+            expected.push((key, i as u32));
+            assert_holds(&da, &expected);
+        }
+        // Seven labels, plus code 0 for the terminal.
+        assert_eq!(da.code_map.alphabet_size(), 8);
+    }
+
+    #[test]
+    fn insert_overwrites_existing_value() {
+        let mut da = DoubleArray::<u8>::build(&[b"ab" as &[u8], b"abc"]);
+        assert_eq!(da.insert(b"ab", 9), Some(0));
+        assert_eq!(da.insert(b"ab", 10), Some(9));
+        assert_holds(&da, &[(b"ab".to_vec(), 10), (b"abc".to_vec(), 1)]);
+    }
+
+    #[test]
+    fn insert_extends_prefix_free_leaf() {
+        let mut da = DoubleArray::<u8>::build_prefix_free(&[b"ab" as &[u8], b"cd"]);
+        assert!(da.nodes.iter().any(|n| n.is_leaf() && n.check() != 0));
+        assert_eq!(da.insert(b"abc", 2), None);
+        assert_eq!(da.insert(b"c", 3), None);
+        assert_eq!(da.insert(b"ab", 4), Some(0));
+        assert_holds(
+            &da,
+            &[
+                (b"ab".to_vec(), 4),
+                (b"abc".to_vec(), 2),
+                (b"c".to_vec(), 3),
+                (b"cd".to_vec(), 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn insert_resets_caches_and_round_trips() {
+        let mut da = DoubleArray::<u8>::build(&[b"apple" as &[u8], b"banana"]);
+        // Fill the caches before inserting.
+        assert!(!da.might_contain(b"applesauce"));
+        assert_eq!(da.rank_of(b"banana"), Some(1));
+
+        da.insert(b"applesauce", 2);
+        da.insert(b"avocado", 3);
+        assert!(da.might_contain(b"applesauce"));
+        assert_eq!(da.rank_of(b"banana"), Some(3));
+        assert_eq!(da.rank_of(b"avocado"), Some(2));
+
+        let restored = DoubleArray::<u8>::from_bytes(&da.as_bytes()).unwrap();
+        for (key, value) in [(b"apple" as &[u8], 0), (b"applesauce", 2), (b"avocado", 3)] {
+            assert_eq!(restored.exact_match(key), Some(value));
+        }
+        assert_eq!(restored.num_keys(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "31 bits")]
+    fn insert_rejects_wide_value() {
+        DoubleArray::<u8>::build(&[b"a"]).insert(b"b", 1 << 31);
+    }
+
+    #[test]
+    fn remove_prunes_childless_ancestors() {
+        let keys: Vec<&[u8]> = vec![b"", b"car", b"card", b"care", b"cat", b"dog"];
+        let mut da = DoubleArray::<u8>::build(&keys);
+        let used = da.num_used_nodes();
+
+        assert_eq!(da.remove(b"card"), Some(2));
+        assert_eq!(da.remove(b"card"), None);
+        // Only the 'd' node and its terminal go; "car" still has children.
+        assert_eq!(da.num_used_nodes(), used - 2);
+        // "do" is a path but not a key, and "cart" is not in the trie.
+        assert_eq!(da.remove(b"do"), None);
+        assert_eq!(da.remove(b"cart"), None);
+
+        assert_eq!(da.remove(b"dog"), Some(5));
+        assert_eq!(da.remove(b""), Some(0));
+        assert_holds(
+            &da,
+            &[
+                (b"car".to_vec(), 1),
+                (b"care".to_vec(), 3),
+                (b"cat".to_vec(), 4),
+            ],
+        );
+        assert!(da.common_prefix_search(b"dog").next().is_none());
+        assert!(!da.might_contain(b"dog"));
+        assert_eq!(da.rank_of(b"cat"), Some(2));
+
+        for key in [b"car" as &[u8], b"care", b"cat"] {
+            assert!(da.remove(key).is_some());
+        }
+        assert_eq!(da.num_keys(), 0);
+        assert_eq!(da.num_used_nodes(), 1);
+        assert_eq!(da.nodes.len(), 1);
+    }
+
+    #[test]
+    fn remove_from_prefix_free_trie() {
+        let mut da = DoubleArray::<u8>::build_prefix_free(&[b"ab" as &[u8], b"ac", b"b"]);
+        assert_eq!(da.remove(b"ab"), Some(0));
+        assert_eq!(da.remove(b"a"), None);
+        assert_eq!(da.remove(b"b"), Some(2));
+        assert_holds(&da, &[(b"ac".to_vec(), 1)]);
+    }
+
+    #[test]
+    fn insert_reuses_removed_slots() {
+        let words: Vec<String> = (0..200).map(|i| format!("w{i:03}")).collect();
+        let mut da = DoubleArray::<u8>::build(&[] as &[&[u8]]);
+        for (i, word) in words.iter().enumerate() {
+            da.insert(word, i as u32);
+        }
+        let peak = da.nodes.len();
+        // Churn through the same keys repeatedly. Reinserting in another
+        // order gives another layout, but the array must not keep growing.
+        for round in 0..10u32 {
+            for word in &words {
+                assert!(da.remove(word).is_some());
+            }
+            for (i, word) in words.iter().enumerate().rev() {
+                assert_eq!(da.insert(word, round * 1000 + i as u32), None);
+            }
+            assert!(
+                da.nodes.len() <= peak + peak / 4,
+                "{} vs {peak}",
+                da.nodes.len()
+            );
+        }
+        let expected: Vec<(Vec<u8>, u32)> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.as_bytes().to_vec(), 9000 + i as u32))
+            .collect();
+        assert_holds(&da, &expected);
+
+        let restored = DoubleArray::<u8>::from_bytes(&da.as_bytes()).unwrap();
+        assert_holds(&restored, &expected);
+    }
 }
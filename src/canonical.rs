@@ -0,0 +1,84 @@
+use crate::{DoubleArray, Label};
+
+/// A normalized trie that remembers each key's original form.
+///
+/// Built by [`DoubleArray::build_canonical`]. Lookups normalize the query
+/// like [`DoubleArray::exact_match_normalized`], and
+/// [`canonical_key`](Self::canonical_key) returns the key as it was given to
+/// the build, e.g. `"Apple"` for a case-folded query `"apple"`.
+#[derive(Clone, Debug)]
+pub struct CanonicalTrie<L: Label> {
+    trie: DoubleArray<L>,
+    /// The original keys, indexed by their position in the build input.
+    originals: Vec<Vec<L>>,
+}
+
+impl<L: Label> CanonicalTrie<L> {
+    /// Returns the stored original form of the key that `key` normalizes to,
+    /// or `None` if there is no such key. `normalize` must be the function
+    /// the trie was built with.
+    ///
+    /// When several keys normalized to the same form, the first of them in
+    /// the build input is returned.
+    pub fn canonical_key(&self, key: impl AsRef<[L]>, normalize: impl Fn(L) -> L) -> Option<&[L]> {
+        let id = self.trie.exact_match_normalized(key, normalize)?;
+        Some(&self.originals[id as usize])
+    }
+
+    /// Returns the underlying normalized trie. A value_id is the index of
+    /// its key's canonical form in the build input.
+    pub fn trie(&self) -> &DoubleArray<L> {
+        &self.trie
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Returns the key exactly as stored, or `None` if `key` is not a key.
+    ///
+    /// A trie compares labels exactly, so this is a copy of `key` whenever it
+    /// exists. For lookups that normalize the query, use a
+    /// [`CanonicalTrie`], which also keeps the original forms.
+    pub fn canonical_key(&self, key: impl AsRef<[L]>) -> Option<Vec<L>> {
+        let key = key.as_ref();
+        self.exact_match(key).map(|_| key.to_vec())
+    }
+
+    /// Builds a [`CanonicalTrie`]: like [`build_normalized`](Self::build_normalized),
+    /// but also keeping every key in its original form.
+    ///
+    /// # Panics
+    /// Same as [`build_normalized`](Self::build_normalized).
+    pub fn build_canonical(
+        keys: &[impl AsRef<[L]>],
+        normalize: impl Fn(L) -> L,
+    ) -> CanonicalTrie<L> {
+        CanonicalTrie {
+            trie: Self::build_normalized(keys, normalize),
+            originals: keys.iter().map(|k| k.as_ref().to_vec()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_key_of_plain_trie_copies_query() {
+        let da = DoubleArray::<u8>::build(&["Apple", "apple"]);
+        assert_eq!(da.canonical_key(b"Apple"), Some(b"Apple".to_vec()));
+        assert_eq!(da.canonical_key(b"APPLE"), None);
+    }
+
+    #[test]
+    fn canonical_key_under_case_folding() {
+        let lower = |l: u8| l.to_ascii_lowercase();
+        let ct = DoubleArray::<u8>::build_canonical(&["Apple", "banana", "APPLE", "Cherry"], lower);
+        assert_eq!(ct.canonical_key(b"apple", lower), Some(&b"Apple"[..]));
+        assert_eq!(ct.canonical_key(b"APPLE", lower), Some(&b"Apple"[..]));
+        assert_eq!(ct.canonical_key(b"cHERRY", lower), Some(&b"Cherry"[..]));
+        assert_eq!(ct.canonical_key(b"Banana", lower), Some(&b"banana"[..]));
+        assert_eq!(ct.canonical_key(b"appl", lower), None);
+        assert_eq!(ct.trie().exact_match(b"apple"), Some(0));
+    }
+}
@@ -1,5 +1,21 @@
+use std::io::{self, Read};
+
 use crate::Label;
 
+/// How [`CodeMapper`] orders labels that occur equally often.
+///
+/// Labels are always ordered by descending frequency first; this only
+/// decides ties, and with it the exact codes a build assigns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CodeOrder {
+    /// Equal-frequency labels are ordered by label value, ascending.
+    #[default]
+    FrequencyThenLabel,
+    /// Equal-frequency labels are ordered by where they first appear,
+    /// scanning the keys in order.
+    FrequencyThenFirstSeen,
+}
+
 /// Maps labels to dense, frequency-ordered codes.
 ///
 /// Code 0 is reserved for the terminal symbol.
@@ -21,6 +37,42 @@ impl CodeMapper {
     /// dense codes in descending frequency order. Code 0 is reserved
     /// for the terminal symbol.
     pub fn build<L: Label>(keys: &[impl AsRef<[L]>]) -> Self {
+        Self::build_capped(keys, u32::MAX)
+    }
+
+    /// Builds a CodeMapper like [`build`](Self::build), breaking frequency
+    /// ties as `order` says.
+    pub fn build_with_order<L: Label>(keys: &[impl AsRef<[L]>], order: CodeOrder) -> Self {
+        Self::build_inner(keys, u32::MAX, order)
+    }
+
+    /// Builds a CodeMapper with at most `max_alphabet` codes, including the
+    /// terminal symbol.
+    ///
+    /// The `max_alphabet - 2` most frequent labels get codes of their own and
+    /// all remaining labels share one bucket code, which keeps tries over a
+    /// huge, sparse label space small. The mapping is then lossy: keys that
+    /// differ only in bucketed labels become indistinguishable (see
+    /// `DoubleArray::build_checked`), a query may match through a different
+    /// bucketed label, and `reverse` returns the most frequent label of the
+    /// bucket. When the keys use fewer labels, this equals [`build`](Self::build).
+    ///
+    /// # Panics
+    /// If `max_alphabet < 2`.
+    pub fn build_capped<L: Label>(keys: &[impl AsRef<[L]>], max_alphabet: u32) -> Self {
+        Self::build_inner(keys, max_alphabet, CodeOrder::default())
+    }
+
+    fn build_inner<L: Label>(
+        keys: &[impl AsRef<[L]>],
+        max_alphabet: u32,
+        order: CodeOrder,
+    ) -> Self {
+        assert!(
+            max_alphabet >= 2,
+            "max_alphabet must leave room for the terminal and one label code"
+        );
+
         // Find max label value in a single pass to size the frequency array.
         let mut max_label: u32 = 0;
         for key in keys {
@@ -44,13 +96,10 @@ impl CodeMapper {
             .checked_add(1)
             .expect("CodeMapper::build: label space too large for this platform");
 
-        // Direct frequency counting — avoids HashMap overhead.
-        let mut freq = vec![0u64; table_size];
-        for key in keys {
-            for &label in key.as_ref() {
-                freq[<L as Into<u32>>::into(label) as usize] += 1;
-            }
-        }
+        #[cfg(not(feature = "parallel"))]
+        let freq = count_frequencies(keys, table_size);
+        #[cfg(feature = "parallel")]
+        let freq = crate::parallel::count_frequencies(keys, table_size);
 
         // Collect (label, freq) pairs for non-zero entries
         let mut labels: Vec<(u32, u64)> = freq
@@ -60,20 +109,49 @@ impl CodeMapper {
             .map(|(i, &f)| (i as u32, f))
             .collect();
 
-        // Sort by frequency descending, then by label ascending for stability
-        labels.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        // Sort by frequency descending, then break ties deterministically
+        match order {
+            CodeOrder::FrequencyThenLabel => {
+                labels.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            }
+            CodeOrder::FrequencyThenFirstSeen => {
+                // Position of each label's first occurrence across all keys.
+                let mut first_seen = vec![u64::MAX; table_size];
+                let mut pos = 0u64;
+                for key in keys {
+                    for &label in key.as_ref() {
+                        let seen = &mut first_seen[<L as Into<u32>>::into(label) as usize];
+                        *seen = (*seen).min(pos);
+                        pos += 1;
+                    }
+                }
+                labels.sort_unstable_by(|a, b| {
+                    b.1.cmp(&a.1)
+                        .then(first_seen[a.0 as usize].cmp(&first_seen[b.0 as usize]))
+                });
+            }
+        }
+
+        Self::from_ranked_labels(&labels, table_size, max_alphabet)
+    }
+
+    /// Assigns codes to `(label, frequency)` pairs already in code order;
+    /// every label is below `table_size`.
+    fn from_ranked_labels(labels: &[(u32, u64)], table_size: usize, max_alphabet: u32) -> Self {
+        // Labels past the cap all share the last code (the bucket).
+        let alphabet_size = (labels.len() as u32 + 1).min(max_alphabet); // including terminal
+        let bucket = alphabet_size - 1;
 
         let mut table = vec![0u32; table_size];
-        let mut reverse_table = vec![0u32; labels.len() + 1]; // +1 for terminal at index 0
+        let mut reverse_table = vec![0u32; alphabet_size as usize]; // terminal at index 0
 
-        for (i, &(label, _)) in labels.iter().enumerate() {
-            let code = (i as u32) + 1; // code 0 is terminal
+        for (i, &(label, _)) in labels.iter().enumerate().rev() {
+            let code = (i as u32 + 1).min(bucket); // code 0 is terminal
             table[label as usize] = code;
+            // Iterating in reverse leaves the most frequent bucketed label here.
             reverse_table[code as usize] = label;
         }
 
-        let alphabet_size = labels.len() as u32 + 1; // including terminal
-
         Self {
             table,
             reverse_table,
@@ -81,6 +159,63 @@ impl CodeMapper {
         }
     }
 
+    /// Builds a CodeMapper like [`build`](Self::build) from keys streamed
+    /// from `reader`, without holding them in memory.
+    ///
+    /// The stream is a sequence of keys, each a `u32` little-endian length in
+    /// labels followed by that many labels, each as its `u32` value in
+    /// little-endian order; it ends at end of input. Only the label
+    /// frequencies are kept, so a disk-backed key set can be mapped in one
+    /// pass and then read again, e.g. after seeking back to the start, for
+    /// the structural pass of the build.
+    ///
+    /// # Errors
+    /// Returns the reader's errors, [`io::ErrorKind::UnexpectedEof`] if the
+    /// stream ends inside a key, and [`io::ErrorKind::InvalidData`] if a
+    /// value is not a valid `L`.
+    pub fn build_from_reader<L: Label>(mut reader: impl Read) -> io::Result<Self> {
+        let mut freq: Vec<u64> = Vec::new();
+        let mut word = [0u8; 4];
+        loop {
+            // A clean end of input is only allowed between keys. Like
+            // `read_exact`, retry reads that were interrupted.
+            let read = loop {
+                match reader.read(&mut word[..1]) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+            if read == 0 {
+                break;
+            }
+            reader.read_exact(&mut word[1..])?;
+            let len = u32::from_le_bytes(word);
+            for _ in 0..len {
+                reader.read_exact(&mut word)?;
+                let v = u32::from_le_bytes(word);
+                if L::try_from(v).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{v:#x} is not a valid label"),
+                    ));
+                }
+                if v as usize >= freq.len() {
+                    freq.resize(v as usize + 1, 0);
+                }
+                freq[v as usize] += 1;
+            }
+        }
+
+        let mut labels: Vec<(u32, u64)> = freq
+            .iter()
+            .enumerate()
+            .filter(|(_, &f)| f > 0)
+            .map(|(i, &f)| (i as u32, f))
+            .collect();
+        labels.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(Self::from_ranked_labels(&labels, freq.len(), u32::MAX))
+    }
+
     /// Returns the code for a label. Returns 0 if the label is unmapped.
     #[inline]
     pub fn get<L: Label>(&self, label: L) -> u32 {
@@ -105,6 +240,44 @@ impl CodeMapper {
         self.reverse_table[code as usize]
     }
 
+    /// Returns the label (as u32) for a code, or `None` if the code is out of
+    /// range, which only corrupt data can produce.
+    #[inline]
+    pub fn try_reverse(&self, code: u32) -> Option<u32> {
+        self.reverse_table.get(code as usize).copied()
+    }
+
+    /// Returns every `(label, code)` pair with a code, in ascending label
+    /// order.
+    ///
+    /// Labels are given as `u32`. Codes rank labels by descending frequency,
+    /// so sorting the pairs by code shows that ranking; two tries whose maps
+    /// differ here serialize differently. With a bucket from
+    /// [`build_capped`](Self::build_capped), several labels share a code.
+    pub fn assignments(&self) -> Vec<(u32, u32)> {
+        self.table
+            .iter()
+            .enumerate()
+            .filter(|&(_, &code)| code != 0)
+            .map(|(label, &code)| (label as u32, code))
+            .collect()
+    }
+
+    /// Gives `label` the next unused code and returns it, for a key inserted
+    /// after the build. The label must not have a code yet.
+    pub(crate) fn add_label(&mut self, label: u32) -> u32 {
+        debug_assert!(self.table.get(label as usize).is_none_or(|&code| code == 0));
+        let idx = label as usize;
+        if idx >= self.table.len() {
+            self.table.resize(idx + 1, 0);
+        }
+        let code = self.alphabet_size;
+        self.table[idx] = code;
+        self.reverse_table.push(label);
+        self.alphabet_size += 1;
+        code
+    }
+
     /// The number of distinct codes including the terminal symbol.
     #[inline]
     pub fn alphabet_size(&self) -> u32 {
@@ -201,6 +374,20 @@ impl CodeMapper {
     }
 }
 
+/// Counts how often each label value occurs across `keys`.
+///
+/// Direct frequency counting — avoids HashMap overhead. Every label must be
+/// below `table_size`.
+pub(crate) fn count_frequencies<L: Label>(keys: &[impl AsRef<[L]>], table_size: usize) -> Vec<u64> {
+    let mut freq = vec![0u64; table_size];
+    for key in keys {
+        for &label in key.as_ref() {
+            freq[<L as Into<u32>>::into(label) as usize] += 1;
+        }
+    }
+    freq
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,6 +414,166 @@ mod tests {
         assert_ne!(code_b, 0);
     }
 
+    fn write_keys<L: Label>(keys: &[Vec<L>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for key in keys {
+            out.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            for &label in key {
+                out.extend_from_slice(&<L as Into<u32>>::into(label).to_le_bytes());
+            }
+        }
+        out
+    }
+
+    fn read_keys<L: Label>(reader: &mut impl Read) -> Vec<Vec<L>> {
+        let mut keys = Vec::new();
+        let mut word = [0u8; 4];
+        while reader.read_exact(&mut word).is_ok() {
+            let key = (0..u32::from_le_bytes(word))
+                .map(|_| {
+                    reader.read_exact(&mut word).unwrap();
+                    L::try_from(u32::from_le_bytes(word)).ok().unwrap()
+                })
+                .collect();
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn build_from_reader_matches_build() {
+        use std::io::{Cursor, Seek};
+
+        let keys: Vec<Vec<char>> = ["", "か", "かな", "かなし", "な", "漢字", "\u{10FFFF}"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let mut cursor = Cursor::new(write_keys(&keys));
+
+        // Pass 1: frequencies only.
+        let streamed = CodeMapper::build_from_reader::<char>(&mut cursor).unwrap();
+        assert_eq!(streamed.as_bytes(), CodeMapper::build(&keys).as_bytes());
+
+        // Pass 2: the same stream again, for the structure.
+        cursor.rewind().unwrap();
+        let reread: Vec<Vec<char>> = read_keys(&mut cursor);
+        assert_eq!(reread, keys);
+
+        let empty = CodeMapper::build_from_reader::<u8>(Cursor::new(Vec::new())).unwrap();
+        assert_eq!(
+            empty.as_bytes(),
+            CodeMapper::build(&Vec::<Vec<u8>>::new()).as_bytes()
+        );
+    }
+
+    #[test]
+    fn build_from_reader_rejects_bad_streams() {
+        let keys = vec![b"ab".to_vec(), b"c".to_vec()];
+        let bytes = write_keys(&keys);
+        let truncated = CodeMapper::build_from_reader::<u8>(&bytes[..bytes.len() - 2]);
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let wide = write_keys(&[vec!['\u{100}']]);
+        let err = CodeMapper::build_from_reader::<u8>(wide.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn build_from_reader_retries_interrupted_reads() {
+        /// Reads one byte at a time, failing with `Interrupted` before each.
+        struct Flaky<'a> {
+            bytes: &'a [u8],
+            interrupt: bool,
+        }
+        impl Read for Flaky<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupt = !self.interrupt;
+                if self.interrupt {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                let n = buf.len().min(1);
+                self.bytes.read(&mut buf[..n])
+            }
+        }
+
+        let keys = vec![b"ab".to_vec(), b"c".to_vec()];
+        let bytes = write_keys(&keys);
+        let flaky = Flaky {
+            bytes: &bytes,
+            interrupt: false,
+        };
+        let streamed = CodeMapper::build_from_reader::<u8>(flaky).unwrap();
+        assert_eq!(streamed.as_bytes(), CodeMapper::build(&keys).as_bytes());
+    }
+
+    #[test]
+    fn frequency_ties_break_by_order() {
+        // x, c, m and a all appear twice; z once. First appearance: z, x, c, m, a.
+        let keys: Vec<&[u8]> = vec![b"zx", b"xc", b"cm", b"ma", b"a"];
+        let codes = |cm: &CodeMapper| -> Vec<u32> { b"acmxz".iter().map(|&l| cm.get(l)).collect() };
+
+        let by_label = CodeMapper::build(&keys);
+        assert_eq!(codes(&by_label), vec![1, 2, 3, 4, 5]);
+        let explicit = CodeMapper::build_with_order(&keys, CodeOrder::FrequencyThenLabel);
+        assert_eq!(codes(&explicit), codes(&by_label));
+
+        let first_seen = CodeMapper::build_with_order(&keys, CodeOrder::FrequencyThenFirstSeen);
+        assert_eq!(codes(&first_seen), vec![4, 2, 3, 1, 5]);
+    }
+
+    #[test]
+    fn build_capped_buckets_rare_labels() {
+        // a: 4, b: 3, c: 2, d: 1
+        let keys: Vec<&[u8]> = vec![b"aaaa", b"bbb", b"cc", b"d"];
+        let cm = CodeMapper::build_capped(&keys, 3);
+        assert_eq!(cm.alphabet_size(), 3);
+        assert_eq!(cm.get(b'a'), 1);
+        assert_eq!(cm.get(b'b'), 2);
+        assert_eq!(cm.get(b'c'), 2);
+        assert_eq!(cm.get(b'd'), 2);
+        assert_eq!(cm.reverse(2), b'b' as u32);
+
+        // A cap above the label count changes nothing.
+        let uncapped = CodeMapper::build(&keys);
+        let roomy = CodeMapper::build_capped(&keys, 100);
+        assert_eq!(roomy.as_bytes(), uncapped.as_bytes());
+    }
+
+    #[test]
+    fn trie_code_map_matches_build() {
+        let keys: Vec<&[u8]> = vec![b"abc", b"abd", b"b", b"zz"];
+        let da = crate::DoubleArray::<u8>::build(&keys);
+        let cm = da.code_map().clone();
+        assert_eq!(cm.as_bytes(), CodeMapper::build(&keys).as_bytes());
+        for label in 0..=u8::MAX {
+            assert_eq!(cm.get(label) != 0, keys.iter().any(|k| k.contains(&label)));
+        }
+        assert_eq!(cm.alphabet_size(), 6); // terminal + a, b, c, d, z
+    }
+
+    #[test]
+    fn assignments_follow_frequency_order() {
+        // n: 5, a: 4, k: 2, i: 1 and y: 1 (tie broken by label)
+        let keys: Vec<&[u8]> = vec![b"ka", b"kan", b"nan", b"ni", b"nya"];
+        let da = crate::DoubleArray::<u8>::build(&keys);
+        let expected = vec![
+            (u32::from(b'a'), 2),
+            (u32::from(b'i'), 4),
+            (u32::from(b'k'), 3),
+            (u32::from(b'n'), 1),
+            (u32::from(b'y'), 5),
+        ];
+        assert_eq!(da.code_assignments(), expected);
+        assert_eq!(da.code_map().assignments(), expected);
+
+        // Same keys, same map: the assignments are deterministic.
+        let again = crate::DoubleArray::<u8>::build(&keys);
+        assert_eq!(again.code_assignments(), expected);
+
+        let empty: &[&[u8]] = &[];
+        assert!(CodeMapper::build(empty).assignments().is_empty());
+    }
+
     #[test]
     fn code_zero_reserved() {
         let keys: Vec<Vec<u8>> = vec![vec![b'x']];
@@ -1,12 +1,15 @@
+use std::ffi::c_void;
 use std::marker::PhantomData;
 use std::mem;
 
+use crate::serial::Layout;
 use crate::view::TrieView;
 use crate::{
-    CodeMapper, DoubleArray, Label, Node, PrefixMatch, ProbeResult, SearchMatch, TrieError,
+    CodeMapper, DoubleArray, KeyCallback, Label, MatchKind, Node, NodeInfo, NodeRef, PrefixMatch,
+    ProbeResult, SearchMatch, TokenStep, TrieError,
 };
 
-/// A zero-copy reference to a serialized double-array trie (v2 format).
+/// A zero-copy reference to a serialized double-array trie (v2 or v4 format).
 ///
 /// Unlike [`DoubleArray`], this type borrows the `nodes` and `siblings` data
 /// directly from an external byte buffer (e.g. an mmap region), avoiding
@@ -22,87 +25,36 @@ pub struct DoubleArrayRef<'a, L: Label> {
 }
 
 impl<'a, L: Label> DoubleArrayRef<'a, L> {
-    /// Creates a zero-copy `DoubleArrayRef` from a byte slice (v2 format only).
+    /// Creates a zero-copy `DoubleArrayRef` from a byte slice (v2 or v4 format only).
     ///
     /// The byte slice must:
-    /// - Use the LXTR v2 binary format (24-byte header)
+    /// - Use the LXTR v2 or v4 binary format (24-byte header)
     /// - Be aligned to at least 4 bytes (for `Node` and `u32` access)
     ///
     /// # Errors
     ///
     /// Returns [`TrieError::InvalidMagic`] if the magic bytes don't match.
-    /// Returns [`TrieError::InvalidVersion`] if the version is not v2.
+    /// Returns [`TrieError::InvalidVersion`] if the version is not v2 or v4.
     /// Returns [`TrieError::MisalignedData`] if the buffer is not properly aligned.
     /// Returns [`TrieError::TruncatedData`] if the buffer is too short.
     pub fn from_bytes_ref(bytes: &'a [u8]) -> Result<Self, TrieError> {
-        const HEADER_SIZE: usize = crate::serial::HEADER_SIZE;
+        let layout = Layout::parse(bytes)?;
+        let node_count = layout.node_count;
+        let nodes_ptr = bytes[layout.nodes.start..].as_ptr();
+        let siblings_ptr = bytes[layout.siblings.start..].as_ptr();
 
-        if bytes.len() < HEADER_SIZE {
-            return Err(TrieError::TruncatedData);
-        }
-
-        if &bytes[0..4] != crate::serial::MAGIC {
-            return Err(TrieError::InvalidMagic);
-        }
-
-        if bytes[4] != crate::serial::VERSION {
-            return Err(TrieError::InvalidVersion);
-        }
-
-        let nodes_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
-        let siblings_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
-        let code_map_len = u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize;
-
-        let expected_size = HEADER_SIZE
-            .checked_add(nodes_len)
-            .and_then(|s| s.checked_add(siblings_len))
-            .and_then(|s| s.checked_add(code_map_len))
-            .ok_or(TrieError::TruncatedData)?;
-        if bytes.len() < expected_size {
-            return Err(TrieError::TruncatedData);
-        }
-
-        // Validate nodes section
-        if !nodes_len.is_multiple_of(mem::size_of::<Node>()) {
-            return Err(TrieError::TruncatedData);
-        }
-
-        // Validate siblings section
-        if !siblings_len.is_multiple_of(mem::size_of::<u32>()) {
-            return Err(TrieError::TruncatedData);
-        }
-
-        let node_count = nodes_len / mem::size_of::<Node>();
-        let sibling_count = siblings_len / mem::size_of::<u32>();
-
-        // Search logic assumes a root node at index 0
-        if node_count == 0 {
-            return Err(TrieError::TruncatedData);
-        }
-
-        // nodes and siblings must be parallel arrays of equal length
-        if sibling_count != node_count {
-            return Err(TrieError::TruncatedData);
-        }
-
-        let nodes_ptr = bytes[HEADER_SIZE..].as_ptr();
-        let siblings_ptr = bytes[HEADER_SIZE + nodes_len..].as_ptr();
-
-        // Check alignment only when sections are non-empty, because
-        // as_ptr() on an empty sub-slice may return a dangling pointer.
-        if node_count > 0 && !(nodes_ptr as usize).is_multiple_of(mem::align_of::<Node>()) {
+        if !(nodes_ptr as usize).is_multiple_of(mem::align_of::<Node>()) {
             return Err(TrieError::MisalignedData);
         }
 
-        if sibling_count > 0 && !(siblings_ptr as usize).is_multiple_of(mem::align_of::<u32>()) {
+        if !(siblings_ptr as usize).is_multiple_of(mem::align_of::<u32>()) {
             return Err(TrieError::MisalignedData);
         }
 
         // SAFETY:
         // - `Node` is `#[repr(C)]` with two `u32` fields, size 8, align 4, no padding
-        // - We verified alignment and bounds above (skipped when count is 0,
-        //   which is safe because from_raw_parts with count 0 requires only
-        //   a non-null pointer, which sub-slice as_ptr() guarantees)
+        // - We verified alignment and bounds above (Layout::parse guarantees
+        //   node_count > 0, so the pointers are into non-empty sections)
         // - The data is valid for any bit pattern (u32 fields)
         // - The lifetime `'a` ties the slice to the input buffer
         // - We only support little-endian platforms (x86_64, aarch64) where the
@@ -110,13 +62,11 @@ impl<'a, L: Label> DoubleArrayRef<'a, L> {
         let nodes = unsafe { std::slice::from_raw_parts(nodes_ptr as *const Node, node_count) };
 
         let siblings =
-            unsafe { std::slice::from_raw_parts(siblings_ptr as *const u32, sibling_count) };
+            unsafe { std::slice::from_raw_parts(siblings_ptr as *const u32, node_count) };
 
         // code_map is always deserialized to heap
-        let code_map_offset = HEADER_SIZE + nodes_len + siblings_len;
         let (code_map, _) =
-            CodeMapper::from_bytes(&bytes[code_map_offset..code_map_offset + code_map_len])
-                .ok_or(TrieError::TruncatedData)?;
+            CodeMapper::from_bytes(&bytes[layout.code_map]).ok_or(TrieError::TruncatedData)?;
 
         Ok(Self {
             nodes,
@@ -142,33 +92,136 @@ impl<'a, L: Label> DoubleArrayRef<'a, L> {
         self.nodes.len()
     }
 
+    /// Returns the label-to-code map of the trie.
+    pub fn code_map(&self) -> &CodeMapper {
+        &self.code_map
+    }
+
+    /// Returns the number of nodes actually used by the trie (root plus
+    /// non-default nodes), excluding holes.
+    pub fn num_used_nodes(&self) -> usize {
+        self.view().num_used_nodes()
+    }
+
+    /// Returns the number of keys; see [`DoubleArray::num_keys`].
+    pub fn num_keys(&self) -> usize {
+        self.view().num_keys()
+    }
+
     /// Exact match search. Returns the value_id if the key exists.
     #[inline]
-    pub fn exact_match(&self, key: &[L]) -> Option<u32> {
-        self.view().exact_match(key)
+    pub fn exact_match(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match(key.as_ref())
+    }
+
+    /// Returns whether any of `keys` exists; see [`DoubleArray::contains_any`].
+    pub fn contains_any(&self, keys: &[impl AsRef<[L]>]) -> bool {
+        self.view().contains_any(keys)
+    }
+
+    /// Returns the positions in `keys` of the keys that exist.
+    pub fn matching_indices(&self, keys: &[impl AsRef<[L]>]) -> Vec<usize> {
+        self.view().matching_indices(keys)
     }
 
     /// Common prefix search. Returns an iterator over all prefixes of `query`
     /// that exist as keys in the trie.
-    pub fn common_prefix_search<'b>(
+    pub fn common_prefix_search<'b, Q: AsRef<[L]> + ?Sized>(
         &'b self,
-        query: &'b [L],
+        query: &'b Q,
     ) -> impl Iterator<Item = PrefixMatch> + 'b {
-        self.view().common_prefix_search(query)
+        self.view().common_prefix_search(query.as_ref())
+    }
+
+    /// Common prefix search that also yields each match's leaf node index;
+    /// see [`DoubleArray::common_prefix_search_leaves`].
+    pub fn common_prefix_search_leaves<'b, Q: AsRef<[L]> + ?Sized>(
+        &'b self,
+        query: &'b Q,
+    ) -> impl Iterator<Item = (PrefixMatch, u32)> + 'b {
+        let mut iter = self.view().common_prefix_search(query.as_ref());
+        std::iter::from_fn(move || iter.next_with_leaf())
     }
 
     /// Predictive search. Returns an iterator over all keys that start with `prefix`.
-    pub fn predictive_search<'b>(
+    pub fn predictive_search<'b, Q: AsRef<[L]> + ?Sized>(
         &'b self,
-        prefix: &'b [L],
+        prefix: &'b Q,
     ) -> impl Iterator<Item = SearchMatch<L>> + 'b {
-        self.view().predictive_search(prefix)
+        self.view().predictive_search(prefix.as_ref())
+    }
+
+    /// Exact match whose timing does not reveal the matched prefix length;
+    /// see [`DoubleArray::exact_match_constant_time`].
+    pub fn exact_match_constant_time(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match_constant_time(key.as_ref())
+    }
+
+    /// Callback form of common prefix search; see
+    /// [`DoubleArray::for_each_prefix`].
+    pub fn for_each_prefix(&self, query: &[L], callback: KeyCallback<L>, ctx: *mut c_void) {
+        self.view().for_each_prefix(query, callback, ctx);
+    }
+
+    /// Callback form of predictive search; see
+    /// [`DoubleArray::for_each_completion`].
+    pub fn for_each_completion(&self, prefix: &[L], callback: KeyCallback<L>, ctx: *mut c_void) {
+        self.view().for_each_completion(prefix, callback, ctx);
     }
 
     /// Probe a key. Returns whether the key exists and whether it has children.
     #[inline]
-    pub fn probe(&self, key: &[L]) -> ProbeResult {
-        self.view().probe(key)
+    pub fn probe(&self, key: impl AsRef<[L]>) -> ProbeResult {
+        self.view().probe(key.as_ref())
+    }
+
+    /// Key value and whether a longer key follows; see
+    /// [`DoubleArray::match_and_continue`].
+    #[inline]
+    pub fn match_and_continue(&self, key: impl AsRef<[L]>) -> (Option<u32>, bool) {
+        let ProbeResult {
+            value,
+            has_children,
+        } = self.view().probe(key.as_ref());
+        (value, has_children)
+    }
+
+    /// Exact match that distinguishes a prefix-only key from a miss.
+    #[inline]
+    pub fn match_or_prefix(&self, key: impl AsRef<[L]>) -> MatchKind {
+        self.view().match_or_prefix(key.as_ref())
+    }
+
+    /// Returns a handle to the node reached by `prefix`, or `None` if no key
+    /// starts with `prefix`.
+    pub fn find_node(&self, prefix: impl AsRef<[L]>) -> Option<NodeRef> {
+        let prefix = prefix.as_ref();
+        self.view().traverse(prefix).map(|index| NodeRef {
+            index,
+            depth: prefix.len(),
+        })
+    }
+
+    /// Exact match resumed from `node`; see [`DoubleArray::exact_match_from`].
+    pub fn exact_match_from(&self, node: NodeRef, rest: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match_from(node, rest.as_ref())
+    }
+
+    /// Tokenizer step: longest prefix match of `input`, the number of labels
+    /// the trie follows, and whether the dictionary continues past them.
+    pub fn tokenize_step(&self, input: impl AsRef<[L]>) -> TokenStep {
+        self.view().tokenize_step(input.as_ref())
+    }
+
+    /// Shortest prefix match; see [`DoubleArray::shortest_prefix_match`].
+    pub fn shortest_prefix_match(&self, query: impl AsRef<[L]>) -> Option<PrefixMatch> {
+        self.view().shortest_prefix_match(query.as_ref())
+    }
+
+    /// Inspects an arbitrary path. Returns `None` only if the path does not
+    /// exist structurally.
+    pub fn inspect(&self, path: impl AsRef<[L]>) -> Option<NodeInfo> {
+        self.view().inspect(path.as_ref())
     }
 
     /// Converts this zero-copy reference to an owned [`DoubleArray`].
@@ -184,6 +237,7 @@ impl<'a, L: Label> DoubleArrayRef<'a, L> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::lcg;
 
     fn build_u8(keys: &[&[u8]]) -> DoubleArray<u8> {
         DoubleArray::build(keys)
@@ -201,7 +255,7 @@ mod tests {
 
     impl AlignedBuffer {
         fn new(bytes: &[u8]) -> Self {
-            let n = (bytes.len() + 7) / 8;
+            let n = bytes.len().div_ceil(8);
             let mut backing = vec![0u64; n];
             // SAFETY: copying bytes into a u64 buffer; u64 has no invalid bit patterns.
             unsafe {
@@ -301,6 +355,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn inspect_via_ref() {
+        let da = build_u8(&[b"abc"]);
+        let buf = AlignedBuffer::new(&da.as_bytes());
+        let da_ref = DoubleArrayRef::<u8>::from_bytes_ref(buf.as_slice()).unwrap();
+        assert_eq!(da_ref.inspect(b"ab"), da.inspect(b"ab"));
+        assert_eq!(da_ref.inspect(b"abc"), da.inspect(b"abc"));
+        assert_eq!(da_ref.inspect(b"x"), None);
+    }
+
+    #[test]
+    fn batch_via_ref() {
+        let da = build_u8(&[b"a", b"abc"]);
+        let buf = AlignedBuffer::new(&da.as_bytes());
+        let da_ref = DoubleArrayRef::<u8>::from_bytes_ref(buf.as_slice()).unwrap();
+        let batch: Vec<&[u8]> = vec![b"ab", b"abc", b"a"];
+        assert_eq!(da_ref.matching_indices(&batch), vec![1, 2]);
+        assert!(da_ref.contains_any(&batch));
+        assert!(!da_ref.contains_any(&batch[..1]));
+    }
+
+    #[test]
+    fn owned_and_ref_agree_on_fuzzed_queries() {
+        // Both types delegate every search to TrieView; this guards the
+        // delegation itself against drift.
+        let mut next = lcg(0x5EED);
+        let mut random_key = |max_len: u64| -> Vec<u8> {
+            (0..next(max_len + 1))
+                .map(|_| b"abcde"[next(5) as usize])
+                .collect()
+        };
+        let mut keys: Vec<Vec<u8>> = (0..400).map(|_| random_key(6)).collect();
+        keys.sort();
+        keys.dedup();
+        let queries: Vec<Vec<u8>> = (0..300).map(|_| random_key(8)).collect();
+
+        let da = DoubleArray::<u8>::build(&keys);
+        let buf = AlignedBuffer::new(&da.as_bytes());
+        let da_ref = DoubleArrayRef::<u8>::from_bytes_ref(buf.as_slice()).unwrap();
+
+        assert_eq!(da_ref.num_nodes(), da.num_nodes());
+        assert_eq!(da_ref.num_used_nodes(), da.num_used_nodes());
+        assert_eq!(da_ref.num_keys(), da.num_keys());
+        assert_eq!(
+            da_ref.matching_indices(&queries),
+            da.matching_indices(&queries)
+        );
+        for q in &queries {
+            assert_eq!(da_ref.exact_match(q), da.exact_match(q), "{q:?}");
+            assert_eq!(da_ref.probe(q), da.probe(q));
+            assert_eq!(da_ref.match_or_prefix(q), da.match_or_prefix(q));
+            assert_eq!(da_ref.inspect(q), da.inspect(q));
+            assert_eq!(da_ref.tokenize_step(q), da.tokenize_step(q));
+            assert_eq!(da_ref.find_node(q), da.find_node(q));
+            assert_eq!(
+                da_ref.common_prefix_search(q).collect::<Vec<_>>(),
+                da.common_prefix_search(q).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                da_ref.common_prefix_search_leaves(q).collect::<Vec<_>>(),
+                da.common_prefix_search_leaves(q).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                da_ref.predictive_search(q).collect::<Vec<_>>(),
+                da.predictive_search(q).collect::<Vec<_>>()
+            );
+            if let Some(node) = da.find_node(&q[..q.len() / 2]) {
+                let rest = &q[q.len() / 2..];
+                assert_eq!(
+                    da_ref.exact_match_from(node, rest),
+                    da.exact_match_from(node, rest)
+                );
+            }
+        }
+    }
+
     #[test]
     fn to_owned_works() {
         let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc"];
@@ -323,7 +453,7 @@ mod tests {
         // Allocate a buffer with extra room, using Vec<u64> for guaranteed
         // 8-byte base alignment. We write into this buffer directly so the
         // offset calculation matches the actual slice being tested.
-        let mut backing = vec![0u64; (bytes.len() + 16 + 7) / 8];
+        let mut backing = vec![0u64; (bytes.len() + 16).div_ceil(8)];
         let buf = unsafe {
             std::slice::from_raw_parts_mut(backing.as_mut_ptr() as *mut u8, backing.len() * 8)
         };
@@ -333,7 +463,7 @@ mod tests {
         // Since 24 % 4 == 0, we need (base + offset) % 4 != 0.
         // At least 3 of offsets 0..4 satisfy this.
         let offset = (0..4)
-            .find(|&o| (base + o + 24) % 4 != 0)
+            .find(|&o| !(base + o + 24).is_multiple_of(4))
             .expect("at least one offset should be misaligned");
 
         buf[offset..offset + bytes.len()].copy_from_slice(&bytes);
@@ -383,5 +513,7 @@ mod tests {
         let buf = AlignedBuffer::new(&da.as_bytes());
         let da_ref = DoubleArrayRef::<u8>::from_bytes_ref(buf.as_slice()).unwrap();
         assert_eq!(da_ref.num_nodes(), da.num_nodes());
+        assert_eq!(da_ref.num_used_nodes(), da.num_used_nodes());
+        assert_eq!(da_ref.code_map().as_bytes(), da.code_map().as_bytes());
     }
 }
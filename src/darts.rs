@@ -0,0 +1,251 @@
+//! Export to the darts-clone unit array format.
+//!
+//! darts-clone stores a trie as an array of 32-bit units:
+//! - bits 0..8: the unit's label (the byte that leads to it)
+//! - bit 8: `has_leaf`, set if a key ends at the unit
+//! - bits 9..32: the XOR offset to the children, relative to the unit's own
+//!   index. Offsets below 2^21 are stored as `offset << 10`; larger ones
+//!   (multiples of 256, below 2^29) as `(offset << 2) | 1 << 9`.
+//! - a value unit (`1 << 31 | value`) sits at `id ^ offset` of a unit with
+//!   `has_leaf`, i.e. at the child reached by label 0.
+//!
+//! The child of unit `id` for byte `c` is `id ^ offset(id) ^ c`, and is valid
+//! if its label equals `c`. Since children are found through their own label
+//! rather than a parent index, every unit needs an offset no other unit uses.
+
+use crate::DoubleArray;
+
+const VALUE_FLAG: u32 = 1 << 31;
+const HAS_LEAF: u32 = 1 << 8;
+const EXTENDED_OFFSET: u32 = 1 << 9;
+/// Units are allocated in blocks of 256, so that `offset ^ c` stays in the
+/// array for every byte `c`.
+const BLOCK: usize = 256;
+
+/// Encodes a relative offset, or returns `None` if darts-clone cannot store it.
+fn encode_offset(rel: u32) -> Option<u32> {
+    if rel < 1 << 21 {
+        Some(rel << 10)
+    } else if rel < 1 << 29 && rel & 0xFF == 0 {
+        Some((rel << 2) | EXTENDED_OFFSET)
+    } else {
+        None
+    }
+}
+
+/// Places units, choosing for each node the lowest offset that fits.
+#[derive(Default)]
+struct DartsBuilder {
+    units: Vec<u32>,
+    /// Whether a unit slot is taken.
+    used: Vec<bool>,
+    /// Whether an offset is already some unit's children block.
+    offset_used: Vec<bool>,
+    /// No slot below this one is free.
+    first_free: usize,
+}
+
+impl DartsBuilder {
+    fn ensure(&mut self, index: usize) {
+        if index >= self.units.len() {
+            let len = (index / BLOCK + 1) * BLOCK;
+            self.units.resize(len, 0);
+            self.used.resize(len, false);
+            self.offset_used.resize(len, false);
+        }
+    }
+
+    /// Finds an offset for the children of unit `id`; `labels` is non-empty.
+    fn find_offset(&mut self, id: u32, labels: &[u8]) -> Option<u32> {
+        while self.first_free < self.used.len() && self.used[self.first_free] {
+            self.first_free += 1;
+        }
+        let mut slot = self.first_free;
+        loop {
+            let offset = slot as u32 ^ u32::from(labels[0]);
+            self.ensure(offset as usize | 0xFF);
+            let fits = !self.used[slot]
+                && !self.offset_used[offset as usize]
+                && encode_offset(id ^ offset).is_some()
+                && labels
+                    .iter()
+                    .all(|&c| !self.used[(offset ^ u32::from(c)) as usize]);
+            if fits {
+                return Some(offset);
+            }
+            slot += 1;
+            if slot >= 1 << 29 {
+                return None;
+            }
+        }
+    }
+}
+
+impl DoubleArray<u8> {
+    /// Exports the trie as a darts-clone unit array, for tools that read the
+    /// Darts / darts-clone format.
+    ///
+    /// The result is the raw array of little-endian 32-bit units with no
+    /// header, the layout darts-clone's `open()` and `set_array()` expect;
+    /// each key's value is its value_id. Nodes are re-placed, since Darts
+    /// indexes children by raw bytes rather than remapped codes. The export is
+    /// one way: there is no import.
+    ///
+    /// Returns `None` if a key contains a 0 byte, which darts-clone reserves
+    /// as the key terminator, or if the trie is too large for its offsets.
+    pub fn as_darts_bytes(&self) -> Option<Vec<u8>> {
+        let view = self.view();
+        let mut builder = DartsBuilder::default();
+        builder.ensure(0);
+        builder.used[0] = true;
+
+        // (node in this trie, its darts unit, the label leading to it)
+        let mut stack: Vec<(u32, u32, u8)> = vec![(0, 0, 0)];
+        let mut labels: Vec<u8> = Vec::new();
+        let mut children: Vec<u32> = Vec::new();
+        while let Some((node_idx, id, label)) = stack.pop() {
+            let base = self.nodes[node_idx as usize].base();
+            labels.clear();
+            children.clear();
+            let mut value = None;
+            // A prefix-free trie's leaf key has no terminal child; darts-clone
+            // needs one, so export it as if it had.
+            let node = self.nodes[node_idx as usize];
+            if node.is_leaf() {
+                value = Some(node.value_id());
+                labels.push(0);
+            }
+            let mut child = view.first_child(node_idx);
+            while let Some(c) = child {
+                let code = base ^ c;
+                if code == 0 {
+                    value = Some(self.nodes[c as usize].value_id());
+                    labels.push(0);
+                } else {
+                    let byte = view.label_of(code)?;
+                    if byte == 0 {
+                        return None;
+                    }
+                    labels.push(byte);
+                    children.push(c);
+                }
+                child = match self.siblings[c as usize] {
+                    0 => None,
+                    next => Some(next),
+                };
+            }
+
+            let mut unit = u32::from(label);
+            if !labels.is_empty() {
+                let offset = builder.find_offset(id, &labels)?;
+                builder.offset_used[offset as usize] = true;
+                for &c in &labels {
+                    builder.used[(offset ^ u32::from(c)) as usize] = true;
+                }
+                unit |= encode_offset(id ^ offset)?;
+                if let Some(value) = value {
+                    unit |= HAS_LEAF;
+                    builder.units[offset as usize] = VALUE_FLAG | value;
+                }
+                let non_terminal = labels.iter().filter(|&&c| c != 0);
+                for (&c, &child) in non_terminal.zip(&children) {
+                    stack.push((child, offset ^ u32::from(c), c));
+                }
+            }
+            builder.units[id as usize] = unit;
+        }
+
+        Some(builder.units.iter().flat_map(|u| u.to_le_bytes()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    /// darts-clone's `exactMatchSearch`, written from its unit layout.
+    fn darts_exact_match(units: &[u32], key: &[u8]) -> Option<u32> {
+        let offset = |unit: u32| (unit >> 10) << ((unit & EXTENDED_OFFSET) >> 6);
+        let label = |unit: u32| unit & (VALUE_FLAG | 0xFF);
+        let mut id = 0u32;
+        let mut unit = units[0];
+        id ^= offset(unit);
+        for &c in key {
+            id ^= u32::from(c);
+            unit = units[id as usize];
+            if label(unit) != u32::from(c) {
+                return None;
+            }
+            id ^= offset(unit);
+        }
+        if unit & HAS_LEAF == 0 {
+            return None;
+        }
+        Some(units[id as usize] & !VALUE_FLAG)
+    }
+
+    #[test]
+    fn exported_units_follow_darts_layout() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bcd"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let units = units(&da.as_darts_bytes().unwrap());
+        assert_eq!(units.len() % BLOCK, 0);
+
+        // The root has label 0 and no value; "a" is a key and has children.
+        assert_eq!(units[0] & (VALUE_FLAG | 0xFF | HAS_LEAF), 0);
+        let a = (units[0] >> 10) ^ u32::from(b'a');
+        assert_eq!(units[a as usize] & 0xFF, u32::from(b'a'));
+        assert_ne!(units[a as usize] & HAS_LEAF, 0);
+        let value_unit = units[(a ^ (units[a as usize] >> 10)) as usize];
+        assert_eq!(value_unit, VALUE_FLAG);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(darts_exact_match(&units, key), Some(i as u32), "{key:?}");
+        }
+        for miss in [&b""[..], b"c", b"bc", b"abcd", b"ba"] {
+            assert_eq!(darts_exact_match(&units, miss), None, "{miss:?}");
+        }
+    }
+
+    #[test]
+    fn exported_units_match_larger_trie() {
+        let mut keys: Vec<String> = (0..2000).map(|i| format!("w{}", i * 37 % 5003)).collect();
+        keys.push(String::new());
+        keys.sort();
+        keys.dedup();
+        let da = DoubleArray::<u8>::build(&keys);
+        let units = units(&da.as_darts_bytes().unwrap());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(darts_exact_match(&units, key.as_bytes()), Some(i as u32));
+        }
+        assert_eq!(darts_exact_match(&units, b"w"), None);
+    }
+
+    #[test]
+    fn prefix_free_trie_exports_terminals() {
+        let keys: Vec<&[u8]> = vec![b"ab", b"ac", b"b", b"cde"];
+        let da = DoubleArray::<u8>::build_prefix_free(&keys);
+        let plain = DoubleArray::<u8>::build(&keys);
+        assert_eq!(da.as_darts_bytes(), plain.as_darts_bytes());
+        let units = units(&da.as_darts_bytes().unwrap());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(darts_exact_match(&units, key), Some(i as u32), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn zero_byte_keys_cannot_be_exported() {
+        let da = DoubleArray::<u8>::build(&[b"a\0b" as &[u8]]);
+        assert_eq!(da.as_darts_bytes(), None);
+        let empty: &[&[u8]] = &[];
+        let units = units(&DoubleArray::<u8>::build(empty).as_darts_bytes().unwrap());
+        assert_eq!(darts_exact_match(&units, b""), None);
+    }
+}
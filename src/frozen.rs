@@ -0,0 +1,89 @@
+use std::ops::Deref;
+
+use crate::{DoubleArray, Label, TrieError};
+
+/// A read-only [`DoubleArray`].
+///
+/// Dereferences to `&DoubleArray<L>`, so every query method is available,
+/// but no `&mut self` method is reachable: a frozen trie can be shared
+/// (e.g. behind an `Arc` across threads) without risk of an accidental
+/// mutation. Obtain one with [`DoubleArray::freeze`] or
+/// [`FrozenDoubleArray::from_bytes`], and get a mutable trie back with
+/// [`thaw`](Self::thaw).
+#[derive(Clone, Debug)]
+pub struct FrozenDoubleArray<L: Label> {
+    trie: DoubleArray<L>,
+}
+
+impl<L: Label> FrozenDoubleArray<L> {
+    /// Deserializes a trie straight into its frozen form; see
+    /// [`DoubleArray::from_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TrieError> {
+        DoubleArray::from_bytes(bytes).map(DoubleArray::freeze)
+    }
+
+    /// Returns the trie as a mutable [`DoubleArray`], without copying.
+    pub fn thaw(self) -> DoubleArray<L> {
+        self.trie
+    }
+}
+
+impl<L: Label> Deref for FrozenDoubleArray<L> {
+    type Target = DoubleArray<L>;
+
+    fn deref(&self) -> &DoubleArray<L> {
+        &self.trie
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Makes the trie read-only; see [`FrozenDoubleArray`].
+    pub fn freeze(self) -> FrozenDoubleArray<L> {
+        FrozenDoubleArray { trie: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MatchKind, ProbeResult};
+
+    #[test]
+    fn frozen_exposes_queries() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bc"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let bytes = da.as_bytes();
+        let frozen = da.freeze();
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(frozen.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(frozen.common_prefix_search(b"abcd").count(), 3);
+        assert_eq!(frozen.predictive_search(b"b").count(), 2);
+        assert_eq!(
+            frozen.probe(b"ab"),
+            ProbeResult {
+                value: Some(1),
+                has_children: true
+            }
+        );
+        assert_eq!(frozen.match_or_prefix(b"c"), MatchKind::Absent);
+        assert_eq!(frozen.num_keys(), keys.len());
+        assert_eq!(frozen.as_bytes(), bytes);
+
+        let loaded = FrozenDoubleArray::<u8>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.exact_match(b"bc"), Some(4));
+
+        fn assert_shareable<T: Send + Sync>(_: &T) {}
+        assert_shareable(&loaded);
+    }
+
+    #[test]
+    fn thaw_returns_mutable_trie() {
+        let frozen = DoubleArray::<u8>::build(&[b"a", b"b"]).freeze();
+        let mut da = frozen.thaw();
+        da.reserve(100);
+        assert!(da.capacity() >= da.num_nodes() + 100);
+        assert_eq!(da.exact_match(b"b"), Some(1));
+    }
+}
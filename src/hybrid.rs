@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{CodeMapper, DoubleArrayMap, Label};
+
+/// A map that keeps keys over a common alphabet in a trie and the rest in a
+/// `HashMap`.
+///
+/// The trie's alphabet is the set of labels used by the common keys. A key
+/// with any label outside it cannot be stored in the trie, so it lives in
+/// the fallback map; [`get`](Self::get) picks the store from the key's
+/// labels, so each lookup touches only one of them.
+#[derive(Clone, Debug)]
+pub struct HybridTrie<L: Label, V> {
+    trie: DoubleArrayMap<L, V>,
+    /// Keys with at least one label the trie's code map does not know.
+    fallback: HashMap<Vec<L>, V>,
+}
+
+impl<L: Label + Hash, V> HybridTrie<L, V> {
+    /// Builds a hybrid map whose trie alphabet is the labels of `common`.
+    ///
+    /// Keys of `rare` that only use those labels go into the trie as well;
+    /// the others go into the fallback map. A key in both maps keeps its
+    /// value from `common`.
+    ///
+    /// # Panics
+    /// If the trie would get more than 2^31 keys.
+    pub fn build(common: HashMap<Vec<L>, V>, rare: HashMap<Vec<L>, V>) -> Self {
+        let common_keys: Vec<&Vec<L>> = common.keys().collect();
+        let alphabet = CodeMapper::build(&common_keys);
+        let mut in_trie = common;
+        let mut fallback = HashMap::new();
+        for (key, value) in rare {
+            if is_mapped(&alphabet, &key) {
+                in_trie.entry(key).or_insert(value);
+            } else {
+                fallback.insert(key, value);
+            }
+        }
+        Self {
+            trie: DoubleArrayMap::build_from_hashmap(in_trie),
+            fallback,
+        }
+    }
+
+    /// Returns the value stored for `key`, from the trie if every label of
+    /// `key` is in its alphabet and from the fallback map otherwise.
+    pub fn get(&self, key: impl AsRef<[L]>) -> Option<&V> {
+        let key = key.as_ref();
+        if is_mapped(self.trie.trie().code_map(), key) {
+            self.trie.get(key)
+        } else {
+            self.fallback.get(key)
+        }
+    }
+
+    /// Returns the trie part.
+    pub fn trie(&self) -> &DoubleArrayMap<L, V> {
+        &self.trie
+    }
+
+    /// Returns the fallback map of keys outside the trie's alphabet.
+    pub fn fallback(&self) -> &HashMap<Vec<L>, V> {
+        &self.fallback
+    }
+
+    /// Returns the number of keys in both parts.
+    pub fn len(&self) -> usize {
+        self.trie.len() + self.fallback.len()
+    }
+
+    /// Returns whether the map has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Whether every label of `key` has a code, i.e. the key can be in the trie.
+fn is_mapped<L: Label>(code_map: &CodeMapper, key: &[L]) -> bool {
+    key.iter().all(|&label| code_map.get(label) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn out_of_alphabet_keys_use_the_fallback() {
+        let common: HashMap<Vec<char>, u32> = [("かな", 1), ("かんじ", 2), ("じ", 3)]
+            .into_iter()
+            .map(|(k, v)| (chars(k), v))
+            .collect();
+        // "かじ" only uses common labels; "カナ" and "かなA" do not.
+        let rare: HashMap<Vec<char>, u32> = [("カナ", 10), ("かなA", 11), ("かじ", 12), ("じ", 13)]
+            .into_iter()
+            .map(|(k, v)| (chars(k), v))
+            .collect();
+        let hybrid = HybridTrie::build(common, rare);
+
+        assert_eq!(hybrid.len(), 6);
+        assert_eq!(hybrid.fallback().len(), 2);
+        assert_eq!(hybrid.trie().len(), 4);
+
+        assert_eq!(hybrid.get(chars("かな")), Some(&1));
+        assert_eq!(hybrid.get(chars("かじ")), Some(&12));
+        assert_eq!(hybrid.get(chars("じ")), Some(&3));
+        assert!(hybrid.trie().trie().exact_match(chars("かじ")).is_some());
+
+        assert_eq!(hybrid.get(chars("カナ")), Some(&10));
+        assert_eq!(hybrid.get(chars("かなA")), Some(&11));
+        assert!(hybrid.fallback().contains_key(&chars("カナ")));
+
+        assert_eq!(hybrid.get(chars("かん")), None);
+        assert_eq!(hybrid.get(chars("カ")), None);
+    }
+
+    #[test]
+    fn empty_parts() {
+        let hybrid = HybridTrie::<u8, ()>::build(HashMap::new(), HashMap::new());
+        assert!(hybrid.is_empty());
+        assert_eq!(hybrid.get(b"a"), None);
+        assert_eq!(hybrid.get(b""), None);
+
+        let rare: HashMap<Vec<u8>, ()> = [(b"x".to_vec(), ())].into_iter().collect();
+        let hybrid = HybridTrie::build(HashMap::new(), rare);
+        assert_eq!(hybrid.fallback().len(), 1);
+        assert_eq!(hybrid.get(b"x"), Some(&()));
+    }
+}
@@ -2,7 +2,10 @@
 ///
 /// Labels must be copyable, orderable, and convertible to/from `u32`.
 /// `ALPHABET_SIZE` defines the theoretical maximum number of distinct labels.
-pub trait Label: Copy + Ord + Into<u32> + TryFrom<u32> {
+///
+/// With the `parallel` feature, builds read labels from worker threads, so
+/// labels must then also be `Sync`. Without it there is no such bound.
+pub trait Label: Copy + Ord + Into<u32> + TryFrom<u32> + sealed::SyncIfParallel {
     /// The theoretical maximum number of distinct label values.
     const ALPHABET_SIZE: u32;
 }
@@ -11,10 +14,28 @@ impl Label for u8 {
     const ALPHABET_SIZE: u32 = 256;
 }
 
+impl Label for u16 {
+    const ALPHABET_SIZE: u32 = 0x1_0000;
+}
+
 impl Label for char {
     const ALPHABET_SIZE: u32 = 0x11_0000;
 }
 
+mod sealed {
+    /// `Sync` with the `parallel` feature, implemented for every type
+    /// without it.
+    #[cfg(feature = "parallel")]
+    pub trait SyncIfParallel: Sync {}
+    #[cfg(feature = "parallel")]
+    impl<T: Sync> SyncIfParallel for T {}
+
+    #[cfg(not(feature = "parallel"))]
+    pub trait SyncIfParallel {}
+    #[cfg(not(feature = "parallel"))]
+    impl<T> SyncIfParallel for T {}
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -24,6 +45,11 @@ mod tests {
         assert_eq!(u8::ALPHABET_SIZE, 256);
     }
 
+    #[test]
+    fn u16_alphabet_size() {
+        assert_eq!(u16::ALPHABET_SIZE, 0x1_0000);
+    }
+
     #[test]
     fn char_alphabet_size() {
         assert_eq!(char::ALPHABET_SIZE, 0x11_0000);
@@ -4,7 +4,8 @@
 //! double-array structure. It supports exact match, common prefix search, predictive
 //! search, and probe operations over sequences of [`Label`] elements (`u8` or `char`).
 //!
-//! For zero-copy access to memory-mapped files, see [`DoubleArrayRef`].
+//! For zero-copy access to memory-mapped files, see [`DoubleArrayRef`]; to search
+//! an owned `Vec<u8>` in place, see [`OwnedAlignedTrie`].
 //!
 //! # Quick start
 //!
@@ -45,22 +46,64 @@
 #[cfg(not(target_endian = "little"))]
 compile_error!("lexime-trie requires a little-endian platform");
 
+// Node indices are u32 and are widened with `as usize` throughout; this is
+// lossless only when usize is at least 32 bits.
+#[cfg(target_pointer_width = "16")]
+compile_error!("lexime-trie requires a 32-bit or wider usize");
+
+mod aligned;
 mod build;
+mod canonical;
 mod code_map;
 mod da_ref;
+mod darts;
+mod frozen;
+mod hybrid;
 mod label;
+mod map;
+mod minimized;
+mod multi;
+mod nested;
 mod node;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod partitioned;
+mod rank;
+mod routes;
 mod search;
+mod segmented;
 mod serial;
+mod stream;
+#[cfg(test)]
+mod test_util;
 mod view;
 
+use std::collections::BTreeSet;
 use std::marker::PhantomData;
+use std::sync::OnceLock;
 
-pub use code_map::CodeMapper;
+pub use aligned::OwnedAlignedTrie;
+pub use build::{BuildMetrics, BuildPlan, LabelRecommendation};
+pub use canonical::CanonicalTrie;
+pub use code_map::{CodeMapper, CodeOrder};
 pub use da_ref::DoubleArrayRef;
+pub use frozen::FrozenDoubleArray;
+pub use hybrid::HybridTrie;
 pub use label::Label;
+pub use map::DoubleArrayMap;
+pub use minimized::MinimizedTrie;
+pub use multi::MultiValueTrie;
+pub use nested::NestedTrie;
 pub use node::Node;
-pub use search::{PrefixMatch, ProbeResult, SearchMatch};
+pub use partitioned::PartitionedTrie;
+pub use routes::PREFIX_RULE;
+pub use search::{
+    KeyCallback, MatchKind, NodeInfo, NodeRef, PartialMatch, PrefixMatch, ProbeResult, SearchMatch,
+    TokenStep,
+};
+pub use segmented::{SegmentId, SegmentedTrie};
+pub use serial::SectionSizes;
+pub use stream::{StreamMatch, StreamMatcher};
 
 /// Errors that can occur during trie operations.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -88,6 +131,61 @@ impl std::fmt::Display for TrieError {
 
 impl std::error::Error for TrieError {}
 
+/// Errors that can occur while building a trie.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// Laying out the trie would need more node slots than the budget allows.
+    ExceededNodeBudget,
+    /// A node would have more children than the fan-out limit allows.
+    ExceededFanOut {
+        /// Number of children of the offending node, its terminal included.
+        fan_out: usize,
+    },
+    /// A node's base would not fit in the 31 bits next to its flag.
+    NodeIndexOverflow,
+    /// The key at `index` sorts before the key at `index - 1`.
+    UnsortedKeys {
+        /// Index of the first key out of order.
+        index: usize,
+    },
+    /// The key at `index` equals the key at `index - 1`.
+    DuplicateKey {
+        /// Index of the second of the two equal keys.
+        index: usize,
+    },
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::ExceededNodeBudget => write!(f, "trie exceeds the node budget"),
+            BuildError::ExceededFanOut { fan_out } => {
+                write!(f, "a node has {fan_out} children, over the fan-out limit")
+            }
+            BuildError::NodeIndexOverflow => write!(f, "node index exceeds 31 bits"),
+            BuildError::UnsortedKeys { index } => {
+                write!(f, "key at index {index} sorts before the previous key")
+            }
+            BuildError::DuplicateKey { index } => write!(f, "duplicate key at index {index}"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Maximum number of nodes in a trie.
+///
+/// Node indices are stored in the low 31 bits of `base` and `check` (the MSB
+/// holds a flag), so every index is below `2^31`. Converting an index to
+/// `usize` is lossless on 32- and 64-bit targets, and comparisons against
+/// `nodes.len()` never truncate. Building a trie that would need more nodes
+/// panics, or fails with [`BuildError::ExceededNodeBudget`] from
+/// [`DoubleArray::build_bounded`]. Serialization further limits a trie to `u32::MAX / 8` nodes, since
+/// the LXTR v2 header stores section sizes in bytes as `u32`.
+pub const MAX_NODES: usize = 1 << 31;
+
+const _: () = assert!(MAX_NODES - 1 <= node::MASK as usize);
+
 /// A double-array trie supporting exact match, common prefix search,
 /// predictive search, and probe operations.
 #[derive(Clone, Debug)]
@@ -95,6 +193,16 @@ pub struct DoubleArray<L: Label> {
     pub(crate) nodes: Vec<Node>,
     pub(crate) siblings: Vec<u32>,
     pub(crate) code_map: CodeMapper,
+    /// Shortest and longest key length, computed on first use by
+    /// [`might_contain`](Self::might_contain).
+    pub(crate) length_range: OnceLock<Option<(usize, usize)>>,
+    /// Number of keys in each node's subtree, computed on first use by
+    /// [`rank_of`](Self::rank_of) and the other rank queries.
+    pub(crate) subtree_keys: OnceLock<Vec<u32>>,
+    /// Unused node slots, collected on the first
+    /// [`insert`](Self::insert) or [`remove`](Self::remove) and kept up to
+    /// date by both.
+    pub(crate) free_slots: Option<BTreeSet<u32>>,
     _phantom: PhantomData<L>,
 }
 
@@ -105,12 +213,79 @@ impl<L: Label> DoubleArray<L> {
             nodes,
             siblings,
             code_map,
+            length_range: OnceLock::new(),
+            subtree_keys: OnceLock::new(),
+            free_slots: None,
             _phantom: PhantomData,
         }
     }
 
     /// Returns the number of nodes in the trie.
+    ///
+    /// This is the length of the node array, including unused slots (holes)
+    /// left between placed children. See [`num_used_nodes`](Self::num_used_nodes).
     pub fn num_nodes(&self) -> usize {
         self.nodes.len()
     }
+
+    /// Returns the label-to-code map the trie was built with.
+    ///
+    /// Clone it to encode queries ahead of time with [`CodeMapper::get`].
+    pub fn code_map(&self) -> &CodeMapper {
+        &self.code_map
+    }
+
+    /// Returns the `(label, code)` pairs of the trie's code map, in
+    /// ascending label order; see [`CodeMapper::assignments`].
+    pub fn code_assignments(&self) -> Vec<(u32, u32)> {
+        self.code_map.assignments()
+    }
+
+    /// Returns the number of nodes actually used by the trie: the root plus
+    /// every non-default node. Always `<= num_nodes()`.
+    ///
+    /// This is a linear scan over the node array.
+    pub fn num_used_nodes(&self) -> usize {
+        self.view().num_used_nodes()
+    }
+
+    /// Load factor below which [`is_sparse`](Self::is_sparse) holds.
+    pub const SPARSE_LOAD_FACTOR: f64 = 0.3;
+
+    /// Returns the fraction of node slots in use,
+    /// `num_used_nodes() / num_nodes()`, in `(0, 1]`.
+    ///
+    /// This is a linear scan over the node array.
+    pub fn load_factor(&self) -> f64 {
+        self.num_used_nodes() as f64 / self.num_nodes() as f64
+    }
+
+    /// Returns whether fewer than [`SPARSE_LOAD_FACTOR`](Self::SPARSE_LOAD_FACTOR)
+    /// of the node slots are in use, so most of the trie's memory and
+    /// serialized size is holes.
+    ///
+    /// Tries from [`build`](Self::build) stay well above it, since codes
+    /// follow the keys' own label frequencies. A sparse trie usually comes
+    /// from a code map made for other keys, e.g. a small trie passed the map
+    /// of a large dictionary in [`build_checked`](Self::build_checked);
+    /// rebuilding it with its own code map packs it.
+    ///
+    /// This is a linear scan over the node array.
+    pub fn is_sparse(&self) -> bool {
+        self.load_factor() < Self::SPARSE_LOAD_FACTOR
+    }
+
+    /// Returns the number of keys in the trie.
+    ///
+    /// For a trie from [`build`](Self::build) (or any builder that numbers
+    /// keys by position), the value_ids are exactly `0..num_keys()`, each
+    /// used once. The trie is then a minimal perfect hash of its keys:
+    /// `exact_match` maps every key to a distinct index into an array of
+    /// `num_keys()` entries, and [`as_bytes`](Self::as_bytes) round trips
+    /// keep the numbering.
+    ///
+    /// This is a linear scan over the node array.
+    pub fn num_keys(&self) -> usize {
+        self.view().num_keys()
+    }
 }
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use crate::{DoubleArray, Label};
+
+/// A trie that maps each key to a value of any type.
+///
+/// Built by [`DoubleArrayMap::build_from_hashmap`]. Keys get value_ids in
+/// sorted key order, and the values are stored alongside the trie, indexed
+/// by value_id.
+#[derive(Clone, Debug)]
+pub struct DoubleArrayMap<L: Label, V> {
+    trie: DoubleArray<L>,
+    /// `values[id]` is the value of the key whose value_id is `id`.
+    values: Vec<V>,
+}
+
+impl<L: Label, V> DoubleArrayMap<L, V> {
+    /// Builds a map from a `HashMap`, sorting its keys.
+    ///
+    /// # Panics
+    /// If there are more than 2^31 keys.
+    pub fn build_from_hashmap(map: HashMap<Vec<L>, V>) -> Self {
+        let mut entries: Vec<(Vec<L>, V)> = map.into_iter().collect();
+        // HashMap keys are unique, so an unstable sort is deterministic.
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let (keys, values): (Vec<Vec<L>>, Vec<V>) = entries.into_iter().unzip();
+        Self {
+            trie: DoubleArray::build(&keys),
+            values,
+        }
+    }
+
+    /// Returns the value stored for `key`, if the key exists.
+    pub fn get(&self, key: impl AsRef<[L]>) -> Option<&V> {
+        let id = self.trie.exact_match(key)?;
+        Some(&self.values[id as usize])
+    }
+
+    /// Returns the underlying trie, whose value_ids index [`values`](Self::values).
+    pub fn trie(&self) -> &DoubleArray<L> {
+        &self.trie
+    }
+
+    /// Returns all values, indexed by value_id.
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    /// Returns the number of keys.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether the map has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_hashmap_entry_is_retrievable() {
+        let map: HashMap<Vec<char>, &str> = [
+            ("きょう", "今日"),
+            ("きょうと", "京都"),
+            ("とうきょう", "東京"),
+            ("", "empty"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.chars().collect(), v))
+        .collect();
+        let dam = DoubleArrayMap::build_from_hashmap(map.clone());
+        assert_eq!(dam.len(), map.len());
+        for (key, value) in &map {
+            assert_eq!(dam.get(key), Some(value));
+        }
+        assert_eq!(dam.get(['き']), None);
+
+        // value_ids follow sorted key order.
+        let mut keys: Vec<&Vec<char>> = map.keys().collect();
+        keys.sort();
+        for (i, key) in keys.into_iter().enumerate() {
+            assert_eq!(dam.trie().exact_match(key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    fn empty_hashmap() {
+        let dam = DoubleArrayMap::<u8, String>::build_from_hashmap(HashMap::new());
+        assert!(dam.is_empty());
+        assert_eq!(dam.get(b"a"), None);
+    }
+}
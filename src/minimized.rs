@@ -0,0 +1,310 @@
+//! Membership-only tries that store equal subtrees once.
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+
+use crate::{CodeMapper, DoubleArray, Label, MAX_NODES};
+
+/// Set in a slot's base when the state the slot leads to ends a key.
+const FINAL: u32 = 1 << 31;
+
+/// A state of the minimized trie: whether a key ends at it, and its
+/// `(code, state)` edges in ascending code order.
+type State = (bool, Vec<(u32, u32)>);
+
+/// A set of keys in which equal subtrees are stored once, like a DAWG
+/// (minimal acyclic automaton).
+///
+/// Built by [`DoubleArray::build_minimized`]. Keys that end the same way
+/// share the nodes for their common suffix, so a word list full of
+/// inflections (`walk`, `walked`, `talk`, `talked`) needs far fewer nodes
+/// than a [`DoubleArray`]. A shared node has several parents, so a slot's
+/// check holds the code of the label leading to it, as in darts-clone,
+/// rather than a parent index. Keys ending in a shared node cannot keep
+/// value_ids of their own, so the trie answers membership only.
+#[derive(Clone, Debug)]
+pub struct MinimizedTrie<L: Label> {
+    /// Per slot: the base of the state it leads to, with [`FINAL`] set if a
+    /// key ends there. States without edges have base 0.
+    base: Vec<u32>,
+    /// Per slot: the code of the label leading to it; 0 for the root and
+    /// unused slots.
+    check: Vec<u32>,
+    code_map: CodeMapper,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Label> MinimizedTrie<L> {
+    /// Returns whether `key` is one of the keys.
+    pub fn contains(&self, key: impl AsRef<[L]>) -> bool {
+        let mut slot = 0usize;
+        for &label in key.as_ref() {
+            let code = self.code_map.get(label);
+            if code == 0 {
+                return false;
+            }
+            // Only the state whose base is `next ^ code` places a slot with
+            // this check at `next`: bases are unique, and never 0 for a
+            // state with edges.
+            let next = ((self.base[slot] & !FINAL) ^ code) as usize;
+            if self.check.get(next) != Some(&code) {
+                return false;
+            }
+            slot = next;
+        }
+        self.base[slot] & FINAL != 0
+    }
+
+    /// Returns the number of slots, including unused ones, comparable to
+    /// [`DoubleArray::num_nodes`].
+    pub fn num_nodes(&self) -> usize {
+        self.base.len()
+    }
+
+    /// Lays out `states` from `root`. Each state with edges gets a base no
+    /// other state uses, and each edge the slot at `base ^ code`, so a state
+    /// has one slot per edge leading to it.
+    fn place(states: &[State], root: u32, code_map: CodeMapper) -> Self {
+        let mut placer = Placer::default();
+        placer.claim(0, 0);
+        let mut state_base = vec![0u32; states.len()];
+        // (slot, the state it leads to)
+        let mut slots: Vec<(u32, u32)> = vec![(0, root)];
+        let mut seen = vec![false; states.len()];
+        seen[root as usize] = true;
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let edges = &states[id as usize].1;
+            if edges.is_empty() {
+                continue;
+            }
+            let base = placer.find_base(edges);
+            placer.bases.insert(base);
+            state_base[id as usize] = base;
+            for &(code, target) in edges {
+                placer.claim(base ^ code, code);
+                slots.push((base ^ code, target));
+                if !seen[target as usize] {
+                    seen[target as usize] = true;
+                    stack.push(target);
+                }
+            }
+        }
+
+        let mut base = vec![0u32; placer.check.len()];
+        for (slot, target) in slots {
+            let flag = if states[target as usize].0 { FINAL } else { 0 };
+            base[slot as usize] = state_base[target as usize] | flag;
+        }
+        Self {
+            base,
+            check: placer.check,
+            code_map,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Places slots, choosing for each state the lowest base that fits.
+#[derive(Default)]
+struct Placer {
+    check: Vec<u32>,
+    /// Whether a slot is taken.
+    used: Vec<bool>,
+    /// Bases already given to a state.
+    bases: HashSet<u32>,
+    /// No slot below this one is free.
+    first_free: usize,
+}
+
+impl Placer {
+    fn is_free(&self, slot: u32) -> bool {
+        !self.used.get(slot as usize).copied().unwrap_or(false)
+    }
+
+    fn claim(&mut self, slot: u32, code: u32) {
+        let i = slot as usize;
+        if i >= self.used.len() {
+            self.used.resize(i + 1, false);
+            self.check.resize(i + 1, 0);
+        }
+        self.used[i] = true;
+        self.check[i] = code;
+    }
+
+    /// Finds a new base whose slots for `edges` are all free; `edges` is
+    /// non-empty.
+    fn find_base(&mut self, edges: &[(u32, u32)]) -> u32 {
+        while self.first_free < self.used.len() && self.used[self.first_free] {
+            self.first_free += 1;
+        }
+        let mut slot = self.first_free as u32;
+        loop {
+            let base = slot ^ edges[0].0;
+            let fits = base != 0
+                && self.is_free(slot)
+                && !self.bases.contains(&base)
+                && edges.iter().all(|&(code, _)| self.is_free(base ^ code));
+            if fits {
+                let last = edges.iter().map(|&(code, _)| base ^ code).max();
+                if last.is_some_and(|s| s as usize >= MAX_NODES) {
+                    panic!("trie exceeds the maximum of {MAX_NODES} nodes");
+                }
+                return base;
+            }
+            slot += 1;
+        }
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Builds a membership-only trie from sorted keys, storing equal
+    /// subtrees once so that shared suffixes take no extra nodes.
+    ///
+    /// The trie is built as by [`build`](Self::build), then every node is
+    /// merged with the others that have the same keys below it: the same
+    /// labelled children, and a key ending there or not. Value_ids are not
+    /// kept, since keys ending in a merged node could not keep their own.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_minimized(keys: &[impl AsRef<[L]>]) -> MinimizedTrie<L> {
+        let trie = Self::build(keys);
+        let mut ids = HashMap::new();
+        let root = intern(&trie, 0, &mut ids);
+        let mut states = vec![State::default(); ids.len()];
+        for (state, id) in ids {
+            states[id as usize] = state;
+        }
+        MinimizedTrie::place(&states, root, trie.code_map.clone())
+    }
+}
+
+/// Returns the id of the state for the subtree at `node_idx`, interning its
+/// children first so that equal subtrees get equal ids.
+fn intern<L: Label>(trie: &DoubleArray<L>, node_idx: u32, ids: &mut HashMap<State, u32>) -> u32 {
+    let view = trie.view();
+    let base = trie.nodes[node_idx as usize].base();
+    // Sibling chains are in ascending code order, so `edges` is too.
+    let mut edges = Vec::new();
+    let mut child = view.first_child(node_idx);
+    while let Some(c) = child {
+        let code = base ^ c;
+        if code != 0 {
+            edges.push((code, intern(trie, c, ids)));
+        }
+        child = match trie.siblings[c as usize] {
+            0 => None,
+            next => Some(next),
+        };
+    }
+    let state = (view.terminal(node_idx).is_some(), edges);
+    let next_id = ids.len() as u32;
+    *ids.entry(state).or_insert(next_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_suffixes_shrink_the_trie() {
+        let stems = [
+            "call", "help", "jump", "kick", "look", "mark", "open", "park", "play", "pull", "push",
+            "rest", "talk", "test", "turn", "walk", "wish", "work",
+        ];
+        let suffixes = ["", "ed", "er", "ers", "ing", "s"];
+        let mut keys: Vec<String> = stems
+            .iter()
+            .flat_map(|stem| suffixes.iter().map(move |suffix| format!("{stem}{suffix}")))
+            .collect();
+        keys.sort();
+
+        let plain = DoubleArray::<u8>::build(&keys);
+        let minimized = DoubleArray::<u8>::build_minimized(&keys);
+        assert!(
+            minimized.num_nodes() * 3 < plain.num_nodes(),
+            "{} vs {}",
+            minimized.num_nodes(),
+            plain.num_nodes()
+        );
+        for key in &keys {
+            assert!(minimized.contains(key), "{key}");
+        }
+        for miss in [
+            "", "wal", "walke", "walkings", "talkeds", "ing", "s", "jumpe", "xyz",
+        ] {
+            assert!(!minimized.contains(miss), "{miss}");
+        }
+    }
+
+    #[test]
+    fn minimized_membership_matches_build() {
+        // Every string over "ab" up to 6 labels with an even number of 'a's:
+        // the subtrees depend only on the parity and the remaining length.
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for len in 0..=6 {
+            for bits in 0..1u32 << len {
+                let key: Vec<u8> = (0..len)
+                    .map(|i| if bits >> i & 1 == 1 { b'a' } else { b'b' })
+                    .collect();
+                if key.iter().filter(|&&l| l == b'a').count() % 2 == 0 {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+        let plain = DoubleArray::<u8>::build(&keys);
+        let minimized = DoubleArray::<u8>::build_minimized(&keys);
+        assert!(minimized.num_nodes() < plain.num_nodes());
+
+        // All queries over "abc" up to 7 labels; 'c' is unmapped.
+        let mut queries: Vec<Vec<u8>> = vec![Vec::new()];
+        for len in 1..=7 {
+            let mut next = Vec::new();
+            for q in queries.iter().filter(|q| q.len() == len - 1) {
+                for &l in b"abc" {
+                    let mut q = q.clone();
+                    q.push(l);
+                    next.push(q);
+                }
+            }
+            queries.extend(next);
+        }
+        for q in &queries {
+            assert_eq!(
+                minimized.contains(q),
+                plain.exact_match(q).is_some(),
+                "{q:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn minimized_edge_cases() {
+        let empty = DoubleArray::<u8>::build_minimized(&[] as &[&[u8]]);
+        assert!(!empty.contains(b""));
+        assert!(!empty.contains(b"a"));
+
+        let only_empty = DoubleArray::<u8>::build_minimized(&[b"" as &[u8]]);
+        assert!(only_empty.contains(b""));
+        assert!(!only_empty.contains(b"a"));
+
+        let words: Vec<Vec<char>> = ["かく", "かくこと", "よむ", "よむこと"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let kana = DoubleArray::<char>::build_minimized(&words);
+        for w in &words {
+            assert!(kana.contains(w));
+        }
+        let miss: Vec<char> = "かくよむ".chars().collect();
+        assert!(!kana.contains(&miss));
+    }
+
+    #[test]
+    #[should_panic(expected = "sorts before the previous key")]
+    fn minimized_rejects_unsorted_keys() {
+        DoubleArray::<u8>::build_minimized(&[b"b" as &[u8], b"a"]);
+    }
+}
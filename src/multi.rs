@@ -0,0 +1,131 @@
+use crate::{DoubleArray, Label};
+
+/// A trie whose keys may carry several values, e.g. homographs with
+/// different readings.
+///
+/// Built by [`DoubleArray::build_multivalued`]. Each distinct key has a single
+/// terminal whose value_id indexes a group of values, stored contiguously
+/// alongside the trie.
+#[derive(Clone, Debug)]
+pub struct MultiValueTrie<L: Label> {
+    trie: DoubleArray<L>,
+    /// `values[offsets[g]..offsets[g + 1]]` is the value group of group id `g`.
+    offsets: Vec<u32>,
+    values: Vec<u32>,
+}
+
+impl<L: Label> MultiValueTrie<L> {
+    /// Returns all values stored for `key`, in insertion order, or an empty
+    /// slice if the key does not exist.
+    pub fn get(&self, key: impl AsRef<[L]>) -> &[u32] {
+        match self.trie.exact_match(key.as_ref()) {
+            Some(group) => self.group(group),
+            None => &[],
+        }
+    }
+
+    /// Returns the values of group `group` (the value_id stored in the trie).
+    ///
+    /// # Panics
+    /// If `group` is not a group id of this trie.
+    pub fn group(&self, group: u32) -> &[u32] {
+        let g = group as usize;
+        &self.values[self.offsets[g] as usize..self.offsets[g + 1] as usize]
+    }
+
+    /// Returns the underlying trie, whose value_ids are group ids.
+    ///
+    /// Use it for prefix or predictive search, then resolve each value_id
+    /// with [`group`](Self::group).
+    pub fn trie(&self) -> &DoubleArray<L> {
+        &self.trie
+    }
+
+    /// Returns the number of distinct keys.
+    pub fn num_keys(&self) -> usize {
+        self.offsets.len() - 1
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Builds a trie from `(key, value)` entries in which a key may appear
+    /// more than once.
+    ///
+    /// Entries need not be sorted. All values of a key are kept, in the order
+    /// they appear in `entries`, and are reachable from its single terminal.
+    ///
+    /// # Panics
+    /// If there are more than `u32::MAX` entries or 2^31 distinct keys.
+    pub fn build_multivalued(entries: &[(Vec<L>, u32)]) -> MultiValueTrie<L> {
+        assert!(
+            entries.len() <= u32::MAX as usize,
+            "too many entries: offsets must fit in u32"
+        );
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        // Stable, so values of equal keys keep their input order.
+        order.sort_by(|&a, &b| entries[a].0.cmp(&entries[b].0));
+
+        let mut keys: Vec<&[L]> = Vec::new();
+        let mut offsets: Vec<u32> = vec![0];
+        let mut values: Vec<u32> = Vec::with_capacity(entries.len());
+        for &i in &order {
+            let (key, value) = &entries[i];
+            if keys.last() != Some(&key.as_slice()) {
+                if !keys.is_empty() {
+                    offsets.push(values.len() as u32);
+                }
+                keys.push(key);
+            }
+            values.push(*value);
+        }
+        if !keys.is_empty() {
+            offsets.push(values.len() as u32);
+        }
+
+        MultiValueTrie {
+            trie: DoubleArray::build(&keys),
+            offsets,
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn multiple_values_per_key() {
+        let entries = vec![
+            (chars("東京"), 10),
+            (chars("大阪"), 20),
+            (chars("東京"), 11),
+            (chars("東"), 30),
+            (chars("東京"), 12),
+        ];
+        let mt = DoubleArray::<char>::build_multivalued(&entries);
+        assert_eq!(mt.num_keys(), 3);
+        assert_eq!(mt.get(chars("東京")), &[10, 11, 12]);
+        assert_eq!(mt.get(chars("大阪")), &[20]);
+        assert_eq!(mt.get(chars("東")), &[30]);
+        assert!(mt.get(chars("京")).is_empty());
+
+        let groups: Vec<&[u32]> = mt
+            .trie()
+            .common_prefix_search(&chars("東京都"))
+            .map(|m| mt.group(m.value_id))
+            .collect();
+        assert_eq!(groups, vec![&[30][..], &[10, 11, 12][..]]);
+    }
+
+    #[test]
+    fn multivalued_empty() {
+        let mt = DoubleArray::<u8>::build_multivalued(&[]);
+        assert_eq!(mt.num_keys(), 0);
+        assert!(mt.get(b"a").is_empty());
+    }
+}
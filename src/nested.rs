@@ -0,0 +1,124 @@
+use crate::{DoubleArray, Label};
+
+/// A two-level trie for keys made of an outer and an inner part, e.g.
+/// `(namespace, name)`.
+///
+/// Built by [`DoubleArray::build_nested`]. The outer trie maps each distinct
+/// outer key to the index of a sub-trie holding its inner keys, so the parts
+/// need no separator label and the names of one namespace share one small
+/// trie.
+#[derive(Clone, Debug)]
+pub struct NestedTrie<L: Label> {
+    /// Outer keys; the value_id of each is its index in `inner`.
+    outer: DoubleArray<L>,
+    inner: Vec<DoubleArray<L>>,
+}
+
+impl<L: Label> NestedTrie<L> {
+    /// Returns the value stored for `(outer, inner)`.
+    pub fn exact_match(&self, outer: impl AsRef<[L]>, inner: impl AsRef<[L]>) -> Option<u32> {
+        self.sub_trie(outer)?.exact_match(inner.as_ref())
+    }
+
+    /// Returns the sub-trie of the inner keys under `outer`.
+    pub fn sub_trie(&self, outer: impl AsRef<[L]>) -> Option<&DoubleArray<L>> {
+        let id = self.outer.exact_match(outer.as_ref())?;
+        self.inner.get(id as usize)
+    }
+
+    /// Returns the outer trie, whose value_ids index the sub-tries in
+    /// ascending outer key order.
+    pub fn outer(&self) -> &DoubleArray<L> {
+        &self.outer
+    }
+
+    /// Returns the sub-tries, one per distinct outer key.
+    pub fn sub_tries(&self) -> &[DoubleArray<L>] {
+        &self.inner
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Builds a [`NestedTrie`] from `(outer, inner, value)` entries.
+    ///
+    /// Entries need not be sorted. Each value is stored as the value_id of
+    /// its inner key, as with [`build_with_values`](Self::build_with_values).
+    ///
+    /// # Panics
+    /// - If an `(outer, inner)` pair appears more than once.
+    /// - If a value does not fit in 31 bits, or there are more than 2^31
+    ///   distinct outer keys.
+    pub fn build_nested(entries: &[(Vec<L>, Vec<L>, u32)]) -> NestedTrie<L> {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (&entries[a], &entries[b]);
+            (&a.0, &a.1).cmp(&(&b.0, &b.1))
+        });
+
+        let mut outer_keys: Vec<&[L]> = Vec::new();
+        let mut inner = Vec::new();
+        for group in order.chunk_by(|&a, &b| entries[a].0 == entries[b].0) {
+            let keys: Vec<&[L]> = group.iter().map(|&i| entries[i].1.as_slice()).collect();
+            let values: Vec<u32> = group.iter().map(|&i| entries[i].2).collect();
+            outer_keys.push(&entries[group[0]].0);
+            inner.push(DoubleArray::build_with_values(&keys, &values));
+        }
+
+        NestedTrie {
+            outer: DoubleArray::build(&outer_keys),
+            inner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(outer: &str, inner: &str, value: u32) -> (Vec<u8>, Vec<u8>, u32) {
+        (outer.into(), inner.into(), value)
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let entries = vec![
+            entry("std", "vec", 1),
+            entry("core", "mem", 2),
+            entry("std", "io", 3),
+            entry("core", "ptr", 4),
+            entry("std", "mem", 5),
+            entry("", "root", 6),
+        ];
+        let nested = DoubleArray::<u8>::build_nested(&entries);
+        assert_eq!(nested.sub_tries().len(), 3);
+        for (outer, inner, value) in &entries {
+            assert_eq!(nested.exact_match(outer, inner), Some(*value));
+        }
+        // "mem" resolves per namespace; the others stay in their own.
+        assert_ne!(
+            nested.exact_match(b"std", b"mem"),
+            nested.exact_match(b"core", b"mem")
+        );
+        assert_eq!(nested.exact_match(b"core", b"vec"), None);
+        assert_eq!(nested.exact_match(b"std", b"ptr"), None);
+        assert_eq!(nested.exact_match(b"st", b"dvec"), None);
+        assert_eq!(nested.exact_match(b"alloc", b"vec"), None);
+
+        let std = nested.sub_trie(b"std").unwrap();
+        assert_eq!(std.num_keys(), 3);
+        assert_eq!(nested.outer().exact_match(b"core"), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate")]
+    fn duplicate_pair_panics() {
+        DoubleArray::<u8>::build_nested(&[entry("a", "b", 0), entry("a", "b", 1)]);
+    }
+
+    #[test]
+    fn nested_empty() {
+        let nested = DoubleArray::<u8>::build_nested(&[]);
+        assert!(nested.sub_tries().is_empty());
+        assert_eq!(nested.exact_match(b"", b""), None);
+    }
+}
@@ -1,6 +1,6 @@
 const IS_LEAF: u32 = 1 << 31;
 const HAS_LEAF: u32 = 1 << 31;
-const MASK: u32 = 0x7FFF_FFFF;
+pub(crate) const MASK: u32 = 0x7FFF_FFFF;
 
 /// A node in the double-array trie.
 ///
@@ -73,6 +73,12 @@ impl Node {
         self.check |= HAS_LEAF;
     }
 
+    /// Clears the HAS_LEAF flag after the terminal child is removed.
+    #[inline]
+    pub fn clear_has_leaf(&mut self) {
+        self.check &= !HAS_LEAF;
+    }
+
     /// Returns the raw base field including flags (for serialization).
     #[inline]
     pub fn raw_base(&self) -> u32 {
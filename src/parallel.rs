@@ -0,0 +1,170 @@
+//! Dependency-free parallel build passes over `std::thread`, enabled by the
+//! `parallel` feature.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+use crate::code_map;
+use crate::Label;
+
+/// Below this many keys per thread, spawning costs more than it saves.
+const MIN_KEYS_PER_THREAD: usize = 16 * 1024;
+
+/// Returns how many threads to split `len` items over.
+fn num_threads(len: usize) -> usize {
+    let available = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    available.min(len / MIN_KEYS_PER_THREAD).max(1)
+}
+
+/// Counts label frequencies like the serial pass, splitting `keys` into one
+/// chunk per thread and adding up the per-chunk counts.
+pub(crate) fn count_frequencies<L: Label + Sync>(
+    keys: &[impl AsRef<[L]>],
+    table_size: usize,
+) -> Vec<u64> {
+    let threads = num_threads(keys.len());
+    if threads == 1 {
+        return code_map::count_frequencies(keys, table_size);
+    }
+    // The key type itself need not be `Sync`; its label slices are.
+    let slices: Vec<&[L]> = keys.iter().map(|k| k.as_ref()).collect();
+    count_frequencies_in(&slices, table_size, threads)
+}
+
+fn count_frequencies_in<L: Label + Sync>(
+    keys: &[&[L]],
+    table_size: usize,
+    threads: usize,
+) -> Vec<u64> {
+    let chunk_len = keys.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = keys
+            .chunks(chunk_len)
+            .map(|chunk| s.spawn(move || code_map::count_frequencies(chunk, table_size)))
+            .collect();
+        let mut freq = vec![0u64; table_size];
+        for handle in handles {
+            let counts = handle.join().expect("frequency counting thread panicked");
+            for (total, count) in freq.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        freq
+    })
+}
+
+/// Stable sort of key indices by key, like the serial `sorted_order` in
+/// `build`. Returns `None` when the input is too small to split.
+pub(crate) fn sorted_order<L: Label + Sync>(keys: &[impl AsRef<[L]>]) -> Option<Vec<usize>> {
+    let threads = num_threads(keys.len());
+    if threads == 1 {
+        return None;
+    }
+    let slices: Vec<&[L]> = keys.iter().map(|k| k.as_ref()).collect();
+    Some(sorted_order_in(&slices, threads))
+}
+
+/// Sorts one chunk of indices per thread, then merges adjacent runs pairwise,
+/// each merge on its own thread, until a single run is left.
+fn sorted_order_in<L: Label + Sync>(keys: &[&[L]], threads: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    let chunk_len = keys.len().div_ceil(threads).max(1);
+    thread::scope(|s| {
+        for chunk in order.chunks_mut(chunk_len) {
+            s.spawn(move || chunk.sort_by(|&a, &b| keys[a].cmp(keys[b])));
+        }
+    });
+
+    let mut merged = vec![0usize; order.len()];
+    let mut run_len = chunk_len;
+    while run_len < order.len() {
+        thread::scope(|s| {
+            for (src, dst) in order
+                .chunks(2 * run_len)
+                .zip(merged.chunks_mut(2 * run_len))
+            {
+                s.spawn(move || {
+                    let (left, right) = src.split_at(run_len.min(src.len()));
+                    merge(keys, left, right, dst);
+                });
+            }
+        });
+        std::mem::swap(&mut order, &mut merged);
+        run_len *= 2;
+    }
+    order
+}
+
+/// Merges two sorted runs into `dst`. Ties take from `left`, which holds the
+/// earlier indices, so the result is stable.
+fn merge<L: Label>(keys: &[&[L]], left: &[usize], right: &[usize], dst: &mut [usize]) {
+    let (mut i, mut j) = (0, 0);
+    for slot in dst.iter_mut() {
+        let take_right = j < right.len() && (i == left.len() || keys[right[j]] < keys[left[i]]);
+        if take_right {
+            *slot = right[j];
+            j += 1;
+        } else {
+            *slot = left[i];
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_frequencies_match_serial() {
+        let keys: Vec<Vec<char>> = (0..5_000u32)
+            .map(|i| {
+                let len = (i % 7 + 1) as usize;
+                (0..len)
+                    .map(|j| char::from_u32(0x3041 + (i * 31 + j as u32 * 17) % 83).unwrap())
+                    .collect()
+            })
+            .collect();
+        let slices: Vec<&[char]> = keys.iter().map(|k| k.as_slice()).collect();
+        let table_size = 0x3041 + 83;
+
+        let serial = code_map::count_frequencies(&slices, table_size);
+        assert_eq!(count_frequencies(&keys, table_size), serial);
+        for threads in [1, 2, 3, 8, 64] {
+            assert_eq!(
+                count_frequencies_in(&slices, table_size, threads),
+                serial,
+                "threads = {threads}"
+            );
+        }
+        // More threads than keys leaves no empty chunk behind.
+        assert_eq!(
+            count_frequencies_in(&slices[..3], table_size, 8),
+            code_map::count_frequencies(&slices[..3], table_size)
+        );
+    }
+
+    #[test]
+    fn parallel_sort_matches_serial() {
+        // Few distinct keys, so equal keys test stability.
+        let keys: Vec<Vec<u8>> = (0..2_000u32)
+            .map(|i| {
+                let n = (i * 7919) % 601;
+                n.to_string().into_bytes()
+            })
+            .collect();
+        let slices: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        let mut serial: Vec<usize> = (0..keys.len()).collect();
+        serial.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+
+        for threads in [2, 3, 5, 8, 64] {
+            assert_eq!(
+                sorted_order_in(&slices, threads),
+                serial,
+                "threads = {threads}"
+            );
+        }
+        assert!(sorted_order_in::<u8>(&[], 4).is_empty());
+        assert_eq!(sorted_order_in(&slices[..1], 4), vec![0]);
+    }
+}
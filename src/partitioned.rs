@@ -0,0 +1,147 @@
+use crate::DoubleArray;
+
+/// Number of low codepoint bits in the second label of a split char.
+const LOW_BITS: u32 = 8;
+
+/// Splits a char into its page (high bits) and offset (low bits) labels.
+///
+/// Comparing the label pairs orders chars the same way as comparing the
+/// chars, so sorted keys stay sorted.
+#[inline]
+fn split(c: char) -> [u16; 2] {
+    let cp = u32::from(c);
+    [(cp >> LOW_BITS) as u16, (cp & ((1 << LOW_BITS) - 1)) as u16]
+}
+
+/// A `char` trie for keys over a huge, sparse set of codepoints.
+///
+/// Built by [`DoubleArray::build_partitioned`]. A [`CodeMapper`](crate::CodeMapper)
+/// sizes its table by the largest label, so a `DoubleArray<char>` holding a
+/// single supplementary-plane char already needs a table of over 100K
+/// entries. Here every char is instead stored as two transitions: first on
+/// its 256-codepoint page (the high bits), then on its offset within the page.
+/// Both kinds of label go into one `DoubleArray<u16>` with a single code map;
+/// the pages do not get maps of their own. Since all labels stay below
+/// `0x1100`, that one map's table does too. The price is twice as many
+/// transitions per char.
+#[derive(Clone, Debug)]
+pub struct PartitionedTrie {
+    trie: DoubleArray<u16>,
+}
+
+impl PartitionedTrie {
+    /// Exact match search. Returns the value_id if the key exists.
+    #[inline]
+    pub fn exact_match(&self, key: impl AsRef<[char]>) -> Option<u32> {
+        let view = self.trie.view();
+        let node_idx = view.traverse_labels(key.as_ref().iter().flat_map(|&c| split(c)))?;
+        view.terminal_value(node_idx)
+    }
+
+    /// Returns the underlying trie over page and offset labels.
+    pub fn trie(&self) -> &DoubleArray<u16> {
+        &self.trie
+    }
+}
+
+impl DoubleArray<char> {
+    /// Builds a [`PartitionedTrie`] from sorted keys.
+    ///
+    /// Each key `keys[i]` is assigned `value_id = i`. Prefer it over
+    /// [`build`](Self::build) when keys use high, scattered codepoints (e.g.
+    /// CJK extensions or private use planes), where the code map would
+    /// otherwise dominate memory.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_partitioned(keys: &[impl AsRef<[char]>]) -> PartitionedTrie {
+        let split_keys: Vec<Vec<u16>> = keys
+            .iter()
+            .map(|k| k.as_ref().iter().flat_map(|&c| split(c)).collect())
+            .collect();
+        PartitionedTrie {
+            trie: DoubleArray::build(&split_keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sorted keys over scattered supplementary-plane codepoints.
+    fn sparse_keys() -> Vec<Vec<char>> {
+        let mut keys: Vec<Vec<char>> = (0..300u32)
+            .map(|i| {
+                let a = char::from_u32(0x2_0000 + i * 97).unwrap();
+                let b = char::from_u32(0xF_0000 + (i * 7919) % 0xFFFD).unwrap();
+                if i % 3 == 0 {
+                    vec![a]
+                } else {
+                    vec![a, b, 'あ']
+                }
+            })
+            .collect();
+        keys.push(vec!['\u{10FFFF}']);
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    #[test]
+    fn split_preserves_char_order() {
+        let chars = [
+            '\0',
+            'a',
+            '\u{FF}',
+            '\u{100}',
+            'あ',
+            '\u{FFFF}',
+            '\u{10000}',
+            '\u{10FFFF}',
+        ];
+        for w in chars.windows(2) {
+            assert!(split(w[0]) < split(w[1]), "{:?} vs {:?}", w[0], w[1]);
+        }
+    }
+
+    #[test]
+    fn partitioned_matches_plain_trie() {
+        let keys = sparse_keys();
+        let plain = DoubleArray::<char>::build(&keys);
+        let pt = DoubleArray::<char>::build_partitioned(&keys);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(pt.exact_match(key), Some(i as u32));
+        }
+        let probes: [&[char]; 5] = [
+            &[],
+            &['あ'],
+            &['\u{2_0000}', 'x'],
+            &['\u{2_0001}'],
+            &['\u{10FFFE}'],
+        ];
+        for probe in probes {
+            assert_eq!(pt.exact_match(probe), plain.exact_match(probe), "{probe:?}");
+        }
+        // A char sharing only its page with a key must not match.
+        assert!(keys.contains(&vec!['\u{2_0061}', '\u{F_1EEF}', 'あ']));
+        assert_eq!(pt.exact_match(['\u{2_0061}', '\u{F_1EEE}', 'あ']), None);
+
+        // The code map no longer scales with the largest codepoint: its label
+        // and reverse tables hold at most 0x1100 and 0x1100 + 1 entries.
+        assert!(pt.trie().section_sizes().code_map <= 12 + (2 * 0x1100 + 1) * 4);
+        assert!(pt.trie().serialized_size() < plain.serialized_size() / 10);
+    }
+
+    #[test]
+    fn partitioned_empty_and_empty_key() {
+        let empty: &[Vec<char>] = &[];
+        assert_eq!(
+            DoubleArray::<char>::build_partitioned(empty).exact_match([]),
+            None
+        );
+        let pt = DoubleArray::<char>::build_partitioned(&[vec![], vec!['漢']]);
+        assert_eq!(pt.exact_match([]), Some(0));
+        assert_eq!(pt.exact_match(['漢']), Some(1));
+    }
+}
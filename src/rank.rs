@@ -0,0 +1,331 @@
+use crate::view::TrieView;
+use crate::{DoubleArray, Label};
+
+impl<L: Label> TrieView<'_, L> {
+    /// Counts, for every node, the keys in its subtree: a leaf counts itself,
+    /// and every other node the leaves below it. Unused slots count 0.
+    pub(crate) fn subtree_key_counts(&self) -> Vec<u32> {
+        let mut counts = vec![0u32; self.nodes.len()];
+        for (leaf, node) in self.nodes.iter().enumerate() {
+            if !node.is_leaf() {
+                continue;
+            }
+            // Climb to the root; the step bound guards against cycles in
+            // malformed data.
+            let mut idx = leaf as u32;
+            for _ in 0..self.nodes.len() {
+                counts[idx as usize] += 1;
+                if idx == 0 {
+                    break;
+                }
+                idx = self.nodes[idx as usize].check();
+                if idx as usize >= self.nodes.len() {
+                    break;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Returns the non-terminal children of `node_idx` with their labels.
+    pub(crate) fn labeled_children(&self, node_idx: u32) -> Vec<(L, u32)> {
+        let base = self.nodes[node_idx as usize].base();
+        let mut children = Vec::new();
+        let mut cursor = self.first_child(node_idx);
+        // At most one step per node; guards against cycles in malformed data.
+        for _ in 0..self.nodes.len() {
+            let Some(child) = cursor else { break };
+            let code = base ^ child;
+            if code != 0 {
+                if let Some(label) = self.label_of(code) {
+                    children.push((label, child));
+                }
+            }
+            let sib = self.siblings[child as usize];
+            cursor = (sib != 0 && (sib as usize) < self.nodes.len()).then_some(sib);
+        }
+        children
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Returns the per-node subtree key counts, computed on first use.
+    fn subtree_key_counts(&self) -> &[u32] {
+        self.subtree_keys
+            .get_or_init(|| self.view().subtree_key_counts())
+    }
+
+    /// Returns the 0-based position of `key` among all keys in ascending
+    /// order, or `None` if the key does not exist.
+    ///
+    /// The rank comes from the trie's structure, not the stored value, so it
+    /// is the key's position even for a trie built with
+    /// [`build_with_values`](Self::build_with_values); for
+    /// [`build`](Self::build) it equals the value_id. At each label, the keys
+    /// under smaller sibling labels are added up from per-node subtree key
+    /// counts. Those counts take one pass over the trie, on the first call,
+    /// and stay with it (4 bytes per node); after that a query costs
+    /// O(key length × fan-out).
+    pub fn rank_of(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        let counts = self.subtree_key_counts();
+        let view = self.view();
+        let mut node_idx = 0u32;
+        let mut rank = 0u32;
+        for &label in key.as_ref() {
+            // A key ending here sorts before every extension of it.
+            if view.terminal(node_idx).is_some() {
+                rank += 1;
+            }
+            rank += view
+                .labeled_children(node_idx)
+                .iter()
+                .filter(|&&(l, _)| l < label)
+                .map(|&(_, child)| counts[child as usize])
+                .sum::<u32>();
+            let code = self.code_map.get(label);
+            if code == 0 {
+                return None;
+            }
+            node_idx = view.child(node_idx, code)?;
+        }
+        view.terminal(node_idx).map(|_| rank)
+    }
+
+    /// Returns the key at 0-based position `rank` among all keys in
+    /// ascending order, or `None` if there are not that many keys.
+    ///
+    /// The inverse of [`rank_of`](Self::rank_of), for paging through a
+    /// sorted dictionary. Descends from the root, skipping whole subtrees by
+    /// their key counts; costs O(key length × fan-out × log fan-out).
+    pub fn select(&self, rank: u32) -> Option<Vec<L>> {
+        let counts = self.subtree_key_counts();
+        let view = self.view();
+        let mut node_idx = 0u32;
+        let mut rest = rank;
+        let mut key = Vec::new();
+        // One level per step; the bound guards against cycles in malformed data.
+        'descend: for _ in 0..self.nodes.len() {
+            if view.terminal(node_idx).is_some() {
+                if rest == 0 {
+                    return Some(key);
+                }
+                rest -= 1;
+            }
+            let mut children = view.labeled_children(node_idx);
+            children.sort_unstable_by_key(|&(label, _)| label);
+            for (label, child) in children {
+                let count = counts[child as usize];
+                if rest < count {
+                    key.push(label);
+                    node_idx = child;
+                    continue 'descend;
+                }
+                rest -= count;
+            }
+            return None;
+        }
+        None
+    }
+
+    /// Returns the value_id of `key` and the number of keys strictly
+    /// extending it, e.g. for "found (12 more completions)".
+    ///
+    /// One traversal plus a lookup in the per-node subtree key counts that
+    /// [`rank_of`](Self::rank_of) uses, computed on the first call; the
+    /// completions are never enumerated. A key that is absent but a prefix
+    /// of others gets `(None, n)`.
+    pub fn match_with_completion_count(&self, key: impl AsRef<[L]>) -> (Option<u32>, usize) {
+        let view = self.view();
+        let Some(node_idx) = view.traverse(key.as_ref()) else {
+            return (None, 0);
+        };
+        let value = view.terminal_value(node_idx);
+        // The node's count includes the key itself if it is stored.
+        let below = self.subtree_key_counts()[node_idx as usize] as usize;
+        (value, below - usize::from(value.is_some()))
+    }
+
+    /// Returns an iterator over all keys in ascending label order.
+    ///
+    /// [`predictive_search`](Self::predictive_search) visits children by
+    /// code, which follows label frequency, not label order. This walk sorts
+    /// each node's children by label as it enters the node, so keys come out
+    /// strictly ascending, as they were given to [`build`](Self::build). Keys
+    /// are produced lazily: the iterator holds the current key and the
+    /// unvisited children of the nodes on its path, which is bounded by the
+    /// trie's depth times its fan-out, not by the number of keys.
+    pub fn keys_sorted(&self) -> impl Iterator<Item = Vec<L>> + '_ {
+        self.entries_sorted().map(|(key, _)| key)
+    }
+
+    /// The walk of [`keys_sorted`](Self::keys_sorted), yielding each key
+    /// with its value_id.
+    fn entries_sorted(&self) -> impl Iterator<Item = (Vec<L>, u32)> + '_ {
+        let view = self.view();
+        let mut key: Vec<L> = Vec::new();
+        // Pending (node, length of its parent's path, label leading to it),
+        // with the smallest label on top. The root has no label.
+        let mut stack: Vec<(u32, usize, Option<L>)> = vec![(0, 0, None)];
+        let mut steps_left = self.nodes.len();
+        std::iter::from_fn(move || {
+            while let Some((node_idx, depth, label)) = stack.pop() {
+                // One step per node; guards against cycles in malformed data.
+                steps_left = steps_left.checked_sub(1)?;
+                key.truncate(depth);
+                key.extend(label);
+                let mut children = view.labeled_children(node_idx);
+                children.sort_unstable_by_key(|&(l, _)| std::cmp::Reverse(l));
+                let len = key.len();
+                stack.extend(children.into_iter().map(|(l, child)| (child, len, Some(l))));
+                // A key sorts before every extension of it.
+                if let Some((_, value_id)) = view.terminal(node_idx) {
+                    return Some((key.clone(), value_id));
+                }
+            }
+            None
+        })
+    }
+
+    /// Returns a hash of the trie's `(key, value_id)` pairs, independent of
+    /// its node layout.
+    ///
+    /// Keys are hashed in [`keys_sorted`](Self::keys_sorted) order, each as
+    /// its length, its labels and its value_id, with 64-bit FNV-1a. Tries of
+    /// the same pairs hash equal however they were built (prefix-free
+    /// leaves, fan-out limits, a different code map), and the value does
+    /// not depend on the platform, so it can key a persistent cache.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = Fnv1a::new();
+        for (key, value_id) in self.entries_sorted() {
+            hash.write(&(key.len() as u64).to_le_bytes());
+            for &label in &key {
+                hash.write(&label.into().to_le_bytes());
+            }
+            hash.write(&value_id.to_le_bytes());
+        }
+        hash.0
+    }
+}
+
+/// 64-bit FNV-1a, which is simple and fixed, unlike `std`'s `DefaultHasher`.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_of_ignores_custom_values() {
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"abc", b"b", b"ba", b"c"];
+        let values = [70, 60, 50, 40, 30, 20, 10];
+        let da = DoubleArray::<u8>::build_with_values(&keys, &values);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(values[i]));
+            assert_eq!(da.rank_of(key), Some(i as u32), "{key:?}");
+        }
+        assert_eq!(da.rank_of(b"abd"), None);
+        assert_eq!(da.rank_of(b"d"), None);
+        assert_eq!(DoubleArray::<u8>::build(&[] as &[&[u8]]).rank_of(b""), None);
+    }
+
+    #[test]
+    fn select_inverts_rank_of() {
+        let words = ["", "か", "かな", "かなし", "かに", "な", "なし", "漢字"];
+        let keys: Vec<Vec<char>> = words.iter().map(|w| w.chars().collect()).collect();
+        let da = DoubleArray::<char>::build_with_values(&keys, &[9; 8]);
+        for (i, key) in keys.iter().enumerate() {
+            let selected = da.select(i as u32).unwrap();
+            assert_eq!(&selected, key);
+            assert_eq!(da.rank_of(&selected), Some(i as u32));
+        }
+        assert_eq!(da.select(keys.len() as u32), None);
+        assert_eq!(da.select(u32::MAX), None);
+        assert_eq!(DoubleArray::<u8>::build(&[] as &[&[u8]]).select(0), None);
+    }
+
+    #[test]
+    fn keys_sorted_is_strictly_label_ascending() {
+        // 'z' is the most frequent label, so code order visits it first.
+        let words = ["", "a", "az", "azz", "b", "bz", "zz", "zzz", "漢", "漢字"];
+        let keys: Vec<Vec<char>> = words.iter().map(|w| w.chars().collect()).collect();
+        let da = DoubleArray::<char>::build(&keys);
+        let by_code: Vec<Vec<char>> = da.predictive_search(&[]).map(|m| m.key).collect();
+        assert_ne!(by_code, keys);
+
+        let sorted: Vec<Vec<char>> = da.keys_sorted().collect();
+        assert_eq!(sorted, keys);
+        assert!(sorted.windows(2).all(|w| w[0] < w[1]));
+
+        let prefix_free = DoubleArray::<u8>::build_prefix_free(&[b"ab" as &[u8], b"b", b"ca"]);
+        let sorted: Vec<Vec<u8>> = prefix_free.keys_sorted().collect();
+        assert_eq!(sorted, vec![b"ab".to_vec(), b"b".to_vec(), b"ca".to_vec()]);
+        let empty = DoubleArray::<u8>::build(&[] as &[&[u8]]);
+        assert_eq!(empty.keys_sorted().count(), 0);
+    }
+
+    #[test]
+    fn rank_of_follows_label_order_not_code_order() {
+        // 'z' is the most frequent label, so it gets the smallest code.
+        let words = ["a", "az", "azz", "b", "zz", "zzz"];
+        let keys: Vec<Vec<char>> = words.iter().map(|w| w.chars().collect()).collect();
+        let da = DoubleArray::<char>::build_with_values(&keys, &[0; 6]);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.rank_of(key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    fn content_hash_ignores_layout() {
+        let keys: Vec<&[u8]> = vec![b"ab", b"b", b"ca", b"cb"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let prefix_free = DoubleArray::<u8>::build_prefix_free(&keys);
+        let shuffled = DoubleArray::<u8>::build_unsorted(&[b"b" as &[u8], b"ab", b"cb", b"ca"]);
+        assert_ne!(da.as_bytes(), prefix_free.as_bytes());
+        assert_eq!(da.content_hash(), prefix_free.content_hash());
+        assert_eq!(
+            da.content_hash(),
+            DoubleArray::<u8>::build(&keys).content_hash()
+        );
+
+        // Same keys, other value_ids.
+        assert_ne!(da.content_hash(), shuffled.content_hash());
+        let renumbered = DoubleArray::<u8>::build_with_values(&keys, &[0, 1, 2, 4]);
+        assert_ne!(da.content_hash(), renumbered.content_hash());
+        // A key moved between lengths.
+        let other = DoubleArray::<u8>::build(&[b"a" as &[u8], b"bb", b"ca", b"cb"]);
+        assert_ne!(da.content_hash(), other.content_hash());
+
+        let empty = DoubleArray::<u8>::build(&[] as &[&[u8]]);
+        assert_eq!(empty.content_hash(), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn match_with_completion_count_excludes_the_key() {
+        let keys: Vec<&[u8]> = vec![b"app", b"apple", b"apply", b"apt"];
+        let da = DoubleArray::<u8>::build_with_values(&keys, &[7, 8, 9, 10]);
+        assert_eq!(da.match_with_completion_count(b"app"), (Some(7), 2));
+        assert_eq!(da.match_with_completion_count(b"ap"), (None, 4));
+        assert_eq!(da.match_with_completion_count(b""), (None, 4));
+        assert_eq!(da.match_with_completion_count(b"apple"), (Some(8), 0));
+        assert_eq!(da.match_with_completion_count(b"appx"), (None, 0));
+        assert_eq!(da.match_with_completion_count(b"b"), (None, 0));
+
+        // Leaf nodes of a prefix-free trie count themselves once.
+        let da = DoubleArray::<u8>::build_prefix_free(&[b"apple" as &[u8], b"apply"]);
+        assert_eq!(da.match_with_completion_count(b"appl"), (None, 2));
+        assert_eq!(da.match_with_completion_count(b"apply"), (Some(1), 0));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{DoubleArray, Label};
+
+/// Bit of a route's value_id marking it as a prefix rule.
+///
+/// Leaves have no spare bit next to IS_LEAF, so routes use a value
+/// convention instead: [`DoubleArray::build_routes`] sets this bit in the
+/// value_id of every prefix rule, which limits route values to 30 bits.
+/// [`DoubleArray::match_route`] strips it again. Plain searches such as
+/// `exact_match` return the value_id with the bit still set.
+pub const PREFIX_RULE: u32 = 1 << 30;
+
+impl<L: Label> DoubleArray<L> {
+    /// Builds a routing table from sorted keys, where `prefix_rules[i]` marks
+    /// `keys[i]` as a prefix rule rather than an exact route.
+    ///
+    /// A prefix rule such as `/api/` matches every path starting with it,
+    /// like `/api/*`; an exact route only matches itself. Query with
+    /// [`match_route`](Self::match_route). Prefix rules are recorded with
+    /// [`PREFIX_RULE`] in their value_id.
+    ///
+    /// # Panics
+    /// - If `keys`, `values` and `prefix_rules` have different lengths.
+    /// - If a value does not fit in 30 bits.
+    /// - Otherwise the same as [`build_with_values`](Self::build_with_values).
+    pub fn build_routes(keys: &[impl AsRef<[L]>], values: &[u32], prefix_rules: &[bool]) -> Self {
+        assert_eq!(
+            keys.len(),
+            values.len(),
+            "keys and values must have the same length"
+        );
+        assert_eq!(
+            keys.len(),
+            prefix_rules.len(),
+            "keys and prefix_rules must have the same length"
+        );
+        let values: Vec<u32> = values
+            .iter()
+            .zip(prefix_rules)
+            .map(|(&value, &is_rule)| {
+                assert!(value < PREFIX_RULE, "route value must fit in 30 bits");
+                if is_rule {
+                    value | PREFIX_RULE
+                } else {
+                    value
+                }
+            })
+            .collect();
+        Self::build_with_values(keys, &values)
+    }
+
+    /// Returns the value of the longest route matching `path`: an exact
+    /// route equal to `path`, or a prefix rule that `path` starts with.
+    ///
+    /// Walks `path` once, as [`common_prefix_search`](Self::common_prefix_search)
+    /// does. The trie must come from [`build_routes`](Self::build_routes).
+    pub fn match_route(&self, path: impl AsRef<[L]>) -> Option<u32> {
+        let path = path.as_ref();
+        self.common_prefix_search(path)
+            .filter(|m| m.len == path.len() || m.value_id & PREFIX_RULE != 0)
+            .last()
+            .map(|m| m.value_id & !PREFIX_RULE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> DoubleArray<u8> {
+        let keys: Vec<&[u8]> = vec![b"/", b"/api/", b"/api/admin", b"/api/v2/", b"/static/"];
+        DoubleArray::build_routes(&keys, &[1, 2, 3, 4, 5], &[false, true, false, true, true])
+    }
+
+    #[test]
+    fn prefix_rule_matches_paths_below_it() {
+        let da = routes();
+        assert_eq!(da.match_route(b"/api/users"), Some(2));
+        assert_eq!(da.match_route(b"/api/"), Some(2));
+        assert_eq!(da.match_route(b"/api/v2/users/7"), Some(4));
+        assert_eq!(da.match_route(b"/static/app.js"), Some(5));
+    }
+
+    #[test]
+    fn exact_routes_only_match_themselves() {
+        let da = routes();
+        assert_eq!(da.match_route(b"/"), Some(1));
+        assert_eq!(da.match_route(b"/api/admin"), Some(3));
+        // Falls back to the enclosing prefix rule.
+        assert_eq!(da.match_route(b"/api/admin/x"), Some(2));
+        // "/" is exact, and "/api" is not under "/api/".
+        assert_eq!(da.match_route(b"/api"), None);
+        assert_eq!(da.match_route(b"/other"), None);
+        assert_eq!(da.match_route(b""), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "route value must fit in 30 bits")]
+    fn oversized_route_value_panics() {
+        DoubleArray::<u8>::build_routes(&[b"/"], &[PREFIX_RULE], &[false]);
+    }
+}
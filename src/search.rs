@@ -1,6 +1,8 @@
+use std::ffi::c_void;
 use std::marker::PhantomData;
+use std::ops::Range;
 
-use crate::view::TrieView;
+use crate::view::{SlicePath, TrieView};
 use crate::{DoubleArray, Label};
 
 /// Result of a common prefix search match.
@@ -21,6 +23,15 @@ pub struct SearchMatch<L> {
     pub value_id: u32,
 }
 
+/// A C-compatible callback for [`for_each_prefix`](crate::DoubleArray::for_each_prefix)
+/// and [`for_each_completion`](crate::DoubleArray::for_each_completion).
+///
+/// Called once per matched key with the caller's `ctx` pointer, passed
+/// through untouched, the key as `len` labels starting at `key`, and its
+/// value_id. The key memory is only valid for the duration of the call;
+/// copy it to keep it.
+pub type KeyCallback<L> = extern "C" fn(ctx: *mut c_void, key: *const L, len: usize, value_id: u32);
+
 /// Result of probing a key in the trie.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ProbeResult {
@@ -30,10 +41,103 @@ pub struct ProbeResult {
     pub has_children: bool,
 }
 
+/// Outcome of [`match_or_prefix`](crate::DoubleArray::match_or_prefix).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The key exists, with this value_id.
+    Exact(u32),
+    /// The key is not in the trie but is a prefix of other keys.
+    PrefixOnly,
+    /// No key starts with the key.
+    Absent,
+}
+
+/// A handle to a node reached by a prefix, for resuming searches from it.
+///
+/// Obtained from `find_node`; pass it to `exact_match_from` to continue
+/// matching without re-walking the prefix. A handle is only meaningful for
+/// the trie that produced it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeRef {
+    pub(crate) index: u32,
+    pub(crate) depth: usize,
+}
+
+impl NodeRef {
+    /// Index of the node in the double array.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Number of labels consumed to reach the node.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Progress of a key matched chunk by chunk; see `exact_match_partial`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialMatch {
+    /// The labels so far form a key. Longer keys may still follow, so
+    /// `node` resumes the match if more input arrives.
+    Complete {
+        /// The value_id of the key.
+        value_id: u32,
+        /// The node reached, to pass as `resume_from`.
+        node: NodeRef,
+    },
+    /// The labels so far are a proper prefix of some key but not a key.
+    Incomplete(NodeRef),
+    /// No key starts with the labels so far; more input cannot help.
+    Dead,
+}
+
+/// Result of one tokenizer step; see `tokenize_step`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenStep {
+    /// The longest key that is a prefix of the input, if any.
+    pub longest: Option<PrefixMatch>,
+    /// Number of input labels that follow an existing path in the trie.
+    /// Always `>= longest.len`.
+    pub consumed: usize,
+    /// Whether some key extends `input[..consumed]` further, i.e. the node
+    /// reached after `consumed` labels has non-terminal children. When
+    /// `consumed == input.len()`, more input could yield a longer match.
+    pub can_extend: bool,
+}
+
+/// Structural information about the node reached by a path.
+///
+/// Returned by `inspect` for any path that exists in the trie, whether or
+/// not it is a complete key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeInfo {
+    /// Index of the node in the double array.
+    pub index: u32,
+    /// The value_id if the path is a complete key.
+    pub value_id: Option<u32>,
+    /// Number of non-terminal children.
+    pub num_children: usize,
+    /// The node's base value (XOR offset of its children).
+    pub base: u32,
+    /// The node's check value (index of its parent).
+    pub check: u32,
+}
+
+impl NodeInfo {
+    /// Returns true if the path is a complete key.
+    #[inline]
+    pub fn is_key(&self) -> bool {
+        self.value_id.is_some()
+    }
+}
+
 impl<L: Label> DoubleArray<L> {
     /// Returns a `TrieView` borrowing this trie's data.
     #[inline]
-    fn view(&self) -> TrieView<'_, L> {
+    pub(crate) fn view(&self) -> TrieView<'_, L> {
         TrieView {
             nodes: &self.nodes,
             siblings: &self.siblings,
@@ -43,29 +147,384 @@ impl<L: Label> DoubleArray<L> {
     }
 
     /// Exact match search. Returns the value_id if the key exists.
+    ///
+    /// The key's length is part of its identity: label `0` has a nonzero
+    /// code like any other label, so `b"ab\0"` and `b"ab"` are different
+    /// keys and fixed-width records may keep their padding.
     #[inline]
-    pub fn exact_match(&self, key: &[L]) -> Option<u32> {
-        self.view().exact_match(key)
+    pub fn exact_match(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match(key.as_ref())
+    }
+
+    /// Exact match of the sub-slice `buf[range]`, for parsers that track
+    /// tokens as positions in one shared buffer.
+    ///
+    /// Returns `None` if `range` is out of bounds for `buf` or decreasing,
+    /// instead of panicking; otherwise equals `exact_match(&buf[range])`.
+    pub fn exact_match_range(&self, buf: &[L], range: Range<usize>) -> Option<u32> {
+        self.view().exact_match(buf.get(range)?)
+    }
+
+    /// Returns a stored key sharing the longest possible prefix with `key`,
+    /// with its value_id: a cheap "did you mean" for a missed lookup.
+    ///
+    /// Follows `key` as far as the trie allows, then returns the first key
+    /// below the deepest node reached, in
+    /// [`predictive_search`](Self::predictive_search) order. If `key` exists,
+    /// that is `key` itself. Unlike edit-distance search, only leading labels
+    /// count. Returns `None` only for an empty trie.
+    pub fn nearest_by_prefix(&self, key: impl AsRef<[L]>) -> Option<SearchMatch<L>> {
+        let key = key.as_ref();
+        let view = self.view();
+        let shared = view.matched_prefix_len(key);
+        view.predictive_search(&key[..shared]).next()
+    }
+
+    /// Exact match for secret keys, such as API tokens in an allowlist, whose
+    /// running time does not depend on how many leading labels match.
+    ///
+    /// [`exact_match`](Self::exact_match) stops at the first mismatch, so its
+    /// timing tells an attacker how long a guessed prefix is. This variant
+    /// always takes `key.len()` steps of one node read each, continuing from
+    /// the root after a mismatch, and combines the checks without early
+    /// returns. Results equal `exact_match`. It is slower, since misses no
+    /// longer exit early, so use it only where the key is secret. The time
+    /// still depends on the key's length, and cache behaviour is outside its
+    /// control; it removes the matched-prefix signal, not every side channel.
+    pub fn exact_match_constant_time(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match_constant_time(key.as_ref())
+    }
+
+    /// Cheap pre-filter for [`exact_match`](Self::exact_match): returns
+    /// `false` if `key` certainly is not in the trie.
+    ///
+    /// Rejects keys shorter than the shortest or longer than the longest key,
+    /// and keys whose first label starts no key, without a full traversal. A
+    /// `true` result may still be a miss, but a key in the trie never gets
+    /// `false`. The length bounds are computed by a scan over all keys on the
+    /// first call and kept with the trie.
+    pub fn might_contain(&self, key: impl AsRef<[L]>) -> bool {
+        let key = key.as_ref();
+        let range = self
+            .length_range
+            .get_or_init(|| self.view().key_length_range());
+        let Some((min_len, max_len)) = *range else {
+            return false;
+        };
+        if key.len() < min_len || key.len() > max_len {
+            return false;
+        }
+        match key.first() {
+            Some(first) => self.view().traverse(std::slice::from_ref(first)).is_some(),
+            None => true,
+        }
+    }
+
+    /// Returns whether any of `keys` exists in the trie.
+    ///
+    /// Stops at the first key found. This is a convenience wrapper: it runs
+    /// one [`exact_match`](Self::exact_match) per key in turn, and costs the
+    /// same as calling it in a loop.
+    pub fn contains_any(&self, keys: &[impl AsRef<[L]>]) -> bool {
+        self.view().contains_any(keys)
+    }
+
+    /// Returns the positions in `keys` of the keys that exist in the trie,
+    /// in ascending order.
+    ///
+    /// Like [`contains_any`](Self::contains_any), a convenience wrapper over
+    /// one [`exact_match`](Self::exact_match) per key.
+    pub fn matching_indices(&self, keys: &[impl AsRef<[L]>]) -> Vec<usize> {
+        self.view().matching_indices(keys)
+    }
+
+    /// Returns one bit per key of `keys`, set if the key exists in the trie.
+    ///
+    /// Bit `i % 64` of word `i / 64` is key `i`, least significant bit
+    /// first; bits past the last key are clear. At one bit per key this is
+    /// an eighth of a `Vec<bool>` and can be combined with other masks a word
+    /// at a time.
+    pub fn exact_match_bitset(&self, keys: &[impl AsRef<[L]>]) -> Vec<u64> {
+        let view = self.view();
+        let mut bits = vec![0u64; keys.len().div_ceil(64)];
+        for (i, key) in keys.iter().enumerate() {
+            let hit = view.exact_match(key.as_ref()).is_some();
+            bits[i / 64] |= u64::from(hit) << (i % 64);
+        }
+        bits
+    }
+
+    /// Exact match of the key formed by concatenating `segments`, such as the
+    /// chunks of a rope, without copying them into one buffer.
+    ///
+    /// Segment boundaries are invisible: the result equals `exact_match` of
+    /// the concatenation, and empty segments are skipped.
+    pub fn exact_match_segments(&self, segments: &[&[L]]) -> Option<u32> {
+        let view = self.view();
+        let node_idx = view.traverse_labels(segments.iter().flat_map(|s| s.iter().copied()))?;
+        view.terminal_value(node_idx)
+    }
+
+    /// Exact match after mapping every label of `key` through `normalize`.
+    ///
+    /// Pass the same function used with
+    /// [`build_normalized`](Self::build_normalized) so that equivalent labels
+    /// match. Labels are normalized on the fly; no key is allocated.
+    #[inline]
+    pub fn exact_match_normalized(
+        &self,
+        key: impl AsRef<[L]>,
+        normalize: impl Fn(L) -> L,
+    ) -> Option<u32> {
+        let view = self.view();
+        let node_idx = view.traverse_labels(key.as_ref().iter().map(|&l| normalize(l)))?;
+        view.terminal_value(node_idx)
+    }
+
+    /// Exact match of `input` mapped label by label through `transform`,
+    /// e.g. a transliteration table, with no intermediate buffer.
+    ///
+    /// A `None` from `transform` makes the whole lookup miss. The mapping
+    /// cannot change the key's length, and `transform` is not called past
+    /// the first label the trie cannot follow.
+    pub fn exact_match_transformed(
+        &self,
+        input: &[L],
+        mut transform: impl FnMut(L) -> Option<L>,
+    ) -> Option<u32> {
+        let view = self.view();
+        let mut mapped_all = true;
+        let node_idx = view.traverse_labels(input.iter().map_while(|&l| {
+            let mapped = transform(l);
+            mapped_all = mapped.is_some();
+            mapped
+        }))?;
+        if !mapped_all {
+            return None;
+        }
+        view.terminal_value(node_idx)
     }
 
     /// Common prefix search. Returns an iterator over all prefixes of `query`
     /// that exist as keys in the trie.
-    pub fn common_prefix_search<'a>(
+    ///
+    /// `query` may be of any length, including more than `u32::MAX` labels:
+    /// positions are tracked as `usize`, and the walk stops at the first label
+    /// with no matching child, so at most `longest key + 1` labels are read.
+    pub fn common_prefix_search<'a, Q: AsRef<[L]> + ?Sized>(
         &'a self,
-        query: &'a [L],
+        query: &'a Q,
     ) -> impl Iterator<Item = PrefixMatch> + 'a {
-        self.view().common_prefix_search(query)
+        self.view().common_prefix_search(query.as_ref())
+    }
+
+    /// Common prefix search over the sub-slice `buf[range]`; see
+    /// [`exact_match_range`](Self::exact_match_range).
+    ///
+    /// Yields nothing if `range` is out of bounds for `buf` or decreasing,
+    /// even when the trie contains the empty key.
+    pub fn common_prefix_search_range<'a>(
+        &'a self,
+        buf: &'a [L],
+        range: Range<usize>,
+    ) -> impl Iterator<Item = PrefixMatch> + 'a {
+        buf.get(range)
+            .into_iter()
+            .flat_map(|query| self.view().common_prefix_search(query))
+    }
+
+    /// Common prefix search that also yields the index of each match's
+    /// terminal (leaf) node.
+    ///
+    /// Leaf indices are distinct per key and fixed for a built trie; they
+    /// equal the entries of [`build_with_leaf_map`](Self::build_with_leaf_map),
+    /// so side data such as connection-cost contexts can be attached by leaf.
+    pub fn common_prefix_search_leaves<'a, Q: AsRef<[L]> + ?Sized>(
+        &'a self,
+        query: &'a Q,
+    ) -> impl Iterator<Item = (PrefixMatch, u32)> + 'a {
+        let mut iter = self.view().common_prefix_search(query.as_ref());
+        std::iter::from_fn(move || iter.next_with_leaf())
+    }
+
+    /// Common prefix search yielding each matched prefix length with its
+    /// entry in an external `values` slice, indexed by value_id.
+    ///
+    /// # Panics
+    /// If a matched value_id is out of range for `values`.
+    pub fn common_prefix_search_values<'a, 'v, Q: AsRef<[L]> + ?Sized, V>(
+        &'a self,
+        query: &'a Q,
+        values: &'v [V],
+    ) -> impl Iterator<Item = (usize, &'v V)> + 'a
+    where
+        'v: 'a,
+    {
+        self.common_prefix_search(query)
+            .map(move |m| (m.len, &values[m.value_id as usize]))
+    }
+
+    /// Returns `(len, value_id)` for every key on the path to `key`, i.e.
+    /// every stored prefix of `key` including `key` itself, shortest first.
+    ///
+    /// The collected form of [`common_prefix_search`](Self::common_prefix_search)
+    /// for hierarchical keys such as `/a`, `/a/b`, `/a/b/c`, where each level
+    /// carries its own value. Nothing past the end of `key` is visited.
+    pub fn path_values(&self, key: impl AsRef<[L]>) -> Vec<(usize, u32)> {
+        self.view()
+            .common_prefix_search(key.as_ref())
+            .map(|m| (m.len, m.value_id))
+            .collect()
+    }
+
+    /// Calls `callback` for each prefix of `query` that exists as a key,
+    /// shortest first: the callback form of
+    /// [`common_prefix_search`](Self::common_prefix_search) for FFI.
+    ///
+    /// The key passed is `query` itself with the match length, so no key is
+    /// copied. `ctx` is never dereferenced here; it is only handed to the
+    /// callback, typically as a pointer to the C caller's state.
+    pub fn for_each_prefix(&self, query: &[L], callback: KeyCallback<L>, ctx: *mut c_void) {
+        self.view().for_each_prefix(query, callback, ctx);
+    }
+
+    /// Calls `callback` for each key that starts with `prefix`, in the order
+    /// of [`predictive_search`](Self::predictive_search): its callback form
+    /// for FFI.
+    ///
+    /// The key passed points into the search's internal buffer, so no key is
+    /// allocated per match. `ctx` is handed to the callback untouched.
+    pub fn for_each_completion(&self, prefix: &[L], callback: KeyCallback<L>, ctx: *mut c_void) {
+        self.view().for_each_completion(prefix, callback, ctx);
     }
 
     /// Predictive search. Returns an iterator over all keys that start with `prefix`.
     ///
     /// Uses sibling chain DFS to enumerate all keys sharing the given prefix.
     /// Keys are reconstructed using `CodeMapper::reverse`.
-    pub fn predictive_search<'a>(
+    ///
+    /// The order is a pre-order walk by code: a key comes before all keys
+    /// extending it, and the subtrees of a node's children follow each other
+    /// in ascending code order. Codes are assigned by label frequency (see
+    /// [`CodeMapper`](crate::CodeMapper)), so this is generally not sorted
+    /// key order, but it is fixed for a given trie, including after
+    /// serialization.
+    ///
+    /// The DFS climbs back up through parent links instead of keeping a
+    /// stack, so the iterator's memory is O(depth of the deepest key) however
+    /// wide the subtree is.
+    pub fn predictive_search<'a, Q: AsRef<[L]> + ?Sized>(
+        &'a self,
+        prefix: &'a Q,
+    ) -> impl Iterator<Item = SearchMatch<L>> + 'a {
+        self.view().predictive_search(prefix.as_ref())
+    }
+
+    /// Predictive search yielding exactly the reverse of
+    /// [`predictive_search`](Self::predictive_search)'s order.
+    ///
+    /// A key comes after all keys extending it, and children are visited in
+    /// descending code order. Since sibling chains only link forward, this
+    /// walk keeps an explicit stack holding the pending children of every
+    /// node on the current path, so its memory grows with fan-out as well as
+    /// depth.
+    pub fn predictive_search_rev<'a, Q: AsRef<[L]> + ?Sized>(
+        &'a self,
+        prefix: &'a Q,
+    ) -> impl Iterator<Item = SearchMatch<L>> + 'a {
+        self.view().predictive_search_rev(prefix.as_ref())
+    }
+
+    /// Predictive search for autocomplete: at most `max_results` keys, each
+    /// at most `max_extra_len` labels longer than `prefix`.
+    ///
+    /// Length pruning comes first: subtrees deeper than the length cap are
+    /// not walked, and the count cap then takes the first `max_results` of
+    /// the remaining keys, in [`predictive_search`](Self::predictive_search)
+    /// order. A long key therefore never uses up a result slot, and the
+    /// results are exactly the first `max_results` of the unbounded search
+    /// filtered by length.
+    pub fn predictive_search_capped<'a, Q: AsRef<[L]> + ?Sized>(
         &'a self,
-        prefix: &'a [L],
+        prefix: &'a Q,
+        max_results: usize,
+        max_extra_len: usize,
     ) -> impl Iterator<Item = SearchMatch<L>> + 'a {
-        self.view().predictive_search(prefix)
+        self.view()
+            .predictive_search_within(prefix.as_ref(), max_extra_len)
+            .take(max_results)
+    }
+
+    /// Predictive search yielding keys converted by `encode`.
+    ///
+    /// Same traversal and order as [`predictive_search`](Self::predictive_search),
+    /// but each key is handed to `encode` as a borrowed slice of the internal
+    /// key buffer, so no intermediate `Vec<L>` is allocated. For example, pass
+    /// `|labels| labels.iter().collect::<String>()` on a `char` trie.
+    pub fn predictive_search_encoded<'a, Q: AsRef<[L]> + ?Sized, T>(
+        &'a self,
+        prefix: &'a Q,
+        encode: impl Fn(&[L]) -> T + 'a,
+    ) -> impl Iterator<Item = (T, u32)> + 'a {
+        let mut iter = self.view().predictive_search(prefix.as_ref());
+        std::iter::from_fn(move || iter.next_encoded(&encode))
+    }
+
+    /// Common prefix search writing matches into `out` instead of yielding
+    /// them; returns the number written.
+    ///
+    /// Matches come in [`common_prefix_search`](Self::common_prefix_search)
+    /// order, shortest first. If `out` is too small, the search stops once it
+    /// is full, keeping the shortest matches. Nothing is allocated.
+    pub fn common_prefix_search_into(
+        &self,
+        query: impl AsRef<[L]>,
+        out: &mut [PrefixMatch],
+    ) -> usize {
+        let mut written = 0;
+        for (slot, m) in out
+            .iter_mut()
+            .zip(self.view().common_prefix_search(query.as_ref()))
+        {
+            *slot = m;
+            written += 1;
+        }
+        written
+    }
+
+    /// Predictive search writing keys into caller buffers; returns the
+    /// number of keys written.
+    ///
+    /// The keys are stored back to back in `labels`, and `out[i]` holds the
+    /// length and value_id of the `i`-th, so it starts at the sum of the
+    /// lengths before it. Keys come in
+    /// [`predictive_search`](Self::predictive_search) order. The search stops
+    /// at the first key that does not fit in `labels`, or once `out` is full,
+    /// so only whole keys are written. Nothing is allocated: the walk keeps
+    /// its path in the unused part of `labels`, so it needs no room beyond
+    /// the keys themselves.
+    pub fn predictive_search_into(
+        &self,
+        prefix: impl AsRef<[L]>,
+        labels: &mut [L],
+        out: &mut [PrefixMatch],
+    ) -> usize {
+        let path = SlicePath::new(labels);
+        let mut iter = self
+            .view()
+            .predictive_search_in(prefix.as_ref(), usize::MAX, path);
+        let mut written = 0;
+        while written < out.len() {
+            let Some(m) = iter.next_with(|key, value_id| PrefixMatch {
+                len: key.len(),
+                value_id,
+            }) else {
+                break;
+            };
+            out[written] = m;
+            written += 1;
+        }
+        written
     }
 
     /// Probe a key. Returns whether the key exists and whether it has children.
@@ -76,14 +535,172 @@ impl<L: Label> DoubleArray<L> {
     /// - `Exact`: key exists but is not a prefix of other keys
     /// - `ExactAndPrefix`: key exists and is also a prefix of other keys
     #[inline]
-    pub fn probe(&self, key: &[L]) -> ProbeResult {
-        self.view().probe(key)
+    pub fn probe(&self, key: impl AsRef<[L]>) -> ProbeResult {
+        self.view().probe(key.as_ref())
+    }
+
+    /// Tokenizer form of [`probe`](Self::probe): the key's value_id, and
+    /// whether a strictly longer key starting with `key` exists, i.e.
+    /// whether reading on can still match.
+    ///
+    /// The key's own terminal does not count, so a key that is no other
+    /// key's prefix reports `false`, in prefix-free tries too.
+    #[inline]
+    pub fn match_and_continue(&self, key: impl AsRef<[L]>) -> (Option<u32>, bool) {
+        let ProbeResult {
+            value,
+            has_children,
+        } = self.view().probe(key.as_ref());
+        (value, has_children)
+    }
+
+    /// Exact match that distinguishes why a key is missing.
+    ///
+    /// Returns [`MatchKind::PrefixOnly`] when the key is a valid prefix but
+    /// not a key itself, and [`MatchKind::Absent`] when no key starts with
+    /// it. Like [`probe`](Self::probe), without computing whether an existing
+    /// key also has children.
+    #[inline]
+    pub fn match_or_prefix(&self, key: impl AsRef<[L]>) -> MatchKind {
+        self.view().match_or_prefix(key.as_ref())
+    }
+
+    /// Returns a handle to the node reached by `prefix`, or `None` if no key
+    /// starts with `prefix`.
+    #[inline]
+    pub fn find_node(&self, prefix: impl AsRef<[L]>) -> Option<NodeRef> {
+        let prefix = prefix.as_ref();
+        self.view().traverse(prefix).map(|index| NodeRef {
+            index,
+            depth: prefix.len(),
+        })
+    }
+
+    /// Exact match resumed from `node`: returns the value_id of the key
+    /// formed by the node's prefix followed by `rest`.
+    ///
+    /// `exact_match_from(find_node(p)?, s)` equals `exact_match(p ++ s)`.
+    /// Returns `None` if `node` does not point into this trie.
+    #[inline]
+    pub fn exact_match_from(&self, node: NodeRef, rest: impl AsRef<[L]>) -> Option<u32> {
+        self.view().exact_match_from(node, rest.as_ref())
+    }
+
+    /// Matches a key that arrives in chunks, e.g. across network reads.
+    ///
+    /// Follows `chunk` from `resume_from`, or from the root if `None`, and
+    /// reports whether the labels so far form a key, may still become one,
+    /// or cannot. Feed the next chunk with the returned node; after the last
+    /// chunk the result is the one `exact_match` gives for the whole key.
+    /// A `resume_from` with an out-of-range index gives
+    /// [`Dead`](PartialMatch::Dead); one taken from another trie gives
+    /// unspecified results.
+    pub fn exact_match_partial(
+        &self,
+        chunk: impl AsRef<[L]>,
+        resume_from: Option<NodeRef>,
+    ) -> PartialMatch {
+        let chunk = chunk.as_ref();
+        let start = resume_from.unwrap_or(NodeRef { index: 0, depth: 0 });
+        let view = self.view();
+        if start.index as usize >= self.nodes.len() {
+            return PartialMatch::Dead;
+        }
+        let Some(index) = view.traverse_from(start.index, chunk.iter().copied()) else {
+            return PartialMatch::Dead;
+        };
+        let node = NodeRef {
+            index,
+            depth: start.depth + chunk.len(),
+        };
+        match view.terminal_value(index) {
+            Some(value_id) => PartialMatch::Complete { value_id, node },
+            None if view.has_children(index, false) => PartialMatch::Incomplete(node),
+            // Only the root of an empty trie: no key starts anywhere.
+            None => PartialMatch::Dead,
+        }
+    }
+
+    /// Tokenizer step: longest prefix match of `input`, the number of labels
+    /// the trie follows, and whether the dictionary continues past them.
+    ///
+    /// Equivalent to taking the last `common_prefix_search` match and probing
+    /// the deepest reachable path, in one traversal.
+    pub fn tokenize_step(&self, input: impl AsRef<[L]>) -> TokenStep {
+        self.view().tokenize_step(input.as_ref())
+    }
+
+    /// Shortest prefix match: the shallowest key that is a prefix of
+    /// `query`, for non-greedy tokenizers.
+    ///
+    /// Equals `common_prefix_search(query).next()`, but the traversal stops
+    /// at the first key found.
+    pub fn shortest_prefix_match(&self, query: impl AsRef<[L]>) -> Option<PrefixMatch> {
+        self.view().shortest_prefix_match(query.as_ref())
+    }
+
+    /// Longest key ending at `text[end_pos]` (exclusive), read right to
+    /// left through a trie from [`build_reversed`](Self::build_reversed).
+    ///
+    /// The match covers `text[end_pos - len..end_pos]`; on any other trie
+    /// the labels are simply compared in reverse order. Returns `None` if no
+    /// key ends there or `end_pos > text.len()`.
+    pub fn longest_suffix_match_at(&self, text: &[L], end_pos: usize) -> Option<PrefixMatch> {
+        let view = self.view();
+        let mut node_idx = 0u32;
+        let mut longest = view
+            .terminal_value(0)
+            .map(|value_id| PrefixMatch { len: 0, value_id });
+        for (i, &label) in text.get(..end_pos)?.iter().rev().enumerate() {
+            let code = view.code_map.get(label);
+            if code == 0 {
+                break;
+            }
+            let Some(next) = view.child(node_idx, code) else {
+                break;
+            };
+            node_idx = next;
+            if let Some(value_id) = view.terminal_value(node_idx) {
+                longest = Some(PrefixMatch {
+                    len: i + 1,
+                    value_id,
+                });
+            }
+        }
+        longest
+    }
+
+    /// Inspects an arbitrary path. Returns `None` only if the path does not
+    /// exist structurally; paths that are prefixes of keys are reported too.
+    ///
+    /// Intended for diagnostics: counting children scans the whole alphabet.
+    pub fn inspect(&self, path: impl AsRef<[L]>) -> Option<NodeInfo> {
+        self.view().inspect(path.as_ref())
+    }
+}
+
+impl DoubleArray<u8> {
+    /// Common prefix search over UTF-8 text that only yields matches ending
+    /// on a char boundary of `query`.
+    ///
+    /// A byte trie matches byte by byte, so a key that ends partway through a
+    /// multi-byte char of `query` would otherwise match, splitting the char.
+    /// A match of length `len` is kept only if `len == query.len()` or
+    /// `query[len]` is not a UTF-8 continuation byte. `query` need not be
+    /// valid UTF-8; only the byte after each match is inspected.
+    pub fn common_prefix_search_utf8<'a>(
+        &'a self,
+        query: &'a [u8],
+    ) -> impl Iterator<Item = PrefixMatch> + 'a {
+        self.common_prefix_search(query)
+            .filter(|m| query.get(m.len).is_none_or(|&b| b & 0xC0 != 0x80))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::lcg;
     use crate::DoubleArray;
 
     fn build_u8(keys: &[&[u8]]) -> DoubleArray<u8> {
@@ -96,6 +713,89 @@ mod tests {
         DoubleArray::build(&char_keys)
     }
 
+    /// Checks that every search method agrees with a brute-force reading of
+    /// `keys` for all prefixes of the keys plus a few misses.
+    fn assert_consistent(keys: &[&[u8]]) {
+        let da = build_u8(keys);
+
+        let mut queries: Vec<Vec<u8>> = keys
+            .iter()
+            .flat_map(|k| (0..=k.len()).map(move |n| k[..n].to_vec()))
+            .collect();
+        for k in keys {
+            let mut longer = k.to_vec();
+            longer.push(b'!');
+            queries.push(longer);
+        }
+        queries.push(b"zz".to_vec());
+
+        for q in &queries {
+            let expected = keys.iter().position(|k| *k == &q[..]).map(|i| i as u32);
+            assert_eq!(da.exact_match(q), expected, "exact_match {q:?}");
+
+            let extended = keys.iter().any(|k| k.len() > q.len() && k.starts_with(q));
+            let probe = da.probe(q);
+            assert_eq!(probe.value, expected, "probe.value {q:?}");
+            assert_eq!(probe.has_children, extended, "probe.has_children {q:?}");
+
+            let prefixes: Vec<PrefixMatch> = da.common_prefix_search(q).collect();
+            let expected_prefixes: Vec<PrefixMatch> = (0..=q.len())
+                .filter_map(|n| {
+                    keys.iter()
+                        .position(|k| *k == &q[..n])
+                        .map(|i| PrefixMatch {
+                            len: n,
+                            value_id: i as u32,
+                        })
+                })
+                .collect();
+            assert_eq!(prefixes, expected_prefixes, "common_prefix_search {q:?}");
+
+            let mut completions: Vec<(Vec<u8>, u32)> = da
+                .predictive_search(q)
+                .map(|m| (m.key, m.value_id))
+                .collect();
+            completions.sort();
+            let expected_completions: Vec<(Vec<u8>, u32)> = keys
+                .iter()
+                .enumerate()
+                .filter(|(_, k)| k.starts_with(q))
+                .map(|(i, k)| (k.to_vec(), i as u32))
+                .collect();
+            assert_eq!(completions, expected_completions, "predictive_search {q:?}");
+            assert_eq!(
+                extended,
+                completions.len() > usize::from(expected.is_some())
+            );
+        }
+    }
+
+    #[test]
+    fn prefix_nesting_matrix() {
+        assert_consistent(&[b"a", b"ab", b"abc", b"abcd"]);
+        assert_consistent(&[b"", b"a"]);
+        assert_consistent(&[b"x", b"xy"]);
+        assert_consistent(&[b"ab", b"abc"]);
+        assert_consistent(&[b"", b"a", b"ab", b"b"]);
+    }
+
+    #[test]
+    fn chain_head_is_found_when_codes_disagree_with_label_order() {
+        // 'c' is the most frequent label, so it gets a smaller code than 'b'
+        // even though it sorts after it.
+        assert_consistent(&[b"ab", b"ac", b"cc"]);
+        assert_consistent(&[b"ab", b"ac", b"bcc", b"ccc"]);
+    }
+
+    #[test]
+    fn wide_root_matrix() {
+        let owned: Vec<Vec<u8>> = (b'a'..b'f')
+            .flat_map(|c| (b'a'..b'o').map(move |d| vec![c, d]))
+            .collect();
+        let keys: Vec<&[u8]> = owned.iter().map(|k| k.as_slice()).collect();
+        assert_consistent(&keys);
+    }
+
     // === exact_match tests ===
 
     #[test]
@@ -106,6 +806,106 @@ mod tests {
         assert_eq!(da.exact_match(b"xyz"), Some(2));
     }
 
+    #[test]
+    fn trailing_zero_labels_are_not_terminals() {
+        // Fixed-width records padded with NUL: every length is its own key.
+        let da = build_u8(&[b"ab", b"ab\0", b"ab\0\0", b"b\0"]);
+        assert_eq!(da.exact_match(b"ab"), Some(0));
+        assert_eq!(da.exact_match(b"ab\0"), Some(1));
+        assert_eq!(da.exact_match(b"ab\0\0"), Some(2));
+        assert_eq!(da.exact_match(b"ab\0\0\0"), None);
+        assert_eq!(da.exact_match(b"b"), None);
+        assert_eq!(da.exact_match(b"b\0"), Some(3));
+        assert_ne!(da.code_map().get(0u8), 0);
+
+        let prefixes: Vec<usize> = da
+            .common_prefix_search(b"ab\0\0\0")
+            .map(|m| m.len)
+            .collect();
+        assert_eq!(prefixes, vec![2, 3, 4]);
+        let mut completions: Vec<Vec<u8>> = da.predictive_search(b"a").map(|m| m.key).collect();
+        completions.sort();
+        assert_eq!(
+            completions,
+            vec![b"ab".to_vec(), b"ab\0".to_vec(), b"ab\0\0".to_vec()]
+        );
+
+        let da = build_char(&["a", "a\0"]);
+        assert_eq!(da.exact_match(['a']), Some(0));
+        assert_eq!(da.exact_match(['a', '\0']), Some(1));
+    }
+
+    #[test]
+    fn might_contain_has_no_false_negatives() {
+        let words = ["か", "かな", "かなし", "かなしい", "かに", "な", "なし"];
+        let da = build_char(&words);
+        for w in words {
+            let key: Vec<char> = w.chars().collect();
+            assert!(da.might_contain(&key), "{w}");
+        }
+        let rejected = |w: &str| !da.might_contain(w.chars().collect::<Vec<_>>());
+        // Outside the length range 1..=4.
+        assert!(rejected(""));
+        assert!(rejected("かなしいな"));
+        // First label starts no key.
+        assert!(rejected("しい"));
+        assert!(rejected("ア"));
+        // Passes the filter but is absent: the filter is only a pre-check.
+        assert!(da.might_contain(['か', 'し']));
+        assert_eq!(da.exact_match(['か', 'し']), None);
+
+        let da = build_u8(&[b"", b"abc"]);
+        assert!(da.might_contain(b""));
+        assert!(!da.might_contain(b"abcd"));
+        assert!(!build_u8(&[]).might_contain(b""));
+    }
+
+    #[test]
+    fn exact_match_constant_time_matches_exact_match() {
+        let mut next = lcg(11);
+        let mut owned: Vec<Vec<u8>> = (0..300)
+            .map(|_| (0..next(8)).map(|_| b'a' + next(6) as u8).collect())
+            .collect();
+        owned.sort();
+        owned.dedup();
+        let keys: Vec<&[u8]> = owned.iter().map(|k| k.as_slice()).collect();
+        let da = build_u8(&keys);
+        for _ in 0..2000 {
+            // Labels beyond 'f' are unmapped; some queries run off the end.
+            let q: Vec<u8> = (0..next(10)).map(|_| b'a' + next(8) as u8).collect();
+            assert_eq!(
+                da.exact_match_constant_time(&q),
+                da.exact_match(&q),
+                "{q:?}"
+            );
+        }
+        for key in &keys {
+            assert_eq!(da.exact_match_constant_time(key), da.exact_match(key));
+        }
+        assert_eq!(build_u8(&[]).exact_match_constant_time(b""), None);
+        assert_eq!(build_u8(&[b""]).exact_match_constant_time(b""), Some(0));
+        // The root-phantom guard holds: no path through unused root slots.
+        let da = build_u8(&[b"ax"]);
+        assert_eq!(da.exact_match_constant_time(b"x"), None);
+        assert_eq!(da.exact_match_constant_time(b"xa"), None);
+    }
+
+    #[test]
+    fn nearest_by_prefix_shares_the_most_labels() {
+        let da = build_u8(&[b"apple", b"apricot", b"banana"]);
+        let near = da.nearest_by_prefix(b"apq").unwrap();
+        assert!(near.key.starts_with(b"ap"), "{:?}", near.key);
+        assert_eq!(da.exact_match(&near.key), Some(near.value_id));
+
+        let near = da.nearest_by_prefix(b"apricots").unwrap();
+        assert_eq!((near.key, near.value_id), (b"apricot".to_vec(), 1));
+        let near = da.nearest_by_prefix(b"banana").unwrap();
+        assert_eq!((near.key, near.value_id), (b"banana".to_vec(), 2));
+        // Nothing shared: any key will do.
+        assert!(da.nearest_by_prefix(b"zzz").is_some());
+        assert!(build_u8(&[]).nearest_by_prefix(b"a").is_none());
+    }
+
     #[test]
     fn exact_match_not_found() {
         let da = build_u8(&[b"abc", b"abd"]);
@@ -133,17 +933,11 @@ mod tests {
     #[test]
     fn exact_match_char_keys() {
         let da = build_char(&["あい", "あう", "かき"]);
-        assert!(da
-            .exact_match(&"あい".chars().collect::<Vec<_>>())
-            .is_some());
-        assert!(da
-            .exact_match(&"あう".chars().collect::<Vec<_>>())
-            .is_some());
-        assert!(da
-            .exact_match(&"かき".chars().collect::<Vec<_>>())
-            .is_some());
-        assert_eq!(da.exact_match(&"あ".chars().collect::<Vec<_>>()), None);
-        assert_eq!(da.exact_match(&"か".chars().collect::<Vec<_>>()), None);
+        assert!(da.exact_match("あい".chars().collect::<Vec<_>>()).is_some());
+        assert!(da.exact_match("あう".chars().collect::<Vec<_>>()).is_some());
+        assert!(da.exact_match("かき".chars().collect::<Vec<_>>()).is_some());
+        assert_eq!(da.exact_match("あ".chars().collect::<Vec<_>>()), None);
+        assert_eq!(da.exact_match("か".chars().collect::<Vec<_>>()), None);
     }
 
     #[test]
@@ -244,6 +1038,245 @@ mod tests {
         assert!(results.is_empty());
     }
 
+    #[test]
+    fn search_accepts_as_ref_keys() {
+        let da = build_u8(&[b"a", b"ab", b"abc", b"b"]);
+
+        // Arrays, references to arrays, Vecs and slices all work.
+        let stack_key: [u8; 2] = *b"ab";
+        assert_eq!(da.exact_match(stack_key), Some(1));
+        let heap_key: Vec<u8> = stack_key.into();
+        assert_eq!(da.exact_match(&heap_key), Some(1));
+        assert_eq!(da.exact_match(heap_key), Some(1));
+        assert_eq!(da.exact_match(&stack_key[..1]), Some(0));
+        assert_eq!(da.probe(stack_key).value, Some(1));
+        assert_eq!(da.inspect(stack_key).map(|i| i.is_key()), Some(true));
+
+        // Iterators borrow the query for as long as they live.
+        let query: [u8; 4] = *b"abcd";
+        let prefixes: Vec<usize> = da.common_prefix_search(&query).map(|m| m.len).collect();
+        assert_eq!(prefixes, vec![1, 2, 3]);
+        let prefix = vec![b'a'];
+        assert_eq!(da.predictive_search(&prefix).count(), 3);
+    }
+
+    #[test]
+    fn predictive_search_encoded_to_string() {
+        let da = build_char(&["あい", "あ", "あいう", "か"]);
+        let prefix: Vec<char> = "あ".chars().collect();
+        let mut results: Vec<(String, u32)> = da
+            .predictive_search_encoded(&prefix, |labels| labels.iter().collect::<String>())
+            .collect();
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                ("あ".to_string(), 0),
+                ("あい".to_string(), 1),
+                ("あいう".to_string(), 2)
+            ]
+        );
+
+        // Same keys, in the same order, as predictive_search.
+        let plain: Vec<(Vec<char>, u32)> = da
+            .predictive_search(&prefix)
+            .map(|m| (m.key, m.value_id))
+            .collect();
+        let encoded: Vec<(Vec<char>, u32)> = da
+            .predictive_search_encoded(&prefix, <[char]>::to_vec)
+            .collect();
+        assert_eq!(plain, encoded);
+    }
+
+    #[test]
+    fn tokenize_step_reports_longest_and_extension() {
+        let da = build_char(&["東", "東京", "東京都"]);
+        let input: Vec<char> = "東京タワー".chars().collect();
+        assert_eq!(
+            da.tokenize_step(&input),
+            TokenStep {
+                longest: Some(PrefixMatch {
+                    len: 2,
+                    value_id: 1
+                }),
+                consumed: 2,
+                can_extend: true,
+            }
+        );
+
+        let input: Vec<char> = "東京都庁".chars().collect();
+        let step = da.tokenize_step(&input);
+        assert_eq!(
+            step.longest,
+            Some(PrefixMatch {
+                len: 3,
+                value_id: 2
+            })
+        );
+        assert_eq!(step.consumed, 3);
+        assert!(!step.can_extend);
+
+        // Input shorter than the dictionary key: consumed all, can extend.
+        let step = da.tokenize_step(['東']);
+        assert_eq!(
+            step.longest,
+            Some(PrefixMatch {
+                len: 1,
+                value_id: 0
+            })
+        );
+        assert_eq!(step.consumed, 1);
+        assert!(step.can_extend);
+
+        let step = da.tokenize_step(['京']);
+        assert_eq!(step.longest, None);
+        assert_eq!(step.consumed, 0);
+        assert!(step.can_extend);
+    }
+
+    #[test]
+    fn tokenize_step_consumed_past_longest() {
+        let da = build_u8(&[b"a", b"abcd"]);
+        let step = da.tokenize_step(b"abcx");
+        assert_eq!(
+            step.longest,
+            Some(PrefixMatch {
+                len: 1,
+                value_id: 0
+            })
+        );
+        assert_eq!(step.consumed, 3);
+        assert!(step.can_extend);
+    }
+
+    #[test]
+    fn common_prefix_search_values_pairs_costs() {
+        let da = build_u8(&[b"a", b"ab", b"abc", b"b"]);
+        let costs: Vec<f32> = vec![1.5, 0.25, 3.0, 9.0];
+        let found: Vec<(usize, f32)> = da
+            .common_prefix_search_values(b"abcd", &costs)
+            .map(|(len, &cost)| (len, cost))
+            .collect();
+        assert_eq!(found, vec![(1, 1.5), (2, 0.25), (3, 3.0)]);
+        assert_eq!(da.common_prefix_search_values(b"x", &costs).count(), 0);
+    }
+
+    #[test]
+    fn path_values_lists_every_level() {
+        let da = build_u8(&[b"a", b"ab", b"abc", b"abcd", b"b"]);
+        assert_eq!(da.path_values(b"abc"), vec![(1, 0), (2, 1), (3, 2)]);
+        assert_eq!(da.path_values(b"abx"), vec![(1, 0), (2, 1)]);
+        assert!(da.path_values(b"c").is_empty());
+        assert!(da.path_values(b"").is_empty());
+    }
+
+    #[test]
+    fn common_prefix_search_leaves_are_stable_and_distinct() {
+        let words = ["か", "かな", "かなし", "かなしい", "かに", "な", "なし"];
+        let keys: Vec<Vec<char>> = words.iter().map(|w| w.chars().collect()).collect();
+        let (da, leaf_map) = DoubleArray::<char>::build_with_leaf_map(&keys);
+
+        let query: Vec<char> = "かなしいな".chars().collect();
+        let mut seen = std::collections::HashSet::new();
+        for start in 0..query.len() {
+            let plain: Vec<PrefixMatch> = da.common_prefix_search(&query[start..]).collect();
+            let with_leaves: Vec<(PrefixMatch, u32)> =
+                da.common_prefix_search_leaves(&query[start..]).collect();
+            assert_eq!(
+                with_leaves
+                    .iter()
+                    .map(|(m, _)| m.clone())
+                    .collect::<Vec<_>>(),
+                plain
+            );
+            for (m, leaf) in with_leaves {
+                assert_eq!(leaf, leaf_map[m.value_id as usize]);
+                seen.insert((m.value_id, leaf));
+            }
+        }
+        // か, かな, かなし, かなしい, な, なし: one leaf per key, no two alike.
+        assert_eq!(seen.len(), 6);
+        let leaves: std::collections::HashSet<u32> = seen.iter().map(|&(_, l)| l).collect();
+        assert_eq!(leaves.len(), 6);
+    }
+
+    #[test]
+    fn exact_match_from_resumes_prefix() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"abd", b"b", b"bcd"];
+        let da = build_u8(&keys);
+        let queries: Vec<&[u8]> = vec![
+            b"", b"a", b"ab", b"abc", b"abd", b"abx", b"b", b"bc", b"bcd",
+        ];
+        for q in &queries {
+            for split in 0..=q.len() {
+                let (prefix, suffix) = q.split_at(split);
+                let resumed = da
+                    .find_node(prefix)
+                    .and_then(|node| da.exact_match_from(node, suffix));
+                assert_eq!(resumed, da.exact_match(q), "{prefix:?} + {suffix:?}");
+            }
+        }
+
+        let node = da.find_node(b"ab").unwrap();
+        assert_eq!(node.depth(), 2);
+        assert_eq!(da.find_node(b"x"), None);
+        // A handle from a larger trie is rejected rather than misread.
+        let stale = NodeRef {
+            index: da.num_nodes() as u32 + 10,
+            depth: 0,
+        };
+        assert_eq!(da.exact_match_from(stale, b"a"), None);
+    }
+
+    /// Adapts a Rust closure to a [`KeyCallback`], with the closure as `ctx`.
+    extern "C" fn trampoline<L, F: FnMut(&[L], u32)>(
+        ctx: *mut c_void,
+        key: *const L,
+        len: usize,
+        value_id: u32,
+    ) {
+        // SAFETY: `ctx` is the `&mut F` passed by `collect_via_callback`, and
+        // the trie passes `len` valid labels at `key`.
+        let f = unsafe { &mut *(ctx as *mut F) };
+        f(unsafe { std::slice::from_raw_parts(key, len) }, value_id);
+    }
+
+    fn trampoline_for<L, F: FnMut(&[L], u32)>(_: &F) -> KeyCallback<L> {
+        trampoline::<L, F>
+    }
+
+    fn collect_via_callback<L: Label>(
+        run: impl FnOnce(KeyCallback<L>, *mut c_void),
+    ) -> Vec<(Vec<L>, u32)> {
+        let mut found = Vec::new();
+        let mut push = |key: &[L], value_id: u32| found.push((key.to_vec(), value_id));
+        run(trampoline_for(&push), &mut push as *mut _ as *mut c_void);
+        found
+    }
+
+    #[test]
+    fn callbacks_match_iterators() {
+        let da = build_char(&["か", "かな", "かなし", "かに", "な"]);
+        let q: Vec<char> = "かなしい".chars().collect();
+        let prefixes = collect_via_callback(|cb, ctx| da.for_each_prefix(&q, cb, ctx));
+        let expected: Vec<(Vec<char>, u32)> = da
+            .common_prefix_search(&q)
+            .map(|m| (q[..m.len].to_vec(), m.value_id))
+            .collect();
+        assert_eq!(prefixes, expected);
+        assert_eq!(prefixes.len(), 3);
+
+        for prefix in ["", "か", "かな", "ん"] {
+            let p: Vec<char> = prefix.chars().collect();
+            let completions = collect_via_callback(|cb, ctx| da.for_each_completion(&p, cb, ctx));
+            let expected: Vec<(Vec<char>, u32)> = da
+                .predictive_search(&p)
+                .map(|m| (m.key, m.value_id))
+                .collect();
+            assert_eq!(completions, expected);
+        }
+    }
+
     // === predictive_search tests ===
 
     #[test]
@@ -309,6 +1342,92 @@ mod tests {
         assert_eq!(keys, vec!["あ", "あい", "あいう"]);
     }
 
+    #[test]
+    fn predictive_search_capped_respects_both_caps() {
+        // Wide: 26 two-letter keys under "a". Deep: one chain "a" + "z" * 30.
+        let mut owned: Vec<Vec<u8>> = (b'a'..=b'z').map(|c| vec![b'a', c]).collect();
+        owned.extend((3..=31).map(|n| {
+            let mut k = vec![b'a'];
+            k.resize(n, b'z');
+            k
+        }));
+        owned.push(b"ab".repeat(3));
+        owned.push(b"b".to_vec());
+        owned.sort();
+        owned.dedup();
+        let keys: Vec<&[u8]> = owned.iter().map(|k| k.as_slice()).collect();
+        let da = build_u8(&keys);
+        let all: Vec<SearchMatch<u8>> = da.predictive_search(b"a").collect();
+
+        for max_results in [0, 1, 5, 10, 100] {
+            for max_extra_len in [0, 1, 2, 5, 40] {
+                let capped: Vec<SearchMatch<u8>> = da
+                    .predictive_search_capped(b"a", max_results, max_extra_len)
+                    .collect();
+                let expected: Vec<SearchMatch<u8>> = all
+                    .iter()
+                    .filter(|m| m.key.len() <= 1 + max_extra_len)
+                    .take(max_results)
+                    .cloned()
+                    .collect();
+                assert_eq!(capped, expected, "{max_results} {max_extra_len}");
+                assert!(capped.len() <= max_results);
+                assert!(capped.iter().all(|m| m.key.len() <= 1 + max_extra_len));
+            }
+        }
+        assert_eq!(da.predictive_search_capped(b"x", 10, 10).count(), 0);
+    }
+
+    #[test]
+    fn common_prefix_search_utf8_skips_mid_char_matches() {
+        // "あ" is E3 81 82 and "い" is E3 81 84: the keys "\xE3" and
+        // "\xE3\x81" end inside both.
+        let mut keys: Vec<&[u8]> = vec![b"\xE3", b"\xE3\x81", "あ".as_bytes(), "あい".as_bytes()];
+        keys.push(b"a");
+        keys.sort();
+        let da = build_u8(&keys);
+        let query = "あいう".as_bytes();
+        let lens =
+            |it: &mut dyn Iterator<Item = PrefixMatch>| it.map(|m| m.len).collect::<Vec<_>>();
+        assert_eq!(lens(&mut da.common_prefix_search(query)), vec![1, 2, 3, 6]);
+        assert_eq!(lens(&mut da.common_prefix_search_utf8(query)), vec![3, 6]);
+        for m in da.common_prefix_search_utf8(query) {
+            assert!(std::str::from_utf8(&query[..m.len]).is_ok());
+        }
+        // A match covering the whole query is always on a boundary.
+        assert_eq!(lens(&mut da.common_prefix_search_utf8(b"\xE3")), vec![1]);
+        assert_eq!(lens(&mut da.common_prefix_search_utf8(b"ab")), vec![1]);
+    }
+
+    #[test]
+    fn predictive_search_rev_is_exact_reverse() {
+        let da = build_char(&[
+            "",
+            "か",
+            "かな",
+            "かなし",
+            "かなしい",
+            "かに",
+            "な",
+            "なし",
+            "ん",
+        ]);
+        for prefix in ["", "か", "かな", "な", "ん", "x"] {
+            let p: Vec<char> = prefix.chars().collect();
+            let mut forward: Vec<SearchMatch<char>> = da.predictive_search(&p).collect();
+            let rev: Vec<SearchMatch<char>> = da.predictive_search_rev(&p).collect();
+            forward.reverse();
+            assert_eq!(rev, forward, "{prefix:?}");
+        }
+        // A key precedes its extensions in forward order.
+        let keys: Vec<Vec<char>> = da.predictive_search(&['か']).map(|m| m.key).collect();
+        let pos = |w: &str| {
+            keys.iter()
+                .position(|k| *k == w.chars().collect::<Vec<_>>())
+        };
+        assert!(pos("か") < pos("かな") && pos("かな") < pos("かなし"));
+    }
+
     // === probe tests ===
 
     #[test]
@@ -401,6 +1520,168 @@ mod tests {
         assert!(!r.has_children);
     }
 
+    #[test]
+    fn match_and_continue_romaji() {
+        let keys: Vec<&[u8]> = vec![b"n", b"na", b"ni", b"nu", b"shi"];
+        let da = build_u8(&keys);
+        assert_eq!(da.match_and_continue(b"n"), (Some(0), true));
+        assert_eq!(da.match_and_continue(b"na"), (Some(1), false));
+        assert_eq!(da.match_and_continue(b"sh"), (None, true));
+        assert_eq!(da.match_and_continue(b"shi"), (Some(4), false));
+        assert_eq!(da.match_and_continue(b"nn"), (None, false));
+        assert_eq!(da.match_and_continue(b""), (None, true));
+
+        // Keys stored as leaves themselves have no terminal child to skip.
+        let da = DoubleArray::<u8>::build_prefix_free(&[b"na" as &[u8], b"ni", b"shi"]);
+        assert_eq!(da.match_and_continue(b"n"), (None, true));
+        assert_eq!(da.match_and_continue(b"na"), (Some(0), false));
+        assert_eq!(da.match_and_continue(b"shi"), (Some(2), false));
+    }
+
+    #[test]
+    fn batch_matches_individual_lookups() {
+        let da = build_u8(&[b"10.0.0.1", b"10.0.0.2", b"192.168.0.1", b"::1"]);
+        let batch: Vec<&[u8]> = vec![b"10.0.0.3", b"::1", b"10.0.0", b"192.168.0.1", b"", b"::"];
+        let expected: Vec<usize> = (0..batch.len())
+            .filter(|&i| da.exact_match(batch[i]).is_some())
+            .collect();
+        assert_eq!(expected, vec![1, 3]);
+        assert_eq!(da.matching_indices(&batch), expected);
+        assert!(da.contains_any(&batch));
+
+        let misses: Vec<&[u8]> = vec![b"10.0.0.3", b"10.0.0"];
+        assert!(da.matching_indices(&misses).is_empty());
+        assert!(!da.contains_any(&misses));
+        assert!(!da.contains_any(&Vec::<&[u8]>::new()));
+    }
+
+    #[test]
+    fn exact_match_bitset_packs_hits() {
+        let stored: Vec<Vec<u8>> = (0..200u32).map(|i| format!("k{i}").into_bytes()).collect();
+        let mut sorted = stored.clone();
+        sorted.sort();
+        let da = build_u8(&sorted.iter().map(Vec::as_slice).collect::<Vec<_>>());
+
+        // Every third query misses; queries 63, 64 and 127 straddle word edges.
+        let queries: Vec<Vec<u8>> = (0..130u32)
+            .map(|i| {
+                if i % 3 == 0 {
+                    format!("x{i}").into_bytes()
+                } else {
+                    format!("k{i}").into_bytes()
+                }
+            })
+            .collect();
+        let bits = da.exact_match_bitset(&queries);
+        assert_eq!(bits.len(), 3);
+        for (i, q) in queries.iter().enumerate() {
+            let bit = bits[i / 64] >> (i % 64) & 1 == 1;
+            assert_eq!(bit, da.exact_match(q).is_some(), "query {i}");
+            assert_eq!(bit, i % 3 != 0, "query {i}");
+        }
+        assert!(bits[0] >> 63 & 1 == 0 && bits[1] & 1 == 1 && bits[1] >> 63 & 1 == 1);
+        // Bits past the last key stay clear.
+        assert_eq!(bits[2] >> 2, 0);
+
+        assert!(da.exact_match_bitset(&Vec::<&[u8]>::new()).is_empty());
+        let exactly_64: Vec<&[u8]> = vec![b"k1"; 64];
+        assert_eq!(da.exact_match_bitset(&exactly_64), vec![u64::MAX]);
+    }
+
+    #[test]
+    fn into_variants_match_iterators_and_truncate() {
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"abc", b"abd", b"b"];
+        let da = build_u8(&keys);
+        let empty = PrefixMatch {
+            len: 0,
+            value_id: u32::MAX,
+        };
+
+        let mut out = [
+            empty.clone(),
+            empty.clone(),
+            empty.clone(),
+            empty.clone(),
+            empty.clone(),
+        ];
+        let n = da.common_prefix_search_into(b"abcz", &mut out);
+        let expected: Vec<PrefixMatch> = da.common_prefix_search(b"abcz").collect();
+        assert_eq!(&out[..n], expected.as_slice());
+        assert_eq!(out[n], empty);
+        let mut small = [empty.clone(), empty.clone()];
+        assert_eq!(da.common_prefix_search_into(b"abcz", &mut small), 2);
+        assert_eq!(&small[..], &expected[..2]);
+        assert_eq!(da.common_prefix_search_into(b"abcz", &mut []), 0);
+
+        let expected: Vec<SearchMatch<u8>> = da.predictive_search(b"a").collect();
+        let mut labels = [0u8; 64];
+        let mut out = vec![empty.clone(); 8];
+        let n = da.predictive_search_into(b"a", &mut labels, &mut out);
+        assert_eq!(n, expected.len());
+        let mut start = 0;
+        for (m, e) in out[..n].iter().zip(&expected) {
+            assert_eq!(&labels[start..start + m.len], e.key.as_slice());
+            assert_eq!(m.value_id, e.value_id);
+            start += m.len;
+        }
+
+        // Room for "a" and "ab" (3 labels), not "abc" too.
+        let mut labels = [0u8; 4];
+        assert_eq!(da.predictive_search_into(b"a", &mut labels, &mut out), 2);
+        assert_eq!(&labels[..3], b"aab");
+        // Or room for two keys in `out`.
+        let mut labels = [0u8; 64];
+        assert_eq!(
+            da.predictive_search_into(b"", &mut labels, &mut out[..2]),
+            2
+        );
+        assert_eq!(da.predictive_search_into(b"x", &mut labels, &mut out), 0);
+
+        // The path lives in the unused part of `labels`: exactly the keys'
+        // total length is enough, with or without label leaves.
+        let keys: [&[u8]; 4] = [b"abc", b"abd", b"b", b"ca"];
+        for da in [
+            DoubleArray::<u8>::build(&keys),
+            DoubleArray::<u8>::build_prefix_free(&keys),
+        ] {
+            let mut labels = [0u8; 9];
+            assert_eq!(da.predictive_search_into(b"", &mut labels, &mut out), 4);
+            assert_eq!(&labels, b"abcabdbca");
+            let mut labels = [0u8; 8];
+            assert_eq!(da.predictive_search_into(b"", &mut labels, &mut out), 3);
+            assert_eq!(&labels[..7], b"abcabdb");
+            let mut labels = [0u8; 2];
+            assert_eq!(da.predictive_search_into(b"ab", &mut labels, &mut out), 0);
+        }
+    }
+
+    #[test]
+    fn exact_match_segments_ignores_boundaries() {
+        let da = build_char(&["", "かな", "かなかな", "漢字"]);
+        let key: Vec<char> = "かなかな".chars().collect();
+        for split in 0..=key.len() {
+            let (head, tail) = key.split_at(split);
+            assert_eq!(da.exact_match_segments(&[head, tail]), Some(2));
+            assert_eq!(da.exact_match_segments(&[head, &[], tail]), Some(2));
+        }
+        let kana: Vec<char> = "かな".chars().collect();
+        let kan = &kana[..1];
+        assert_eq!(da.exact_match_segments(&[kan, &['な']]), Some(1));
+        assert_eq!(da.exact_match_segments(&[kan, &['な', 'か']]), None);
+        assert_eq!(da.exact_match_segments(&[]), Some(0));
+        assert_eq!(da.exact_match_segments(&[&[], &[]]), Some(0));
+    }
+
+    #[test]
+    fn match_or_prefix_kinds() {
+        let da = build_u8(&[b"abc"]);
+        assert_eq!(da.match_or_prefix(b"abc"), MatchKind::Exact(0));
+        assert_eq!(da.match_or_prefix(b"ab"), MatchKind::PrefixOnly);
+        assert_eq!(da.match_or_prefix(b""), MatchKind::PrefixOnly);
+        assert_eq!(da.match_or_prefix(b"xyz"), MatchKind::Absent);
+        assert_eq!(da.match_or_prefix(b"abcd"), MatchKind::Absent);
+    }
+
     #[test]
     fn probe_empty_trie() {
         let da = build_u8(&[]);
@@ -414,6 +1695,118 @@ mod tests {
         );
     }
 
+    #[test]
+    fn range_queries_match_sliced_queries() {
+        let da = build_u8(&[b"", b"ab", b"abc", b"c"]);
+        let buf = b"xabcab";
+        for start in 0..=buf.len() {
+            for end in start..=buf.len() {
+                let slice = &buf[start..end];
+                assert_eq!(da.exact_match_range(buf, start..end), da.exact_match(slice));
+                assert_eq!(
+                    da.common_prefix_search_range(buf, start..end)
+                        .collect::<Vec<_>>(),
+                    da.common_prefix_search(slice).collect::<Vec<_>>()
+                );
+            }
+        }
+        assert_eq!(da.exact_match_range(buf, 1..3), Some(1));
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let invalid = [4..7, 7..7, 0..100, 3..2];
+        for range in invalid {
+            assert_eq!(da.exact_match_range(buf, range.clone()), None);
+            assert_eq!(da.common_prefix_search_range(buf, range).count(), 0);
+        }
+    }
+
+    #[test]
+    fn root_only_trie() {
+        let da = build_u8(&[]);
+        assert_eq!(da.num_nodes(), 1);
+
+        let queries: [&[u8]; 4] = [b"", b"a", b"\0", b"\xff\xff"];
+        for q in queries {
+            assert_eq!(da.exact_match(q), None);
+            assert_eq!(da.exact_match_constant_time(q), None);
+            assert!(!da.might_contain(q));
+            assert_eq!(
+                da.probe(q),
+                ProbeResult {
+                    value: None,
+                    has_children: false,
+                }
+            );
+            assert_eq!(da.match_or_prefix(q), MatchKind::Absent);
+            assert_eq!(da.common_prefix_search(q).count(), 0);
+            assert_eq!(da.predictive_search(q).count(), 0);
+            assert_eq!(da.predictive_search_rev(q).count(), 0);
+            assert!(da.path_values(q).is_empty());
+        }
+        assert!(da.find_node(b"").is_some());
+        assert!(da.find_node(b"a").is_none());
+
+        let restored = DoubleArray::<u8>::from_bytes(&da.as_bytes()).unwrap();
+        assert_eq!(restored.num_nodes(), 1);
+        assert_eq!(restored.exact_match(b""), None);
+        assert_eq!(restored.predictive_search(b"").count(), 0);
+
+        let chars = build_char(&[]);
+        assert_eq!(chars.num_nodes(), 1);
+        assert_eq!(chars.exact_match(['あ']), None);
+        assert_eq!(chars.predictive_search(&[] as &[char]).count(), 0);
+    }
+
+    // === inspect tests ===
+
+    #[test]
+    fn inspect_prefix_and_key() {
+        let da = build_u8(&[b"abc"]);
+
+        let info = da.inspect(b"ab").unwrap();
+        assert!(!info.is_key());
+        assert_eq!(info.value_id, None);
+        assert_eq!(info.num_children, 1);
+
+        let info = da.inspect(b"abc").unwrap();
+        assert!(info.is_key());
+        assert_eq!(info.value_id, Some(0));
+        assert_eq!(info.num_children, 0);
+        assert_eq!(info.check, da.inspect(b"ab").unwrap().index);
+    }
+
+    #[test]
+    fn inspect_root() {
+        let da = build_u8(&[b"a", b"b"]);
+        let info = da.inspect(b"").unwrap();
+        assert_eq!(info.index, 0);
+        assert_eq!(info.value_id, None);
+        assert_eq!(info.num_children, 2);
+    }
+
+    #[test]
+    fn inspect_missing_path() {
+        let da = build_u8(&[b"abc"]);
+        assert_eq!(da.inspect(b"abd"), None);
+        assert_eq!(da.inspect(b"x"), None);
+        assert_eq!(da.inspect(b"abcd"), None);
+    }
+
+    #[test]
+    fn inspect_never_reports_unused_root_slots() {
+        // Labels used only below the root have codes, so `base(root) ^ code`
+        // can land on an unused slot (check == 0) or on the root itself.
+        let keys: Vec<Vec<u8>> = (b'a'..b'f')
+            .flat_map(|c| (b'a'..b'o').map(move |d| vec![c, d]))
+            .collect();
+        let da = DoubleArray::<u8>::build(&keys);
+        for c in b'f'..=b'n' {
+            assert_eq!(da.inspect([c]), None, "label {:?}", c as char);
+            assert_eq!(da.exact_match([c]), None);
+            assert_eq!(da.predictive_search(&[c]).count(), 0);
+        }
+    }
+
     #[test]
     fn probe_empty_key_on_empty_trie() {
         let da = build_u8(&[]);
@@ -426,4 +1819,142 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn longest_suffix_match_at_reads_right_to_left() {
+        let keys = ["ing", "ring", "string", "g", "ng"];
+        let da = DoubleArray::<char>::build_reversed(
+            &keys
+                .iter()
+                .map(|k| k.chars().collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        );
+        let text: Vec<char> = "strings ring".chars().collect();
+
+        let m = da.longest_suffix_match_at(&text, 6).unwrap();
+        assert_eq!(
+            m,
+            PrefixMatch {
+                len: 6,
+                value_id: 2
+            }
+        );
+        let m = da.longest_suffix_match_at(&text, 12).unwrap();
+        assert_eq!(
+            m,
+            PrefixMatch {
+                len: 4,
+                value_id: 1
+            }
+        );
+        assert_eq!(da.longest_suffix_match_at(&text, 7), None);
+        assert_eq!(da.longest_suffix_match_at(&text, 0), None);
+        assert_eq!(da.longest_suffix_match_at(&text, 13), None);
+
+        // Against brute force: the longest key equal to a suffix of text[..end].
+        for end in 0..=text.len() {
+            let expected = (0..=end).find_map(|start| {
+                let s: String = text[start..end].iter().collect();
+                let value_id = keys.iter().position(|&k| k == s)? as u32;
+                Some(PrefixMatch {
+                    len: end - start,
+                    value_id,
+                })
+            });
+            assert_eq!(
+                da.longest_suffix_match_at(&text, end),
+                expected,
+                "end {end}"
+            );
+        }
+    }
+
+    #[test]
+    fn shortest_prefix_match_stops_at_first_key() {
+        let da = build_u8(&[b"a", b"ab", b"abc"]);
+        assert_eq!(
+            da.shortest_prefix_match(b"abc"),
+            Some(PrefixMatch {
+                len: 1,
+                value_id: 0
+            })
+        );
+        assert_eq!(da.shortest_prefix_match(b"b"), None);
+        assert_eq!(da.shortest_prefix_match(b""), None);
+
+        let da = build_u8(&[b"", b"xy", b"xyz"]);
+        assert_eq!(da.shortest_prefix_match(b"xyz").map(|m| m.len), Some(0));
+        let da = build_u8(&[b"xy", b"xyz"]);
+        for q in [&b"x"[..], b"xy", b"xyzw", b"xz", b"q"] {
+            assert_eq!(
+                da.shortest_prefix_match(q),
+                da.common_prefix_search(q).next(),
+                "{q:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn exact_match_transformed_maps_labels_during_traversal() {
+        let da = build_u8(&[b"ka", b"kana", b"na"]);
+        let lower = |b: u8| Some(b.to_ascii_lowercase());
+        assert_eq!(da.exact_match_transformed(b"KANA", lower), Some(1));
+        assert_eq!(da.exact_match_transformed(b"Na", lower), Some(2));
+        assert_eq!(da.exact_match_transformed(b"KAN", lower), None);
+        assert_eq!(da.exact_match_transformed(b"", lower), None);
+
+        // Only letters map; anything else aborts, even after a full path.
+        let letters = |b: u8| b.is_ascii_alphabetic().then(|| b.to_ascii_lowercase());
+        assert_eq!(da.exact_match_transformed(b"KA", letters), Some(0));
+        assert_eq!(da.exact_match_transformed(b"KA!", letters), None);
+        let mut calls = 0;
+        let counted = |b: u8| {
+            calls += 1;
+            Some(b)
+        };
+        assert_eq!(da.exact_match_transformed(b"xyz", counted), None);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn exact_match_partial_resumes_across_chunks() {
+        let keys: Vec<&[u8]> = vec![b"GET", b"GETALL", b"POST", b"PUT"];
+        let da = build_u8(&keys);
+        let queries: Vec<&[u8]> = vec![b"GET", b"GETALL", b"GETA", b"POST", b"PUTS", b"P", b""];
+        for q in &queries {
+            for split in 0..=q.len() {
+                let (first, second) = q.split_at(split);
+                let resumed = match da.exact_match_partial(first, None) {
+                    PartialMatch::Complete { node, .. } | PartialMatch::Incomplete(node) => {
+                        assert_eq!(node.depth(), split);
+                        da.exact_match_partial(second, Some(node))
+                    }
+                    PartialMatch::Dead => PartialMatch::Dead,
+                };
+                let expected = match resumed {
+                    PartialMatch::Complete { value_id, .. } => Some(value_id),
+                    _ => None,
+                };
+                assert_eq!(expected, da.exact_match(q), "{first:?} + {second:?}");
+            }
+        }
+
+        let PartialMatch::Incomplete(node) = da.exact_match_partial(b"GE", None) else {
+            panic!("\"GE\" is a proper prefix of \"GET\"");
+        };
+        assert!(matches!(
+            da.exact_match_partial(b"T", Some(node)),
+            PartialMatch::Complete { value_id: 0, .. }
+        ));
+        assert_eq!(da.exact_match_partial(b"X", Some(node)), PartialMatch::Dead);
+        assert_eq!(da.exact_match_partial(b"DELETE", None), PartialMatch::Dead);
+        let out_of_range = NodeRef {
+            index: u32::MAX,
+            depth: 0,
+        };
+        assert_eq!(
+            da.exact_match_partial(b"", Some(out_of_range)),
+            PartialMatch::Dead
+        );
+    }
 }
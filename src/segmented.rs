@@ -0,0 +1,123 @@
+use std::marker::PhantomData;
+
+use crate::{DoubleArray, Label};
+
+/// Id of a sub-sequence in a segment vocabulary; the label type of a
+/// [`SegmentedTrie`].
+pub type SegmentId = u16;
+
+/// A trie whose labels are segment ids rather than the keys' own labels.
+///
+/// Built by [`DoubleArray::build_segmented`]. Each key is split by the
+/// segmenter, e.g. a path into ids of its components, and the trie holds
+/// the id sequences. Keys that repeat whole segments (`/users/`, `/posts/`)
+/// then need one node per segment instead of one per label. The segmenter is
+/// kept and applied to queries the same way.
+pub struct SegmentedTrie<L: Label, F> {
+    trie: DoubleArray<SegmentId>,
+    segmenter: F,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Label, F: Fn(&[L]) -> Vec<SegmentId>> SegmentedTrie<L, F> {
+    /// Returns the value_id of `key`, looking up its segment ids.
+    pub fn exact_match(&self, key: impl AsRef<[L]>) -> Option<u32> {
+        self.trie.exact_match(self.segment(key))
+    }
+
+    /// Returns the segment ids of `key`, as stored in the trie.
+    pub fn segment(&self, key: impl AsRef<[L]>) -> Vec<SegmentId> {
+        (self.segmenter)(key.as_ref())
+    }
+
+    /// Returns the trie over segment ids, e.g. for a common prefix search
+    /// over the output of [`segment`](Self::segment).
+    pub fn trie(&self) -> &DoubleArray<SegmentId> {
+        &self.trie
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Builds a [`SegmentedTrie`] of `keys`, each split into segment ids by
+    /// `segmenter`.
+    ///
+    /// The keys may come in any order; `keys[i]` is assigned `value_id = i`.
+    /// The segmenter must be deterministic, and should give distinct keys
+    /// distinct id sequences, since it also segments every query.
+    ///
+    /// # Panics
+    /// - If two keys have the same segment ids.
+    /// - If there are more than 2^31 keys.
+    pub fn build_segmented<F: Fn(&[L]) -> Vec<SegmentId>>(
+        keys: &[impl AsRef<[L]>],
+        segmenter: F,
+    ) -> SegmentedTrie<L, F> {
+        let segmented: Vec<Vec<SegmentId>> = keys.iter().map(|k| segmenter(k.as_ref())).collect();
+        SegmentedTrie {
+            trie: DoubleArray::build_unsorted(&segmented),
+            segmenter,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn path_segments_share_nodes() {
+        let paths: Vec<Vec<char>> = [
+            "/users/alice/posts",
+            "/users/alice/settings",
+            "/users/bob/posts",
+            "/users/bob/settings",
+            "/posts/recent",
+            "/posts/popular",
+            "/users",
+        ]
+        .iter()
+        .map(|p| p.chars().collect())
+        .collect();
+
+        // A vocabulary of the path components, '/' included.
+        let mut vocabulary: HashMap<Vec<char>, SegmentId> = HashMap::new();
+        let split = |path: &[char]| -> Vec<Vec<char>> {
+            path.split_inclusive(|&c| c == '/')
+                .map(<[char]>::to_vec)
+                .collect()
+        };
+        for path in &paths {
+            for segment in split(path) {
+                let next = vocabulary.len() as SegmentId + 1;
+                vocabulary.entry(segment).or_insert(next);
+            }
+        }
+        // Unknown segments map to 0, which no key uses.
+        let segmenter = |path: &[char]| -> Vec<SegmentId> {
+            split(path)
+                .iter()
+                .map(|s| vocabulary.get(s).copied().unwrap_or(0))
+                .collect()
+        };
+
+        let segmented = DoubleArray::build_segmented(&paths, segmenter);
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(segmented.exact_match(path), Some(i as u32));
+        }
+        let query = |s: &str| segmented.exact_match(s.chars().collect::<Vec<_>>());
+        assert_eq!(query("/users/carol/posts"), None);
+        assert_eq!(query("/users/alice"), None);
+        assert_eq!(query("/users/alice/posts/"), None);
+
+        let chars = DoubleArray::<char>::build_unsorted(&paths);
+        assert!(
+            segmented.trie().num_used_nodes() * 3 < chars.num_used_nodes(),
+            "{} vs {}",
+            segmented.trie().num_used_nodes(),
+            chars.num_used_nodes()
+        );
+    }
+}
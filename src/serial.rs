@@ -1,7 +1,17 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::node::MASK;
 use crate::{CodeMapper, DoubleArray, Label, Node, TrieError};
 
 pub(crate) const MAGIC: &[u8; 4] = b"LXTR";
 pub(crate) const VERSION: u8 = 2;
+/// Version of [`DoubleArray::as_bytes_compact`]: v2 with a varint siblings section.
+pub(crate) const VERSION_COMPACT: u8 = 3;
+/// v2 for a trie with values on label nodes, which readers of v2 would miss.
+pub(crate) const VERSION_LABEL_LEAVES: u8 = 4;
+/// v3 for a trie with values on label nodes.
+pub(crate) const VERSION_COMPACT_LABEL_LEAVES: u8 = 5;
 /// Header: magic(4) + version(1) + reserved(3) + nodes_len(4) + siblings_len(4) + code_map_len(4) + reserved(4) = 24
 pub(crate) const HEADER_SIZE: usize = 24;
 
@@ -16,7 +26,90 @@ unsafe fn as_byte_slice<T>(slice: &[T]) -> &[u8] {
     std::slice::from_raw_parts(slice.as_ptr() as *const u8, std::mem::size_of_val(slice))
 }
 
+/// Size in bytes of each section of a serialized trie, from
+/// [`DoubleArray::section_sizes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// The node array, 8 bytes per slot.
+    pub nodes: usize,
+    /// The sibling array, 4 bytes per slot.
+    pub siblings: usize,
+    /// The code map.
+    pub code_map: usize,
+}
+
+impl SectionSizes {
+    /// Returns the total serialized size: the sections plus the fixed header.
+    pub fn total(&self) -> usize {
+        HEADER_SIZE + self.nodes + self.siblings + self.code_map
+    }
+}
+
 impl<L: Label> DoubleArray<L> {
+    /// Returns the size in bytes of the output of [`as_bytes`](Self::as_bytes),
+    /// without serializing.
+    pub fn serialized_size(&self) -> usize {
+        self.section_sizes().total()
+    }
+
+    /// Returns how many bytes the nodes, siblings and code map each
+    /// contribute to [`serialized_size`](Self::serialized_size), without
+    /// serializing.
+    pub fn section_sizes(&self) -> SectionSizes {
+        SectionSizes {
+            nodes: std::mem::size_of_val(self.nodes.as_slice()),
+            siblings: std::mem::size_of_val(self.siblings.as_slice()),
+            code_map: self.code_map.serialized_size(),
+        }
+    }
+
+    /// Returns a lower bound on the serialized size of the trie that
+    /// `build(keys)` would produce, without building it.
+    ///
+    /// The code map section is computed exactly from the largest and distinct
+    /// labels. The node and sibling sections are counted at one slot per trie
+    /// node (the root, one node per distinct key prefix, and one terminal per
+    /// distinct key), which every build needs; base placement then only adds
+    /// holes. The result never exceeds [`serialized_size`](Self::serialized_size)
+    /// of the built trie, and for typical inputs the actual size is less than
+    /// twice the estimate.
+    ///
+    /// The keys need not be sorted; a sorted copy of the references is made
+    /// to count shared prefixes, so this costs O(n log n) comparisons.
+    pub fn estimate_size(keys: &[impl AsRef<[L]>]) -> usize {
+        let mut sorted: Vec<&[L]> = keys.iter().map(AsRef::as_ref).collect();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        let mut seen: Vec<bool> = Vec::new();
+        let mut distinct = 0usize;
+        // Root, plus one terminal per key.
+        let mut node_slots = 1 + sorted.len();
+        let mut prev: &[L] = &[];
+        for &key in &sorted {
+            let shared = prev.iter().zip(key).take_while(|(a, b)| a == b).count();
+            node_slots += key.len() - shared;
+            prev = key;
+            for &label in key {
+                let v = <L as Into<u32>>::into(label) as usize;
+                if v >= seen.len() {
+                    seen.resize(v + 1, false);
+                }
+                if !seen[v] {
+                    seen[v] = true;
+                    distinct += 1;
+                }
+            }
+        }
+
+        // Mirrors CodeMapper::serialized_size: 12-byte header, the label table
+        // (max label + 1 entries) and the reverse table (distinct + terminal).
+        let code_map_size = 12 + (seen.len() + distinct + 1) * 4;
+        let slot_size = std::mem::size_of::<Node>() + std::mem::size_of::<u32>();
+
+        HEADER_SIZE + node_slots * slot_size + code_map_size
+    }
+
     /// Serializes the double-array trie to a byte vector.
     ///
     /// Format (v2):
@@ -33,44 +126,27 @@ impl<L: Label> DoubleArray<L> {
     /// 24+N    S     siblings data (each: u32 LE)
     /// 24+N+S  C     code_map data
     /// ```
+    ///
+    /// A trie that stores values on label nodes, as
+    /// [`build_prefix_free`](Self::build_prefix_free) does, is written with
+    /// version `0x04` instead. The layout is the same, but a v2 reader only
+    /// finds values on terminal children and would silently miss those keys.
     pub fn as_bytes(&self) -> Vec<u8> {
         // SAFETY: Node is #[repr(C)] (two u32, 8 bytes, no padding).
         //         u32 is 4 bytes with no padding.
         //         LE platform is enforced by the crate-level compile_error.
         let nodes_raw = unsafe { as_byte_slice(&self.nodes) };
         let siblings_raw = unsafe { as_byte_slice(&self.siblings) };
-        let code_map_size = self.code_map.serialized_size();
-
-        debug_assert!(
-            nodes_raw.len() <= u32::MAX as usize,
-            "nodes section exceeds u32::MAX bytes"
-        );
-        debug_assert!(
-            siblings_raw.len() <= u32::MAX as usize,
-            "siblings section exceeds u32::MAX bytes"
-        );
-        debug_assert!(
-            code_map_size <= u32::MAX as usize,
-            "code_map section exceeds u32::MAX bytes"
-        );
 
         let total = HEADER_SIZE
             .checked_add(nodes_raw.len())
             .and_then(|s| s.checked_add(siblings_raw.len()))
-            .and_then(|s| s.checked_add(code_map_size))
+            .and_then(|s| s.checked_add(self.code_map.serialized_size()))
             .expect("total serialized size exceeds usize::MAX");
         let mut buf = Vec::with_capacity(total);
 
-        // Header (24 bytes)
-        buf.extend_from_slice(MAGIC);
-        buf.push(VERSION);
-        buf.extend_from_slice(&[0, 0, 0]); // reserved
-        buf.extend_from_slice(&(nodes_raw.len() as u32).to_le_bytes());
-        buf.extend_from_slice(&(siblings_raw.len() as u32).to_le_bytes());
-        buf.extend_from_slice(&(code_map_size as u32).to_le_bytes());
-        buf.extend_from_slice(&[0, 0, 0, 0]); // reserved
-
         // Data sections — zero intermediate allocations
+        buf.extend_from_slice(&self.header());
         buf.extend_from_slice(nodes_raw);
         buf.extend_from_slice(siblings_raw);
         self.code_map.write_to(&mut buf);
@@ -78,8 +154,231 @@ impl<L: Label> DoubleArray<L> {
         buf
     }
 
-    /// Deserializes a double-array trie from a byte slice.
+    /// Writes the serialized trie to `w`.
+    ///
+    /// Produces exactly the bytes of [`as_bytes`](Self::as_bytes), but streams
+    /// the nodes and siblings sections straight from memory instead of
+    /// assembling a second copy of the trie in a `Vec<u8>`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // SAFETY: see as_bytes.
+        let nodes_raw = unsafe { as_byte_slice(&self.nodes) };
+        let siblings_raw = unsafe { as_byte_slice(&self.siblings) };
+
+        w.write_all(&self.header())?;
+        w.write_all(nodes_raw)?;
+        w.write_all(siblings_raw)?;
+        // The code map is small; serialize it through a scratch buffer.
+        w.write_all(&self.code_map.as_bytes())
+    }
+
+    /// Serializes the trie in the v3 format, which varint-encodes the
+    /// siblings section.
+    ///
+    /// The header, nodes and code map are as in v2 (see
+    /// [`as_bytes`](Self::as_bytes)), with version `0x03` (`0x05` for a trie
+    /// with values on label nodes); `siblings_len` is
+    /// the encoded length. Each sibling link is written as an unsigned LEB128
+    /// varint: 0 for no sibling, otherwise one more than the zigzag-encoded
+    /// offset from the entry's own index. Siblings sit near each other, so
+    /// most links take one byte instead of four.
+    ///
+    /// [`from_bytes`](Self::from_bytes) reads every version and decodes the
+    /// siblings into an owned array. The zero-copy loaders,
+    /// [`DoubleArrayRef::from_bytes_ref`](crate::DoubleArrayRef::from_bytes_ref)
+    /// and [`OwnedAlignedTrie`](crate::OwnedAlignedTrie), need the raw `u32`
+    /// layout and reject v3 and v5 with [`TrieError::InvalidVersion`].
+    pub fn as_bytes_compact(&self) -> Vec<u8> {
+        let mut siblings = Vec::with_capacity(self.siblings.len());
+        for (i, &sib) in self.siblings.iter().enumerate() {
+            let code = if sib == 0 {
+                0
+            } else {
+                zigzag(i64::from(sib) - i as i64) + 1
+            };
+            write_varint(&mut siblings, code);
+        }
+        // SAFETY: see as_bytes.
+        let nodes_raw = unsafe { as_byte_slice(&self.nodes) };
+
+        let mut buf = Vec::with_capacity(
+            HEADER_SIZE + nodes_raw.len() + siblings.len() + self.code_map.serialized_size(),
+        );
+        let version = if self.has_label_leaves() {
+            VERSION_COMPACT_LABEL_LEAVES
+        } else {
+            VERSION_COMPACT
+        };
+        buf.extend_from_slice(&self.header_with(version, siblings.len()));
+        buf.extend_from_slice(nodes_raw);
+        buf.extend_from_slice(&siblings);
+        self.code_map.write_to(&mut buf);
+        buf
+    }
+
+    /// Builds a trie from sorted keys and streams its serialized form to `w`.
+    ///
+    /// Equivalent to `build(keys).write_to(w)`: the trie is built in memory,
+    /// then written without an intermediate serialized copy. That saves the
+    /// copy `build(keys).as_bytes()` makes after the build, but not the
+    /// build's own working buffers, which are usually the larger peak (see
+    /// the `memory` bench). Construction itself is not external-memory; the
+    /// whole node array still resides in memory while it is being built.
+    ///
+    /// # Panics
+    /// Same as [`build`](Self::build).
+    pub fn build_to_writer<W: Write>(keys: &[impl AsRef<[L]>], w: &mut W) -> io::Result<()> {
+        Self::build(keys).write_to(w)
+    }
+
+    /// Encodes the 24-byte v2 (or v4) header for this trie.
+    fn header(&self) -> [u8; HEADER_SIZE] {
+        let version = if self.has_label_leaves() {
+            VERSION_LABEL_LEAVES
+        } else {
+            VERSION
+        };
+        self.header_with(version, std::mem::size_of_val(self.siblings.as_slice()))
+    }
+
+    /// Whether any value is stored on a label node rather than on the
+    /// terminal child at its parent's `base ^ 0`.
+    fn has_label_leaves(&self) -> bool {
+        self.nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .any(|(i, n)| n.is_leaf() && self.nodes[n.check() as usize].base() != i as u32)
+    }
+
+    /// Encodes a header with the given version and siblings section length.
+    fn header_with(&self, version: u8, siblings_len: usize) -> [u8; HEADER_SIZE] {
+        let nodes_len = std::mem::size_of_val(self.nodes.as_slice());
+        let code_map_len = self.code_map.serialized_size();
+
+        // Section sizes are stored as u32; refuse to write a truncated header.
+        assert!(
+            nodes_len <= u32::MAX as usize,
+            "nodes section exceeds u32::MAX bytes"
+        );
+        assert!(
+            siblings_len <= u32::MAX as usize,
+            "siblings section exceeds u32::MAX bytes"
+        );
+        assert!(
+            code_map_len <= u32::MAX as usize,
+            "code_map section exceeds u32::MAX bytes"
+        );
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4] = version;
+        // 5..8 reserved
+        header[8..12].copy_from_slice(&(nodes_len as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(siblings_len as u32).to_le_bytes());
+        header[16..20].copy_from_slice(&(code_map_len as u32).to_le_bytes());
+        // 20..24 reserved
+        header
+    }
+
+    /// Deserializes a double-array trie from a byte slice, in the v2/v4
+    /// format of [`as_bytes`](Self::as_bytes) or the v3/v5 format of
+    /// [`as_bytes_compact`](Self::as_bytes_compact).
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, TrieError> {
+        let layout = Layout::parse_any(bytes)?;
+
+        let nodes =
+            deserialize_nodes(&bytes[layout.nodes.clone()]).ok_or(TrieError::TruncatedData)?;
+        let siblings_raw = &bytes[layout.siblings.clone()];
+        let siblings = if layout.compact_siblings {
+            decode_siblings(siblings_raw, layout.node_count)
+        } else {
+            deserialize_u32_slice(siblings_raw)
+        }
+        .ok_or(TrieError::TruncatedData)?;
+        let (code_map, _consumed) =
+            CodeMapper::from_bytes(&bytes[layout.code_map]).ok_or(TrieError::TruncatedData)?;
+
+        Ok(Self::new(nodes, siblings, code_map))
+    }
+
+    /// Rewrites the value_id of every key in a serialized trie in place,
+    /// replacing each `v` with `f(v)`.
+    ///
+    /// Walks the nodes section of `bytes` and rewrites the `base` field of
+    /// every leaf (IS_LEAF set) directly, so remapping values does not need a
+    /// [`from_bytes`](Self::from_bytes) and [`as_bytes`](Self::as_bytes) round
+    /// trip. The structure and the other sections are left untouched. `bytes`
+    /// need not be aligned.
+    ///
+    /// # Errors
+    /// Same as [`from_bytes`](Self::from_bytes), checked before anything is
+    /// written; the code map is not validated.
+    ///
+    /// All new values are computed and checked before the first one is
+    /// written, so a panic leaves `bytes` unchanged; this holds one `u32` per
+    /// leaf in memory meanwhile.
+    ///
+    /// # Panics
+    /// If `f` panics or returns a value that does not fit in 31 bits.
+    pub fn transform_values_in_bytes(
+        bytes: &mut [u8],
+        f: impl Fn(u32) -> u32,
+    ) -> Result<(), TrieError> {
+        let layout = Layout::parse_any(bytes)?;
+        let nodes = &mut bytes[layout.nodes];
+        let base_of = |node: &[u8]| u32::from_le_bytes(node[0..4].try_into().unwrap());
+        let is_leaf = |node: &&[u8]| base_of(node) & !MASK != 0;
+
+        let values: Vec<u32> = nodes
+            .chunks_exact(std::mem::size_of::<Node>())
+            .filter(is_leaf)
+            .map(|node| {
+                let value_id = f(base_of(node) & MASK);
+                assert!(value_id <= MASK, "value_id must fit in 31 bits");
+                value_id
+            })
+            .collect();
+
+        let leaves = nodes
+            .chunks_exact_mut(std::mem::size_of::<Node>())
+            .filter(|node| is_leaf(&&node[..]));
+        for (node, value_id) in leaves.zip(values) {
+            node[0..4].copy_from_slice(&(!MASK | value_id).to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Validated section layout of an LXTR buffer.
+pub(crate) struct Layout {
+    /// Number of nodes (and siblings entries).
+    pub(crate) node_count: usize,
+    pub(crate) nodes: Range<usize>,
+    pub(crate) siblings: Range<usize>,
+    pub(crate) code_map: Range<usize>,
+    /// Whether the siblings section is varint-encoded (v3/v5) rather than raw.
+    pub(crate) compact_siblings: bool,
+}
+
+impl Layout {
+    /// Parses and validates the header of a v2 or v4 buffer, whose sections
+    /// can be used in place.
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self, TrieError> {
+        let layout = Self::parse_any(bytes)?;
+        if layout.compact_siblings {
+            return Err(TrieError::InvalidVersion);
+        }
+        Ok(layout)
+    }
+
+    /// Parses and validates the header of a v2, v3, v4 or v5 buffer.
+    ///
+    /// Checks the magic and version, that every section lies within `bytes`,
+    /// and that the nodes section holds a non-zero number of whole entries
+    /// (search logic assumes a root node at index 0). For raw siblings, the
+    /// section must hold as many entries; a compact siblings section is only
+    /// checked when decoded. The code map section itself is not decoded.
+    pub(crate) fn parse_any(bytes: &[u8]) -> Result<Self, TrieError> {
         if bytes.len() < HEADER_SIZE {
             return Err(TrieError::TruncatedData);
         }
@@ -88,9 +387,11 @@ impl<L: Label> DoubleArray<L> {
             return Err(TrieError::InvalidMagic);
         }
 
-        if bytes[4] != VERSION {
-            return Err(TrieError::InvalidVersion);
-        }
+        let compact_siblings = match bytes[4] {
+            VERSION | VERSION_LABEL_LEAVES => false,
+            VERSION_COMPACT | VERSION_COMPACT_LABEL_LEAVES => true,
+            _ => return Err(TrieError::InvalidVersion),
+        };
 
         let nodes_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
         let siblings_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
@@ -105,31 +406,78 @@ impl<L: Label> DoubleArray<L> {
             return Err(TrieError::TruncatedData);
         }
 
-        let mut offset = HEADER_SIZE;
-
-        let nodes = deserialize_nodes(&bytes[offset..offset + nodes_len])
-            .ok_or(TrieError::TruncatedData)?;
-        offset += nodes_len;
-
-        let siblings = deserialize_u32_slice(&bytes[offset..offset + siblings_len])
-            .ok_or(TrieError::TruncatedData)?;
-        offset += siblings_len;
-
-        let (code_map, _consumed) = CodeMapper::from_bytes(&bytes[offset..offset + code_map_len])
-            .ok_or(TrieError::TruncatedData)?;
-
-        // Search logic assumes a root node at index 0
-        if nodes.is_empty() {
+        if !nodes_len.is_multiple_of(std::mem::size_of::<Node>()) {
             return Err(TrieError::TruncatedData);
         }
 
-        // nodes and siblings must be parallel arrays of equal length
-        if siblings.len() != nodes.len() {
+        let node_count = nodes_len / std::mem::size_of::<Node>();
+        // nodes and siblings must be parallel arrays of equal length, with a root
+        let raw_siblings_ok = compact_siblings || siblings_len == node_count * 4;
+        if node_count == 0 || !raw_siblings_ok {
             return Err(TrieError::TruncatedData);
         }
 
-        Ok(Self::new(nodes, siblings, code_map))
+        let siblings_start = HEADER_SIZE + nodes_len;
+        let code_map_start = siblings_start + siblings_len;
+        Ok(Self {
+            node_count,
+            nodes: HEADER_SIZE..siblings_start,
+            siblings: siblings_start..code_map_start,
+            code_map: code_map_start..expected_size,
+            compact_siblings,
+        })
+    }
+}
+
+/// Maps a signed offset to an unsigned one, small magnitudes first.
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+/// Appends `n` as an unsigned LEB128 varint.
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Decodes a v3 siblings section of exactly `count` varints.
+fn decode_siblings(bytes: &[u8], count: usize) -> Option<Vec<u32>> {
+    // Every varint takes at least one byte.
+    if bytes.len() < count {
+        return None;
+    }
+    let mut out = Vec::with_capacity(count);
+    let mut pos = 0;
+    for i in 0..count {
+        let mut code = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = *bytes.get(pos)?;
+            pos += 1;
+            // Sibling links need at most 34 bits; reject longer encodings.
+            if shift > 35 {
+                return None;
+            }
+            code |= u64::from(byte & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let sib = match code {
+            0 => 0,
+            _ => u32::try_from(i as i64 + unzigzag(code - 1)).ok()?,
+        };
+        out.push(sib);
     }
+    (pos == bytes.len()).then_some(out)
 }
 
 fn deserialize_nodes(bytes: &[u8]) -> Option<Vec<Node>> {
@@ -175,6 +523,83 @@ mod tests {
         assert_eq!(da.siblings, da2.siblings);
     }
 
+    #[test]
+    fn section_sizes_add_up() {
+        let keys: Vec<Vec<char>> = ["か", "かな", "かなし", "漢字", "\u{10FFFF}"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let da = DoubleArray::<char>::build(&keys);
+        let sizes = da.section_sizes();
+        assert_eq!(
+            HEADER_SIZE + sizes.nodes + sizes.siblings + sizes.code_map,
+            da.serialized_size()
+        );
+        assert_eq!(sizes.total(), da.serialized_size());
+
+        let bytes = da.as_bytes();
+        let layout = Layout::parse(&bytes).unwrap();
+        assert_eq!(sizes.nodes, layout.nodes.len());
+        assert_eq!(sizes.siblings, layout.siblings.len());
+        assert_eq!(sizes.code_map, layout.code_map.len());
+    }
+
+    #[test]
+    fn transform_values_in_bytes_remaps_leaves() {
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"abc", b"b", b"bc"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let mut bytes = da.as_bytes();
+        DoubleArray::<u8>::transform_values_in_bytes(&mut bytes, |v| v * 10 + 7).unwrap();
+
+        let da2 = DoubleArray::<u8>::from_bytes(&bytes).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da2.exact_match(key), Some(i as u32 * 10 + 7));
+        }
+        assert_eq!(da2.exact_match(b"c"), None);
+        assert_eq!(da2.predictive_search(b"a").count(), 3);
+        // Only leaf base fields changed.
+        let original = da.as_bytes();
+        let layout = Layout::parse(&original).unwrap();
+        assert_eq!(
+            bytes[layout.siblings.start..],
+            original[layout.siblings.start..]
+        );
+    }
+
+    #[test]
+    fn transform_values_in_bytes_rejects_bad_data() {
+        let mut bytes = b"NOPE".repeat(8);
+        assert_eq!(
+            DoubleArray::<u8>::transform_values_in_bytes(&mut bytes, |v| v),
+            Err(TrieError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "value_id must fit in 31 bits")]
+    fn transform_values_in_bytes_oversized_value_panics() {
+        let mut bytes = DoubleArray::<u8>::build(&[b"a"]).as_bytes();
+        let _ = DoubleArray::<u8>::transform_values_in_bytes(&mut bytes, |_| 1 << 31);
+    }
+
+    #[test]
+    fn transform_values_in_bytes_panic_leaves_bytes_unchanged() {
+        let keys: Vec<&[u8]> = vec![b"a", b"b", b"c"];
+        let original = DoubleArray::<u8>::build(&keys).as_bytes();
+        let mut bytes = original.clone();
+        // Fails on the third leaf, after the first two would have been written.
+        let calls = std::cell::Cell::new(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            DoubleArray::<u8>::transform_values_in_bytes(&mut bytes, |v| {
+                calls.set(calls.get() + 1);
+                assert!(calls.get() < 3, "third leaf");
+                v + 1
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(bytes, original);
+    }
+
     #[test]
     fn round_trip_u8() {
         let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bc"];
@@ -205,6 +630,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn round_trip_max_char() {
+        let keys: Vec<Vec<char>> = vec![vec!['a'], vec!['a', '\u{10FFFF}'], vec!['\u{10FFFF}']];
+        let da = DoubleArray::<char>::build(&keys);
+        let bytes = da.as_bytes();
+        // The label table covers every codepoint up to U+10FFFF: 0x110000
+        // entries of 4 bytes for three keys. build_partitioned avoids this for
+        // sparse high codepoints; the plain build keeps the dense table.
+        assert!(bytes.len() > 0x11_0000 * 4);
+        assert_eq!(bytes.len(), da.serialized_size());
+
+        let da2 = DoubleArray::<char>::from_bytes(&bytes).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da2.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(da2.exact_match(['\u{10FFFE}']), None);
+        let found: Vec<Vec<char>> = da2.predictive_search(&['a']).map(|m| m.key).collect();
+        assert_eq!(found, vec![vec!['a'], vec!['a', '\u{10FFFF}']]);
+        assert_eq!(
+            da2.common_prefix_search(&['a', '\u{10FFFF}', 'b']).count(),
+            2
+        );
+    }
+
     fn build_empty_u8() -> DoubleArray<u8> {
         let keys: Vec<&[u8]> = vec![];
         DoubleArray::<u8>::build(&keys)
@@ -274,6 +723,221 @@ mod tests {
         assert_eq!(results.len(), 4); // "n", "na", "ni", "nu"
     }
 
+    #[test]
+    fn serialized_size_matches_as_bytes() {
+        let keys: Vec<&[u8]> = vec![b"a", b"ab", b"abc", b"b", b"bc"];
+        let da = DoubleArray::<u8>::build(&keys);
+        assert_eq!(da.serialized_size(), da.as_bytes().len());
+        assert_eq!(
+            build_empty_u8().serialized_size(),
+            build_empty_u8().as_bytes().len()
+        );
+    }
+
+    fn assert_estimate_within_factor<L: crate::Label>(keys: &[Vec<L>]) {
+        let estimate = DoubleArray::<L>::estimate_size(keys);
+        let actual = DoubleArray::<L>::build(keys).serialized_size();
+        assert!(
+            estimate <= actual && actual < estimate * 2,
+            "estimate {estimate} vs actual {actual}"
+        );
+    }
+
+    #[test]
+    fn estimate_size_within_factor() {
+        assert_estimate_within_factor::<u8>(&[]);
+        assert_estimate_within_factor::<u8>(&[b"abc".to_vec()]);
+
+        let romaji: Vec<Vec<u8>> = ["a", "ka", "ki", "n", "na", "ni", "shi", "tsu"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        assert_estimate_within_factor(&romaji);
+
+        let mut words: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| format!("w{}", i.wrapping_mul(2_654_435_761) % 100_000).into_bytes())
+            .collect();
+        words.sort();
+        words.dedup();
+        assert_estimate_within_factor(&words);
+
+        let mut kana: Vec<Vec<char>> = (0..1000u32)
+            .map(|i| {
+                (0..(i % 5 + 2))
+                    .map(|j| char::from_u32(0x3041 + (i * 7 + j * 13) % 83).unwrap())
+                    .collect()
+            })
+            .collect();
+        kana.sort();
+        kana.dedup();
+        assert_estimate_within_factor(&kana);
+
+        // Order and duplicates do not change the estimate.
+        let mut shuffled = words.clone();
+        shuffled.reverse();
+        shuffled.push(words[0].clone());
+        assert_eq!(
+            DoubleArray::<u8>::estimate_size(&shuffled),
+            DoubleArray::<u8>::estimate_size(&words)
+        );
+    }
+
+    #[test]
+    fn write_to_matches_as_bytes() {
+        let keys: Vec<&[u8]> = vec![b"n", b"na", b"ni", b"nu", b"shi"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let mut out = Vec::new();
+        da.write_to(&mut out).unwrap();
+        assert_eq!(out, da.as_bytes());
+
+        let mut empty = Vec::new();
+        build_empty_u8().write_to(&mut empty).unwrap();
+        assert_eq!(empty, build_empty_u8().as_bytes());
+    }
+
+    #[test]
+    fn build_to_writer_round_trip() {
+        let keys: Vec<Vec<char>> = vec![
+            "あ".chars().collect(),
+            "あい".chars().collect(),
+            "か".chars().collect(),
+        ];
+        let mut out = Vec::new();
+        DoubleArray::<char>::build_to_writer(&keys, &mut out).unwrap();
+        assert_eq!(out, DoubleArray::<char>::build(&keys).as_bytes());
+
+        let da = DoubleArray::<char>::from_bytes(&out).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(da.exact_match(key), Some(i as u32));
+        }
+    }
+
+    #[test]
+    fn write_to_propagates_io_errors() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WriteZero, "full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let da = DoubleArray::<u8>::build(&[b"a"]);
+        assert!(da.write_to(&mut Full).is_err());
+    }
+
+    #[test]
+    fn compact_round_trip_shrinks_siblings() {
+        let mut hiragana: Vec<Vec<char>> = (0..3000u32)
+            .map(|i| {
+                let mut n = i.wrapping_mul(2_654_435_761);
+                (0..1 + i % 5)
+                    .map(|_| {
+                        let c = char::from_u32(0x3042 + n % 80).unwrap();
+                        n /= 80;
+                        c
+                    })
+                    .collect()
+            })
+            .collect();
+        hiragana.sort();
+        hiragana.dedup();
+        let da = DoubleArray::<char>::build(&hiragana);
+
+        let plain = da.as_bytes();
+        let compact = da.as_bytes_compact();
+        assert_eq!(compact[4], VERSION_COMPACT);
+        let plain_layout = Layout::parse(&plain).unwrap();
+        let compact_layout = Layout::parse_any(&compact).unwrap();
+        assert_eq!(compact_layout.nodes, plain_layout.nodes);
+        assert!(
+            compact_layout.siblings.len() * 3 < plain_layout.siblings.len(),
+            "{} vs {}",
+            compact_layout.siblings.len(),
+            plain_layout.siblings.len()
+        );
+        assert!(compact.len() < plain.len());
+
+        let restored = DoubleArray::<char>::from_bytes(&compact).unwrap();
+        assert_eq!(restored.nodes, da.nodes);
+        assert_eq!(restored.siblings, da.siblings);
+        assert_eq!(restored.as_bytes(), plain);
+        for (i, key) in hiragana.iter().enumerate() {
+            assert_eq!(restored.exact_match(key), Some(i as u32));
+        }
+        let empty = build_empty_u8();
+        let restored = DoubleArray::<u8>::from_bytes(&empty.as_bytes_compact()).unwrap();
+        assert_eq!(restored.siblings, empty.siblings);
+    }
+
+    #[test]
+    fn compact_format_rejects_bad_siblings() {
+        let da = DoubleArray::<u8>::build(&[b"ab" as &[u8], b"ac", b"b"]);
+        let compact = da.as_bytes_compact();
+        let layout = Layout::parse_any(&compact).unwrap();
+
+        // The zero-copy loaders need raw siblings.
+        assert!(matches!(
+            crate::DoubleArrayRef::<u8>::from_bytes_ref(&compact),
+            Err(TrieError::InvalidVersion)
+        ));
+
+        // A varint running into the code map, or one too few entries.
+        let mut unterminated = compact.clone();
+        unterminated[layout.siblings.end - 1] |= 0x80;
+        let mut short = compact.clone();
+        short.remove(layout.siblings.start);
+        let siblings_len = (layout.siblings.len() - 1) as u32;
+        short[12..16].copy_from_slice(&siblings_len.to_le_bytes());
+        for bad in [unterminated, short] {
+            assert!(matches!(
+                DoubleArray::<u8>::from_bytes(&bad),
+                Err(TrieError::TruncatedData)
+            ));
+        }
+    }
+
+    #[test]
+    fn label_leaves_get_their_own_version() {
+        let keys: [&[u8]; 3] = [b"ab", b"ac", b"b"];
+        // A v2 reader from before label leaves existed: it checks the magic
+        // and version, then only finds values on code-0 terminal children.
+        let v2_reader_accepts =
+            |bytes: &[u8]| bytes.len() >= HEADER_SIZE && &bytes[0..4] == MAGIC && bytes[4] == 2;
+
+        let plain = DoubleArray::<u8>::build(&keys);
+        assert_eq!(plain.as_bytes()[4], VERSION);
+        assert_eq!(plain.as_bytes_compact()[4], VERSION_COMPACT);
+        assert!(v2_reader_accepts(&plain.as_bytes()));
+
+        let da = DoubleArray::<u8>::build_prefix_free(&keys);
+        let bytes = da.as_bytes();
+        let compact = da.as_bytes_compact();
+        assert_eq!(bytes[4], VERSION_LABEL_LEAVES);
+        assert_eq!(compact[4], VERSION_COMPACT_LABEL_LEAVES);
+        assert!(!v2_reader_accepts(&bytes));
+        assert!(!v2_reader_accepts(&compact));
+
+        let mut written = Vec::new();
+        da.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+
+        let aligned = crate::OwnedAlignedTrie::<u8>::from_vec(bytes.clone()).unwrap();
+        assert!(matches!(
+            crate::OwnedAlignedTrie::<u8>::from_vec(compact.clone()),
+            Err(TrieError::InvalidVersion)
+        ));
+        for encoded in [&bytes, &compact] {
+            let restored = DoubleArray::<u8>::from_bytes(encoded).unwrap();
+            assert_eq!(restored.as_bytes(), bytes);
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(restored.exact_match(key), Some(i as u32));
+                assert_eq!(aligned.exact_match(key), Some(i as u32));
+            }
+        }
+    }
+
     #[test]
     fn header_alignment() {
         let da = build_empty_u8();
@@ -284,4 +948,54 @@ mod tests {
         assert_eq!(HEADER_SIZE, 24);
         assert!(HEADER_SIZE.is_multiple_of(8));
     }
+
+    /// `as_bytes` of `build(&[[1], [1, 2], [2], [3, 1]])`. Small labels keep
+    /// the code map short.
+    #[rustfmt::skip]
+    const GOLDEN_V2: [u8; 188] = [
+        // Header: magic, version 2, section lengths 80, 40 and 44.
+        0x4c, 0x58, 0x54, 0x52, 0x02, 0x00, 0x00, 0x00,
+        0x50, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00,
+        0x2c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Nodes: (base, check) of 10 slots; IS_LEAF and HAS_LEAF are the MSBs.
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x04, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x80, 0x03, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x80,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x02, 0x00, 0x00, 0x80, 0x07, 0x00, 0x00, 0x00,
+        0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x09, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x80,
+        0x03, 0x00, 0x00, 0x80, 0x08, 0x00, 0x00, 0x00,
+        // Siblings: one u32 per slot.
+        0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Code map.
+        0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+        0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x03, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn as_bytes_matches_golden_bytes() {
+        // Any change here is a format change: node layout (e.g. padding in
+        // `Node`), section order, or build placement. Readers of caches keyed
+        // by content hash depend on these bytes staying put.
+        let keys: [&[u8]; 4] = [&[1], &[1, 2], &[2], &[3, 1]];
+        let da = DoubleArray::<u8>::build(&keys);
+        assert_eq!(da.as_bytes(), GOLDEN_V2);
+
+        let restored = DoubleArray::<u8>::from_bytes(&GOLDEN_V2).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(restored.exact_match(key), Some(i as u32));
+        }
+        assert_eq!(restored.as_bytes(), GOLDEN_V2);
+    }
 }
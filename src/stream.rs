@@ -0,0 +1,166 @@
+use crate::view::TrieView;
+use crate::{DoubleArray, Label};
+
+/// A key found by a [`StreamMatcher`]: it occupies `len` labels of the
+/// stream starting at offset `start`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamMatch {
+    /// Offset of the key's first label in the stream.
+    pub start: usize,
+    /// Length of the key (in labels).
+    pub len: usize,
+    /// The value_id associated with the key.
+    pub value_id: u32,
+}
+
+/// Finds every occurrence of every key in a stream fed one label at a time.
+///
+/// Created by [`DoubleArray::stream_matcher`]. The matcher keeps one trie
+/// node per start offset that is still a prefix of some key, so its state is
+/// bounded by the length of the longest key, not by the stream. There are no
+/// failure links as in Aho-Corasick: each label advances every partial match
+/// and starts a new one, which costs O(active matches) per label. The empty
+/// key, if stored, is never reported.
+pub struct StreamMatcher<'a, L: Label> {
+    view: TrieView<'a, L>,
+    /// `(node, start)` of each partial match, oldest start first.
+    active: Vec<(u32, usize)>,
+    /// Matches completed by the last label fed.
+    completed: Vec<StreamMatch>,
+    /// Number of labels fed so far.
+    pos: usize,
+}
+
+impl<'a, L: Label> StreamMatcher<'a, L> {
+    /// Feeds the next label and returns the keys that end with it, longest
+    /// (earliest start) first.
+    pub fn feed(&mut self, label: L) -> &[StreamMatch] {
+        self.completed.clear();
+        // A key may start at this label, too.
+        self.active.push((0, self.pos));
+        self.pos += 1;
+
+        let code = self.view.code_map.get(label);
+        if code == 0 {
+            // No key contains this label; every partial match ends here.
+            self.active.clear();
+            return &self.completed;
+        }
+        let mut kept = 0;
+        for i in 0..self.active.len() {
+            let (node_idx, start) = self.active[i];
+            let Some(next) = self.view.child(node_idx, code) else {
+                continue;
+            };
+            let value = self.view.terminal_value(next);
+            if let Some(value_id) = value {
+                self.completed.push(StreamMatch {
+                    start,
+                    len: self.pos - start,
+                    value_id,
+                });
+            }
+            if self.view.has_children(next, value.is_some()) {
+                self.active[kept] = (next, start);
+                kept += 1;
+            }
+        }
+        self.active.truncate(kept);
+        &self.completed
+    }
+
+    /// Returns the start offsets of the partial matches: the offsets at which
+    /// the labels fed since then are a proper prefix of some key.
+    pub fn active_starts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.active.iter().map(|&(_, start)| start)
+    }
+
+    /// Returns the number of labels fed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Drops all partial matches and restarts offsets at 0, as for a new
+    /// stream.
+    pub fn reset(&mut self) {
+        self.active.clear();
+        self.completed.clear();
+        self.pos = 0;
+    }
+}
+
+impl<L: Label> DoubleArray<L> {
+    /// Returns a matcher that reports keys occurring anywhere in a stream of
+    /// labels; see [`StreamMatcher`].
+    pub fn stream_matcher(&self) -> StreamMatcher<'_, L> {
+        StreamMatcher {
+            view: self.view(),
+            active: Vec::new(),
+            completed: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_every_keyword_occurrence() {
+        let keys: Vec<&[u8]> = vec![b"am", b"ham", b"sp", b"spam", b"spammer"];
+        let da = DoubleArray::<u8>::build(&keys);
+        let stream = b"xxspamyyhamspammer spa!spam";
+
+        let mut matcher = da.stream_matcher();
+        let mut found = Vec::new();
+        for &b in stream {
+            found.extend_from_slice(matcher.feed(b));
+        }
+        assert_eq!(matcher.position(), stream.len());
+
+        // Every (start, key) pair by brute force, in order of end offset.
+        let mut expected = Vec::new();
+        for end in 1..=stream.len() {
+            for start in 0..end {
+                if let Some(value_id) = da.exact_match(&stream[start..end]) {
+                    expected.push(StreamMatch {
+                        start,
+                        len: end - start,
+                        value_id,
+                    });
+                }
+            }
+        }
+        assert_eq!(found, expected);
+        let starts: Vec<usize> = found.iter().map(|m| m.start).collect();
+        assert_eq!(starts, vec![2, 2, 4, 8, 9, 11, 11, 13, 11, 19, 23, 23, 25]);
+    }
+
+    #[test]
+    fn active_starts_track_partial_matches() {
+        let da = DoubleArray::<u8>::build(&[b"abc" as &[u8], b"bcd"]);
+        let mut matcher = da.stream_matcher();
+        assert!(matcher.feed(b'a').is_empty());
+        assert!(matcher.feed(b'b').is_empty());
+        assert_eq!(matcher.active_starts().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(
+            matcher.feed(b'c'),
+            &[StreamMatch {
+                start: 0,
+                len: 3,
+                value_id: 0
+            }]
+        );
+        assert_eq!(matcher.active_starts().collect::<Vec<_>>(), vec![1]);
+        // An unknown label ends every partial match.
+        assert!(matcher.feed(b'!').is_empty());
+        assert_eq!(matcher.active_starts().count(), 0);
+
+        matcher.reset();
+        assert_eq!(matcher.position(), 0);
+        matcher.feed(b'b');
+        matcher.feed(b'c');
+        assert_eq!(matcher.feed(b'd')[0].start, 0);
+    }
+}
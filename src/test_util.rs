@@ -0,0 +1,13 @@
+//! Helpers shared by the unit tests of several modules.
+
+/// A seeded LCG for reproducible fuzz inputs: each call returns a value in
+/// `0..bound`.
+pub(crate) fn lcg(seed: u64) -> impl FnMut(u64) -> u64 {
+    let mut state = seed;
+    move |bound| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) % bound
+    }
+}
@@ -1,6 +1,10 @@
+use std::ffi::c_void;
 use std::marker::PhantomData;
 
-use crate::{CodeMapper, Label, Node, PrefixMatch, ProbeResult, SearchMatch};
+use crate::{
+    CodeMapper, KeyCallback, Label, MatchKind, Node, NodeInfo, NodeRef, PrefixMatch, ProbeResult,
+    SearchMatch, TokenStep,
+};
 
 /// A borrowed view into a double-array trie, holding references to nodes,
 /// siblings, and the code mapper. All search methods are implemented here
@@ -18,53 +22,235 @@ impl<'a, L: Label> TrieView<'a, L> {
     /// Returns the node index after consuming all labels, or None if traversal fails.
     #[inline]
     pub(crate) fn traverse(&self, key: &[L]) -> Option<u32> {
-        let nodes = self.nodes;
-        let len = nodes.len();
-        let mut node_idx: u32 = 0; // start at root (always valid: deserialization rejects empty nodes)
-        for &label in key {
+        self.traverse_labels(key.iter().copied())
+    }
+
+    /// Like `traverse`, but takes the labels from an iterator, so callers can
+    /// transform them on the fly without collecting a key.
+    #[inline]
+    pub(crate) fn traverse_labels(&self, key: impl IntoIterator<Item = L>) -> Option<u32> {
+        // start at root (always valid: deserialization rejects empty nodes)
+        self.traverse_from(0, key)
+    }
+
+    /// Continues a traversal from `node_idx`, which must be a valid index.
+    #[inline]
+    pub(crate) fn traverse_from(
+        &self,
+        mut node_idx: u32,
+        key: impl IntoIterator<Item = L>,
+    ) -> Option<u32> {
+        for label in key {
+            // A label absent from every key has code 0. This is the whole miss
+            // path for queries from a foreign alphabet: one table lookup (or
+            // only its bounds check) and no node access.
             let code = self.code_map.get(label);
             if code == 0 {
                 return None;
             }
-            // SAFETY: node_idx is a verified index — it was either 0 (root, guaranteed
-            // to exist) or set to next_idx after bounds + check validation below.
-            let next_idx = unsafe { nodes.get_unchecked(node_idx as usize) }.base() ^ code;
-            if next_idx as usize >= len {
-                return None;
-            }
-            // SAFETY: next_idx is within bounds (checked above).
-            if unsafe { nodes.get_unchecked(next_idx as usize) }.check() != node_idx {
-                return None;
-            }
-            node_idx = next_idx;
+            node_idx = self.child(node_idx, code)?;
         }
         Some(node_idx)
     }
 
-    /// Exact match search. Returns the value_id if the key exists.
+    /// Returns how many leading labels of `key` can be followed from the
+    /// root, i.e. the length of the longest prefix of `key` that is a path.
+    pub(crate) fn matched_prefix_len(&self, key: &[L]) -> usize {
+        let mut node_idx = 0u32;
+        for (depth, &label) in key.iter().enumerate() {
+            let code = self.code_map.get(label);
+            match (code != 0).then(|| self.child(node_idx, code)).flatten() {
+                Some(next) => node_idx = next,
+                None => return depth,
+            }
+        }
+        key.len()
+    }
+
+    /// Returns the index of the child of `node_idx` labelled with `code`, if it exists.
+    ///
+    /// `node_idx` must be a valid index (the root, or a value previously returned
+    /// by this method).
     #[inline]
-    pub(crate) fn exact_match(&self, key: &[L]) -> Option<u32> {
-        let node_idx = self.traverse(key)?;
-        // SAFETY: traverse guarantees node_idx is a valid index.
-        let node = unsafe { *self.nodes.get_unchecked(node_idx as usize) };
+    pub(crate) fn child(&self, node_idx: u32, code: u32) -> Option<u32> {
+        let nodes = self.nodes;
+        // SAFETY: node_idx is a verified index — it is either 0 (root, guaranteed
+        // to exist) or was returned by a previous call after bounds + check validation.
+        let next_idx = unsafe { nodes.get_unchecked(node_idx as usize) }.base() ^ code;
+        // Widening u32 -> usize is lossless (usize is at least 32 bits).
+        if next_idx as usize >= nodes.len() {
+            return None;
+        }
+        // SAFETY: next_idx is within bounds (checked above).
+        let next = unsafe { nodes.get_unchecked(next_idx as usize) };
+        if next.check() != node_idx {
+            return None;
+        }
+        // Unused slots are all-zero, so their check reads as the root's index.
+        // Every real child has a non-zero raw base (internal nodes never get
+        // base 0, leaves carry IS_LEAF), and the root is never its own child.
+        if node_idx == 0 && (next_idx == 0 || next.raw_base() == 0) {
+            return None;
+        }
+        Some(next_idx)
+    }
+
+    /// Exact match that walks `key.len()` steps whatever the key, reading one
+    /// node per label, so its running time does not reveal how many leading
+    /// labels matched.
+    ///
+    /// After a mismatch the walk continues from the root with the failure
+    /// recorded in a flag; the per-step checks are combined with bitwise
+    /// operators rather than early returns. The code map lookup still depends
+    /// on each label's value, and cache effects are not controlled.
+    pub(crate) fn exact_match_constant_time(&self, key: &[L]) -> Option<u32> {
+        let nodes = self.nodes;
+        let last = nodes.len() - 1;
+        let mut node_idx = 0u32;
+        let mut matched = true;
+        for &label in key {
+            let code = self.code_map.get(label);
+            let next_idx = nodes[node_idx as usize].base() ^ code;
+            // Clamp rather than bail out, so an out-of-range child still costs a read.
+            let next = nodes[(next_idx as usize).min(last)];
+            let root_phantom = (node_idx == 0) & ((next_idx == 0) | (next.raw_base() == 0));
+            let ok = (code != 0)
+                & (next_idx as usize <= last)
+                & (next.check() == node_idx)
+                & !root_phantom;
+            matched &= ok;
+            let keep = 0u32.wrapping_sub(u32::from(matched));
+            node_idx = next_idx & keep;
+        }
+        let terminal = self.terminal_value(node_idx);
+        terminal.filter(|_| matched)
+    }
 
+    /// Returns the value_id stored in the terminal child of `node_idx`, if any.
+    ///
+    /// `node_idx` must be a valid index.
+    #[inline]
+    pub(crate) fn terminal_value(&self, node_idx: u32) -> Option<u32> {
+        self.terminal(node_idx).map(|(_, value_id)| value_id)
+    }
+
+    /// Returns the index of the terminal (leaf) child of `node_idx` and its
+    /// value_id, if any.
+    ///
+    /// In a prefix-free trie a key's last node may be a leaf itself, with no
+    /// terminal child; it is then its own terminal.
+    ///
+    /// `node_idx` must be a valid index.
+    #[inline]
+    pub(crate) fn terminal(&self, node_idx: u32) -> Option<(u32, u32)> {
+        let nodes = self.nodes;
+        // SAFETY: callers only pass indices obtained from traversal.
+        let node = unsafe { nodes.get_unchecked(node_idx as usize) };
+        if node.is_leaf() {
+            return Some((node_idx, node.value_id()));
+        }
         if !node.has_leaf() {
             return None;
         }
 
         let terminal_idx = node.base();
-        if terminal_idx as usize >= self.nodes.len() {
+        if terminal_idx as usize >= nodes.len() {
             return None;
         }
         // SAFETY: terminal_idx is within bounds (checked above).
-        let terminal = unsafe { *self.nodes.get_unchecked(terminal_idx as usize) };
+        let terminal = unsafe { nodes.get_unchecked(terminal_idx as usize) };
         if terminal.check() == node_idx && terminal.is_leaf() {
-            Some(terminal.value_id())
+            Some((terminal_idx, terminal.value_id()))
         } else {
             None
         }
     }
 
+    /// Counts the root plus every non-default node, i.e. the slots actually
+    /// occupied by the trie. Uses the same predicate as `build`'s trim.
+    pub(crate) fn num_used_nodes(&self) -> usize {
+        1 + self.nodes[1..]
+            .iter()
+            .filter(|n| **n != Node::default())
+            .count()
+    }
+
+    /// Counts the leaf (terminal) nodes, one per key.
+    pub(crate) fn num_keys(&self) -> usize {
+        self.nodes.iter().filter(|n| n.is_leaf()).count()
+    }
+
+    /// Exact match search. Returns the value_id if the key exists.
+    #[inline]
+    pub(crate) fn exact_match(&self, key: &[L]) -> Option<u32> {
+        let node_idx = self.traverse(key)?;
+        self.terminal_value(node_idx)
+    }
+
+    /// Whether any of `keys` exists, stopping at the first hit.
+    pub(crate) fn contains_any(&self, keys: &[impl AsRef<[L]>]) -> bool {
+        keys.iter()
+            .any(|key| self.exact_match(key.as_ref()).is_some())
+    }
+
+    /// Positions in `keys` of the keys that exist, in ascending order.
+    pub(crate) fn matching_indices(&self, keys: &[impl AsRef<[L]>]) -> Vec<usize> {
+        let mut hits = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            if self.exact_match(key.as_ref()).is_some() {
+                hits.push(i);
+            }
+        }
+        hits
+    }
+
+    /// Exact match that tells a prefix-only key apart from a structural miss.
+    #[inline]
+    pub(crate) fn match_or_prefix(&self, key: &[L]) -> MatchKind {
+        match self.traverse(key) {
+            // Every non-terminal node on a key path has children, so a
+            // reachable node without a terminal is a proper prefix. The one
+            // exception is the root of an empty trie.
+            Some(node_idx) => match self.terminal_value(node_idx) {
+                Some(value_id) => MatchKind::Exact(value_id),
+                None if node_idx == 0 && self.first_child(0).is_none() => MatchKind::Absent,
+                None => MatchKind::PrefixOnly,
+            },
+            None => MatchKind::Absent,
+        }
+    }
+
+    /// Exact match of `rest` starting from a node handle, or None if the
+    /// handle does not point into this trie.
+    pub(crate) fn exact_match_from(&self, node: NodeRef, rest: &[L]) -> Option<u32> {
+        // Handles are plain indices; one from another trie may be out of range.
+        if node.index as usize >= self.nodes.len() {
+            return None;
+        }
+        let node_idx = self.traverse_from(node.index, rest.iter().copied())?;
+        self.terminal_value(node_idx)
+    }
+
+    /// Returns structural information about the node reached by `path`,
+    /// or None if no such node exists.
+    ///
+    /// Children are counted by scanning every code, so this is
+    /// O(alphabet_size) and meant for diagnostics rather than hot paths.
+    pub(crate) fn inspect(&self, path: &[L]) -> Option<NodeInfo> {
+        let node_idx = self.traverse(path)?;
+        let node = self.nodes[node_idx as usize];
+        let num_children = (1..self.code_map.alphabet_size())
+            .filter(|&code| self.child(node_idx, code).is_some())
+            .count();
+        Some(NodeInfo {
+            index: node_idx,
+            value_id: self.terminal_value(node_idx),
+            num_children,
+            base: node.base(),
+            check: node.check(),
+        })
+    }
+
     /// Common prefix search. Returns an iterator over all prefixes of `query`
     /// that exist as keys in the trie.
     pub(crate) fn common_prefix_search(self, query: &'a [L]) -> CommonPrefixIter<'a, L> {
@@ -79,43 +265,141 @@ impl<'a, L: Label> TrieView<'a, L> {
 
     /// Predictive search. Returns an iterator over all keys that start with `prefix`.
     pub(crate) fn predictive_search(self, prefix: &[L]) -> PredictiveIter<'a, L> {
-        let start_node = self.traverse(prefix);
-        let mut stack = Vec::new();
-        let key_buf = if start_node.is_some() {
-            prefix.to_vec()
-        } else {
-            Vec::new()
-        };
-        if let Some(node) = start_node {
-            // None label = root entry; key_buf is already set to the prefix.
-            stack.push((node, prefix.len() as u32, None));
-        }
+        self.predictive_search_within(prefix, usize::MAX)
+    }
+
+    /// Predictive search over keys at most `max_extra_len` labels longer than
+    /// `prefix`. Deeper subtrees are pruned, not walked and filtered.
+    pub(crate) fn predictive_search_within(
+        self,
+        prefix: &[L],
+        max_extra_len: usize,
+    ) -> PredictiveIter<'a, L> {
+        self.predictive_search_in(prefix, max_extra_len, Vec::with_capacity(prefix.len()))
+    }
+
+    /// Like `predictive_search_within`, but keeps the walk's path in `path`.
+    /// If `path` has no room for the prefix, no keys are found.
+    pub(crate) fn predictive_search_in<P: KeyPath<L>>(
+        self,
+        prefix: &[L],
+        max_extra_len: usize,
+        mut path: P,
+    ) -> PredictiveIter<'a, L, P> {
+        let start = self
+            .traverse(prefix)
+            .filter(|_| prefix.iter().all(|&l| path.push(l)));
+        let start_node = start.map(|node| self.nodes[node as usize]);
         PredictiveIter {
             view: self,
-            stack,
-            key_buf,
-            children_buf: Vec::new(),
+            start: start.unwrap_or(0),
+            start_value: start_node
+                .filter(|node| node.is_leaf())
+                .map(|node| node.value_id()),
+            cursor: start.and_then(|node| self.first_child(node)),
+            key_buf: path,
+            prefix_len: prefix.len(),
+            max_len: prefix.len().saturating_add(max_extra_len),
+            steps_left: 2 * self.nodes.len(),
+        }
+    }
+
+    /// Calls `callback` with each prefix of `query` that is a key, shortest
+    /// first; see [`KeyCallback`].
+    pub(crate) fn for_each_prefix(
+        self,
+        query: &'a [L],
+        callback: KeyCallback<L>,
+        ctx: *mut c_void,
+    ) {
+        for m in self.common_prefix_search(query) {
+            callback(ctx, query.as_ptr(), m.len, m.value_id);
         }
     }
 
-    /// Finds the first child of `node_idx`.
+    /// Calls `callback` with each key that starts with `prefix`, in
+    /// predictive search order; see [`KeyCallback`].
+    pub(crate) fn for_each_completion(
+        self,
+        prefix: &[L],
+        callback: KeyCallback<L>,
+        ctx: *mut c_void,
+    ) {
+        let mut iter = self.predictive_search(prefix);
+        while iter
+            .next_with(|key, value_id| callback(ctx, key.as_ptr(), key.len(), value_id))
+            .is_some()
+        {}
+    }
+
+    /// Returns the lengths of the shortest and longest keys, or `None` if the
+    /// trie has no keys. Visits every key once.
+    pub(crate) fn key_length_range(self) -> Option<(usize, usize)> {
+        let mut iter = self.predictive_search(&[]);
+        let mut range: Option<(usize, usize)> = None;
+        while let Some(len) = iter.next_with(|key, _| key.len()) {
+            range = Some(match range {
+                Some((min, max)) => (min.min(len), max.max(len)),
+                None => (len, len),
+            });
+        }
+        range
+    }
+
+    /// Predictive search in the exact reverse order of `predictive_search`.
+    pub(crate) fn predictive_search_rev(self, prefix: &[L]) -> PredictiveRevIter<'a, L> {
+        let mut iter = PredictiveRevIter {
+            view: self,
+            stack: Vec::new(),
+            key_buf: prefix.to_vec(),
+            steps_left: 2 * self.nodes.len(),
+        };
+        if let Some(start) = self.traverse(prefix) {
+            if self.nodes[start as usize].is_leaf() {
+                // Revisited like any leaf child, with the last label popped
+                // so that entering it pushes the label back. Only a corrupt
+                // root is a leaf with no label to pop; it has no keys.
+                if let Some(depth) = prefix.len().checked_sub(1) {
+                    iter.stack.push((start, depth));
+                }
+            } else {
+                iter.push_children(start, prefix.len());
+            }
+        }
+        iter
+    }
+
+    /// Maps a non-terminal code back to its label.
+    ///
+    /// Returns `None` if the code is outside the code map or its label does
+    /// not fit in `L`. Neither happens for a trie built by this crate, but a
+    /// corrupt serialized code map can cause both; every path that turns codes
+    /// into labels goes through here and skips such codes alike.
     #[inline]
-    fn first_child(&self, node_idx: u32) -> Option<u32> {
-        let base = self.nodes[node_idx as usize].base();
-        let terminal_idx = base;
-        if terminal_idx != node_idx
-            && (terminal_idx as usize) < self.nodes.len()
-            && self.nodes[terminal_idx as usize].check() == node_idx
-        {
-            return Some(terminal_idx);
-        }
-        for code in 1..self.code_map.alphabet_size() {
-            let idx = base ^ code;
-            if (idx as usize) < self.nodes.len() && self.nodes[idx as usize].check() == node_idx {
-                return Some(idx);
+    pub(crate) fn label_of(&self, code: u32) -> Option<L> {
+        let label = self.code_map.try_reverse(code)?;
+        L::try_from(label).ok()
+    }
+
+    /// Finds the first child of `node_idx`, i.e. the head of its sibling chain.
+    ///
+    /// Chains are ordered by code, so the terminal child comes first when
+    /// present, followed by the lowest-coded non-terminal child.
+    #[inline]
+    pub(crate) fn first_child(&self, node_idx: u32) -> Option<u32> {
+        let node = self.nodes[node_idx as usize];
+        if node.is_leaf() {
+            return None;
+        }
+        if node.has_leaf() {
+            let terminal_idx = node.base();
+            if (terminal_idx as usize) < self.nodes.len()
+                && self.nodes[terminal_idx as usize].check() == node_idx
+            {
+                return Some(terminal_idx);
             }
         }
-        None
+        (1..self.code_map.alphabet_size()).find_map(|code| self.child(node_idx, code))
     }
 
     /// Probe a key. Returns whether the key exists and whether it has children.
@@ -131,60 +415,122 @@ impl<'a, L: Label> TrieView<'a, L> {
             }
         };
 
-        let base = self.nodes[node_idx as usize].base();
+        let value = self.terminal_value(node_idx);
+        ProbeResult {
+            value,
+            has_children: self.has_children(node_idx, value.is_some()),
+        }
+    }
 
-        let terminal_idx = base;
-        if (terminal_idx as usize) < self.nodes.len() {
-            let terminal = self.nodes[terminal_idx as usize];
-            if terminal.check() == node_idx && terminal.is_leaf() {
-                let has_children = self.siblings[terminal_idx as usize] != 0;
-                return ProbeResult {
-                    value: Some(terminal.value_id()),
-                    has_children,
-                };
+    /// Returns whether `node_idx` has non-terminal children. `has_terminal`
+    /// must be whether `terminal_value(node_idx)` is `Some`.
+    #[inline]
+    pub(crate) fn has_children(&self, node_idx: u32, has_terminal: bool) -> bool {
+        if self.nodes[node_idx as usize].is_leaf() {
+            false
+        } else if has_terminal {
+            // The terminal heads the sibling chain; any further link is a real child.
+            let terminal_idx = self.nodes[node_idx as usize].base();
+            self.siblings[terminal_idx as usize] != 0
+        } else {
+            self.first_child(node_idx).is_some()
+        }
+    }
+
+    /// One tokenizer step over the start of `input`, in a single traversal:
+    /// the longest key that is a prefix of `input`, how far the input matches
+    /// the trie structurally, and whether the dictionary continues past it.
+    pub(crate) fn tokenize_step(&self, input: &[L]) -> TokenStep {
+        let mut node_idx = 0u32;
+        let mut consumed = 0usize;
+        let mut value = self.terminal_value(0);
+        let mut longest = value.map(|value_id| PrefixMatch { len: 0, value_id });
+        for &label in input {
+            let code = self.code_map.get(label);
+            if code == 0 {
+                break;
             }
+            let Some(next) = self.child(node_idx, code) else {
+                break;
+            };
+            node_idx = next;
+            consumed += 1;
+            value = self.terminal_value(node_idx);
+            if let Some(value_id) = value {
+                longest = Some(PrefixMatch {
+                    len: consumed,
+                    value_id,
+                });
+            }
+        }
+        TokenStep {
+            longest,
+            consumed,
+            can_extend: self.has_children(node_idx, value.is_some()),
         }
+    }
 
-        let has_children = self.first_child(node_idx).is_some();
-        ProbeResult {
-            value: None,
-            has_children,
+    /// Shortest prefix match: the first key met walking `query` down from
+    /// the root. Stops there instead of following the rest of `query`.
+    pub(crate) fn shortest_prefix_match(&self, query: &[L]) -> Option<PrefixMatch> {
+        let mut node_idx = 0u32;
+        for len in 0..=query.len() {
+            if let Some(value_id) = self.terminal_value(node_idx) {
+                return Some(PrefixMatch { len, value_id });
+            }
+            let &label = query.get(len)?;
+            let code = self.code_map.get(label);
+            if code == 0 {
+                return None;
+            }
+            node_idx = self.child(node_idx, code)?;
         }
+        None
     }
 }
 
 pub(crate) struct CommonPrefixIter<'a, L: Label> {
     view: TrieView<'a, L>,
     query: &'a [L],
+    /// Number of labels consumed. Kept as `usize` so it is never truncated,
+    /// whatever the query length; in practice it cannot exceed the depth of
+    /// the deepest key, since each step must land on an existing node.
     pos: usize,
+    /// Current node. Always obtained from `TrieView::child`, so it is a valid
+    /// index into `nodes` (and therefore < 2^31), independent of `pos`.
     node_idx: u32,
     done: bool,
 }
 
 impl<L: Label> CommonPrefixIter<'_, L> {
+    /// Returns the next match together with the index of its leaf node.
     #[inline]
-    fn check_terminal(&self) -> Option<PrefixMatch> {
-        let nodes = self.view.nodes;
-        // SAFETY: node_idx is always a valid index (starts at root 0, advanced only
-        // after bounds + check validation in try_advance).
-        let node = unsafe { nodes.get_unchecked(self.node_idx as usize) };
-        if !node.has_leaf() {
-            return None;
-        }
-        let terminal_idx = node.base();
-        if terminal_idx as usize >= nodes.len() {
-            return None;
+    pub(crate) fn next_with_leaf(&mut self) -> Option<(PrefixMatch, u32)> {
+        while !self.done {
+            let result = self.check_terminal();
+            if !self.try_advance() {
+                self.done = true;
+            }
+            if result.is_some() {
+                return result;
+            }
         }
-        // SAFETY: terminal_idx bounds-checked above.
-        let terminal = unsafe { nodes.get_unchecked(terminal_idx as usize) };
-        if terminal.check() == self.node_idx && terminal.is_leaf() {
-            Some(PrefixMatch {
-                len: self.pos,
-                value_id: terminal.value_id(),
+        None
+    }
+
+    #[inline]
+    fn check_terminal(&self) -> Option<(PrefixMatch, u32)> {
+        // node_idx is always a valid index (starts at root 0, advanced only
+        // through TrieView::child in try_advance).
+        self.view
+            .terminal(self.node_idx)
+            .map(|(leaf_idx, value_id)| {
+                let m = PrefixMatch {
+                    len: self.pos,
+                    value_id,
+                };
+                (m, leaf_idx)
             })
-        } else {
-            None
-        }
     }
 
     #[inline]
@@ -197,20 +543,15 @@ impl<L: Label> CommonPrefixIter<'_, L> {
         if code == 0 {
             return false;
         }
-        let nodes = self.view.nodes;
-        // SAFETY: node_idx is a valid index (see check_terminal SAFETY comment).
-        let base = unsafe { nodes.get_unchecked(self.node_idx as usize) }.base();
-        let next_idx = base ^ code;
-        if next_idx as usize >= nodes.len() {
-            return false;
-        }
-        // SAFETY: next_idx bounds-checked above.
-        if unsafe { nodes.get_unchecked(next_idx as usize) }.check() != self.node_idx {
-            return false;
+        match self.view.child(self.node_idx, code) {
+            Some(next_idx) => {
+                self.node_idx = next_idx;
+                // Cannot overflow: pos < query.len() <= usize::MAX.
+                self.pos += 1;
+                true
+            }
+            None => false,
         }
-        self.node_idx = next_idx;
-        self.pos += 1;
-        true
     }
 }
 
@@ -218,100 +559,486 @@ impl<L: Label> Iterator for CommonPrefixIter<'_, L> {
     type Item = PrefixMatch;
 
     fn next(&mut self) -> Option<PrefixMatch> {
-        while !self.done {
-            let result = self.check_terminal();
-            if !self.try_advance() {
-                self.done = true;
+        self.next_with_leaf().map(|(m, _)| m)
+    }
+}
+
+/// Stackless pre-order DFS over the subtree below a prefix node.
+///
+/// The walk moves down through `first_child`, across through `siblings`, and
+/// back up through each node's `check` (its parent index), so no frontier is
+/// kept: besides the emitted keys, the iterator holds only `key_buf`, which
+/// is bounded by the depth of the deepest key in the subtree, independent of
+/// its fan-out. Each node is entered once and climbed out of at most once,
+/// so a full enumeration takes O(subtree nodes) steps.
+///
+/// `key_buf` is a `Vec` unless the caller supplies other storage through
+/// `predictive_search_in`. When it runs out of room, the walk ends.
+pub(crate) struct PredictiveIter<'a, L: Label, P = Vec<L>> {
+    view: TrieView<'a, L>,
+    /// The prefix node; climbing stops when its children are exhausted.
+    start: u32,
+    /// The prefix's value_id, not yet emitted, if the prefix node is a leaf
+    /// itself (a key of a prefix-free trie) and so has no terminal child.
+    start_value: Option<u32>,
+    /// Next child node to visit. Its parent's path is in `key_buf`.
+    cursor: Option<u32>,
+    /// Labels of the path to the cursor's parent. Grows on descent and
+    /// shrinks on climb; only cloned when emitting a SearchMatch.
+    key_buf: P,
+    /// Length of the prefix, below which `key_buf` never shrinks.
+    prefix_len: usize,
+    /// Keys longer than this are pruned: `key_buf` never grows past it.
+    max_len: usize,
+    /// Remaining step budget; guards against cycles in malformed data.
+    steps_left: usize,
+}
+
+impl<L: Label, P: KeyPath<L>> PredictiveIter<'_, L, P> {
+    /// Returns the parent of `node_idx` if it is a valid index.
+    #[inline]
+    fn parent(&self, node_idx: u32) -> Option<u32> {
+        let parent = self.view.nodes[node_idx as usize].check();
+        ((parent as usize) < self.view.nodes.len()).then_some(parent)
+    }
+
+    /// Returns the node visited after the subtree of `node_idx`: its next
+    /// sibling, or the next sibling of the nearest ancestor below `start`
+    /// that has one. Pops a label from `key_buf` for each level climbed.
+    fn advance(&mut self, mut node_idx: u32) -> Option<u32> {
+        loop {
+            self.steps_left = self.steps_left.checked_sub(1)?;
+            let sib = self.view.siblings[node_idx as usize];
+            if sib != 0 {
+                return ((sib as usize) < self.view.nodes.len()).then_some(sib);
             }
-            if result.is_some() {
-                return result;
+            let parent = self.parent(node_idx)?;
+            if parent == self.start || self.key_buf.depth() <= self.prefix_len {
+                return None;
+            }
+            self.key_buf.pop();
+            node_idx = parent;
+        }
+    }
+}
+
+impl<L: Label, P: KeyPath<L>> PredictiveIter<'_, L, P> {
+    /// Advances to the next key and passes the borrowed key buffer to
+    /// `encode`, returning its output with the key's value_id. This lets
+    /// callers build their own key type without an intermediate `Vec<L>`.
+    pub(crate) fn next_encoded<T>(&mut self, encode: impl FnOnce(&[L]) -> T) -> Option<(T, u32)> {
+        self.next_with(|key, value_id| (encode(key), value_id))
+    }
+
+    /// Advances to the next key and passes the borrowed key buffer and the
+    /// key's value_id to `f`, returning its output.
+    pub(crate) fn next_with<T>(&mut self, f: impl FnOnce(&[L], u32) -> T) -> Option<T> {
+        if let Some(value_id) = self.start_value.take() {
+            return self.emit(None, |key| f(key, value_id));
+        }
+        while let Some(node_idx) = self.cursor {
+            self.steps_left = self.steps_left.checked_sub(1)?;
+            let Some(parent) = self.parent(node_idx) else {
+                self.cursor = None;
+                break;
+            };
+            let code = self.view.nodes[parent as usize].base() ^ node_idx;
+            if code == 0 {
+                // Terminal child: the key is exactly the parent's path.
+                let node = self.view.nodes[node_idx as usize];
+                if node.is_leaf() {
+                    let result = self.emit(None, |key| f(key, node.value_id()))?;
+                    self.cursor = self.advance(node_idx);
+                    return Some(result);
+                }
+                self.cursor = self.advance(node_idx);
+                continue;
+            }
+
+            // Descend into the child if its label maps back to an L and its
+            // keys are not too long.
+            if self.key_buf.depth() >= self.max_len {
+                self.cursor = self.advance(node_idx);
+                continue;
+            }
+            let label = self.view.label_of(code);
+            let node = self.view.nodes[node_idx as usize];
+            if node.is_leaf() {
+                // A prefix-free trie's leaf key: the key ends at this label.
+                let Some(l) = label else {
+                    self.cursor = self.advance(node_idx);
+                    continue;
+                };
+                let result = self.emit(Some(l), |key| f(key, node.value_id()))?;
+                self.cursor = self.advance(node_idx);
+                return Some(result);
+            }
+            let first = label.and_then(|_| self.view.first_child(node_idx));
+            match (label, first) {
+                (Some(l), Some(first)) => {
+                    // The subtree's keys are all longer than the path.
+                    if !self.key_buf.push(l) {
+                        self.cursor = None;
+                        return None;
+                    }
+                    self.cursor = Some(first);
+                }
+                _ => self.cursor = self.advance(node_idx),
             }
         }
         None
     }
+
+    /// Passes the path, followed by `last` if given, to `f`. Ends the walk
+    /// if `key_buf` has no room for the key.
+    fn emit<T>(&mut self, last: Option<L>, f: impl FnOnce(&[L]) -> T) -> Option<T> {
+        let result = self.key_buf.with_key(last, f);
+        if result.is_none() {
+            self.cursor = None;
+        }
+        result
+    }
 }
 
-pub(crate) struct PredictiveIter<'a, L: Label> {
-    view: TrieView<'a, L>,
-    /// DFS stack: (node_idx, parent_depth, label_to_append).
-    /// `None` label = root entry (prefix node); the key_buf already contains
-    /// the prefix so no label needs to be appended.
-    stack: Vec<(u32, u32, Option<L>)>,
-    /// Shared key buffer. Grows/truncates as DFS proceeds, avoiding per-node
-    /// Vec<L> clones. Only cloned when emitting a SearchMatch.
-    key_buf: Vec<L>,
-    /// Reusable buffer for collecting children within a single `next()` call.
-    children_buf: Vec<(u32, bool)>,
+/// Storage for the path of a `PredictiveIter`.
+pub(crate) trait KeyPath<L> {
+    /// Number of labels on the path.
+    fn depth(&self) -> usize;
+
+    /// Appends `label`; returns false if there is no room for it.
+    fn push(&mut self, label: L) -> bool;
+
+    /// Removes the last label.
+    fn pop(&mut self);
+
+    /// Passes the path, followed by `last` if given, to `f`; returns `None`
+    /// if there is no room for that key.
+    fn with_key<T>(&mut self, last: Option<L>, f: impl FnOnce(&[L]) -> T) -> Option<T>;
+}
+
+impl<L: Label> KeyPath<L> for Vec<L> {
+    fn depth(&self) -> usize {
+        self.len()
+    }
+
+    fn push(&mut self, label: L) -> bool {
+        Vec::push(self, label);
+        true
+    }
+
+    fn pop(&mut self) {
+        Vec::pop(self);
+    }
+
+    fn with_key<T>(&mut self, last: Option<L>, f: impl FnOnce(&[L]) -> T) -> Option<T> {
+        let Some(l) = last else {
+            return Some(f(self));
+        };
+        Vec::push(self, l);
+        let result = f(self);
+        Vec::pop(self);
+        Some(result)
+    }
+}
+
+/// A path kept in a caller's buffer, after the keys written to it so far.
+///
+/// Each key is written where the path already is, then left in place: the
+/// path is read from the last key until it grows again, and only then is
+/// it copied past that key. So the buffer needs no room beyond the keys.
+pub(crate) struct SlicePath<'b, L> {
+    labels: &'b mut [L],
+    /// Length of the keys written, back to back, at the start of `labels`.
+    used: usize,
+    /// The path is `labels[start..start + depth]`; `start < used` while it
+    /// is still read from the last key.
+    start: usize,
+    depth: usize,
+}
+
+impl<'b, L: Label> SlicePath<'b, L> {
+    pub(crate) fn new(labels: &'b mut [L]) -> Self {
+        Self {
+            labels,
+            used: 0,
+            start: 0,
+            depth: 0,
+        }
+    }
+
+    /// Moves the path past the written keys; returns false if it does not fit.
+    fn own(&mut self) -> bool {
+        if self.start != self.used {
+            if self.used + self.depth > self.labels.len() {
+                return false;
+            }
+            let path = self.start..self.start + self.depth;
+            self.labels.copy_within(path, self.used);
+            self.start = self.used;
+        }
+        true
+    }
+}
+
+impl<L: Label> KeyPath<L> for SlicePath<'_, L> {
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn push(&mut self, label: L) -> bool {
+        if !self.own() || self.used + self.depth == self.labels.len() {
+            return false;
+        }
+        self.labels[self.used + self.depth] = label;
+        self.depth += 1;
+        true
+    }
+
+    fn pop(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn with_key<T>(&mut self, last: Option<L>, f: impl FnOnce(&[L]) -> T) -> Option<T> {
+        let len = self.depth + usize::from(last.is_some());
+        if !self.own() || self.used + len > self.labels.len() {
+            return None;
+        }
+        if let Some(l) = last {
+            self.labels[self.used + self.depth] = l;
+        }
+        let result = f(&self.labels[self.used..self.used + len]);
+        self.used += len;
+        Some(result)
+    }
 }
 
 impl<L: Label> Iterator for PredictiveIter<'_, L> {
     type Item = SearchMatch<L>;
 
     fn next(&mut self) -> Option<SearchMatch<L>> {
-        let node_count = self.view.nodes.len();
-        while let Some((node_idx, parent_depth, label)) = self.stack.pop() {
-            // Restore key_buf to the parent's depth, then append this node's label.
-            self.key_buf.truncate(parent_depth as usize);
-            if let Some(l) = label {
-                self.key_buf.push(l);
-            }
-            let depth = self.key_buf.len() as u32;
+        self.next_encoded(<[L]>::to_vec)
+            .map(|(key, value_id)| SearchMatch { key, value_id })
+    }
+}
 
-            let node = &self.view.nodes[node_idx as usize];
-            let base = node.base();
+/// Iterator for `predictive_search_rev`.
+///
+/// Children are pushed in chain (ascending code) order and popped in
+/// reverse, and the terminal child, which heads each chain, pops last: the
+/// exact reverse of `PredictiveIter`'s pre-order.
+pub(crate) struct PredictiveRevIter<'a, L: Label> {
+    view: TrieView<'a, L>,
+    /// Pending nodes with the length of their parent's path.
+    stack: Vec<(u32, usize)>,
+    /// Labels of the path to the most recently entered node.
+    key_buf: Vec<L>,
+    /// Remaining step budget; guards against cycles in malformed data.
+    steps_left: usize,
+}
 
-            self.children_buf.clear();
+impl<L: Label> PredictiveRevIter<'_, L> {
+    /// Pushes the children of `node_idx`, whose path has length `depth`.
+    fn push_children(&mut self, node_idx: u32, depth: usize) {
+        let mut cursor = self.view.first_child(node_idx);
+        while let Some(child) = cursor {
+            let Some(steps) = self.steps_left.checked_sub(1) else {
+                return;
+            };
+            self.steps_left = steps;
+            self.stack.push((child, depth));
+            let sib = self.view.siblings[child as usize];
+            cursor = (sib != 0 && (sib as usize) < self.view.nodes.len()).then_some(sib);
+        }
+    }
+}
 
-            let terminal_idx = base;
-            if (terminal_idx as usize) < node_count
-                && self.view.nodes[terminal_idx as usize].check() == node_idx
-            {
-                self.children_buf.push((terminal_idx, true));
-
-                let mut sib = self.view.siblings[terminal_idx as usize];
-                // Guard against cycles and out-of-range indices in malformed data
-                let mut steps = 0u32;
-                while sib != 0 && (sib as usize) < node_count && (steps as usize) < node_count {
-                    self.children_buf.push((sib, false));
-                    sib = self.view.siblings[sib as usize];
-                    steps += 1;
-                }
-            } else if let Some(first) = self.view.first_child(node_idx) {
-                self.children_buf.push((first, false));
-                let mut sib = self.view.siblings[first as usize];
-                let mut steps = 0u32;
-                while sib != 0 && (sib as usize) < node_count && (steps as usize) < node_count {
-                    self.children_buf.push((sib, false));
-                    sib = self.view.siblings[sib as usize];
-                    steps += 1;
+impl<L: Label> Iterator for PredictiveRevIter<'_, L> {
+    type Item = SearchMatch<L>;
+
+    fn next(&mut self) -> Option<SearchMatch<L>> {
+        while let Some((node_idx, depth)) = self.stack.pop() {
+            let parent = self.view.nodes[node_idx as usize].check();
+            if parent as usize >= self.view.nodes.len() {
+                continue;
+            }
+            let code = self.view.nodes[parent as usize].base() ^ node_idx;
+            self.key_buf.truncate(depth);
+            if code == 0 {
+                let node = self.view.nodes[node_idx as usize];
+                if node.is_leaf() {
+                    return Some(SearchMatch {
+                        key: self.key_buf.clone(),
+                        value_id: node.value_id(),
+                    });
                 }
+                continue;
             }
-
-            let mut result: Option<SearchMatch<L>> = None;
-
-            for i in (0..self.children_buf.len()).rev() {
-                let (child_idx, is_terminal) = self.children_buf[i];
-                if is_terminal {
-                    let child = &self.view.nodes[child_idx as usize];
-                    if child.is_leaf() {
-                        result = Some(SearchMatch {
-                            key: self.key_buf.clone(),
-                            value_id: child.value_id(),
-                        });
-                    }
-                } else {
-                    let child_code = base ^ child_idx;
-                    let label_u32 = self.view.code_map.reverse(child_code);
-                    if let Ok(l) = L::try_from(label_u32) {
-                        self.stack.push((child_idx, depth, Some(l)));
-                    }
+            if let Some(label) = self.view.label_of(code) {
+                self.key_buf.push(label);
+                let node = self.view.nodes[node_idx as usize];
+                if node.is_leaf() {
+                    return Some(SearchMatch {
+                        key: self.key_buf.clone(),
+                        value_id: node.value_id(),
+                    });
                 }
+                self.push_children(node_idx, depth + 1);
             }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DoubleArray;
 
-            if let Some(r) = result {
-                return Some(r);
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn common_prefix_len_is_not_truncated_past_u32() {
+        // Mock an iterator that has already consumed more than u32::MAX labels
+        // and sits on a terminal node (the empty key at the root).
+        let da = DoubleArray::<u8>::build(&[b"" as &[u8], b"a"]);
+        let big = u32::MAX as usize + 7;
+        let mut iter = CommonPrefixIter {
+            view: da.view(),
+            query: b"a",
+            pos: big,
+            node_idx: 0,
+            done: false,
+        };
+        assert_eq!(
+            iter.next(),
+            Some(PrefixMatch {
+                len: big,
+                value_id: 0
+            })
+        );
+        // pos is past the end of the query, so the walk stops cleanly.
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn predictive_state_is_bounded_by_depth() {
+        // Wide: every 4-label string over a 6-letter alphabet (1296 keys, fan-out
+        // 6 at each level). Deep: one 500-label key. A frontier-holding DFS
+        // would keep O(fan-out * depth) entries; this walk keeps only the path.
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for i in 0..6u32.pow(4) {
+            let mut key = Vec::new();
+            let mut n = i;
+            for _ in 0..4 {
+                key.push(b'a' + (n % 6) as u8);
+                n /= 6;
             }
+            keys.push(key);
+        }
+        keys.push(vec![b'a'; 500]);
+        keys.sort();
+        keys.dedup();
+        let max_len = keys.iter().map(Vec::len).max().unwrap();
+
+        let da = DoubleArray::<u8>::build(&keys);
+        let mut iter = da.view().predictive_search(b"");
+        let mut found = Vec::new();
+        while let Some(m) = iter.next() {
+            assert!(iter.key_buf.len() <= max_len);
+            found.push(m.key);
+        }
+        found.sort();
+        assert_eq!(found, keys);
+    }
+
+    #[test]
+    fn predictive_stops_on_sibling_cycle() {
+        let da = DoubleArray::<u8>::build(&[b"a" as &[u8], b"b", b"c"]);
+        let mut siblings = da.siblings.clone();
+        // Point the last child of the root back at the first to form a cycle.
+        let view = da.view();
+        let first = view.first_child(0).unwrap();
+        let mut last = first;
+        while siblings[last as usize] != 0 {
+            last = siblings[last as usize];
+        }
+        siblings[last as usize] = first;
+        let view = TrieView {
+            siblings: &siblings,
+            ..view
+        };
+        assert!(view.predictive_search(b"").count() <= 2 * da.nodes.len());
+    }
+
+    #[test]
+    fn predictive_rev_skips_leaf_root() {
+        // A zero-copy loader accepts a root flagged as a leaf from corrupt bytes.
+        let da = DoubleArray::<u8>::build(&[b"a" as &[u8], b"b"]);
+        let mut nodes = da.nodes.clone();
+        nodes[0].set_leaf(5);
+        let view = TrieView {
+            nodes: &nodes,
+            ..da.view()
+        };
+        assert_eq!(view.predictive_search_rev(b"").count(), 0);
+        assert_eq!(view.predictive_search_rev(b"a").count(), 0);
+    }
+
+    #[test]
+    fn unmapped_alphabet_misses_everywhere() {
+        let hiragana: Vec<Vec<char>> = ["あ", "あい", "い", "かな"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        let da = DoubleArray::<char>::build(&hiragana);
+        let view = da.view();
+        // Katakana and ASCII never occur in the keys, so every code is 0.
+        for q in ["ア", "アイ", "カナ", "a", "zz", "\u{10FFFF}"] {
+            let q: Vec<char> = q.chars().collect();
+            assert_eq!(view.traverse(&q), None);
+            assert_eq!(view.exact_match(&q), None);
+            assert_eq!(view.match_or_prefix(&q), MatchKind::Absent);
+            assert!(!view.probe(&q).has_children);
+            assert_eq!(view.common_prefix_search(&q).count(), 0);
+            assert_eq!(view.predictive_search(&q).count(), 0);
+        }
+        // A mapped label followed by an unmapped one misses at the second.
+        assert_eq!(view.exact_match(&['あ', 'ア']), None);
+        assert_eq!(view.common_prefix_search(&['あ', 'ア']).count(), 1);
+    }
+
+    #[test]
+    fn corrupt_reverse_codes_are_skipped_consistently() {
+        let da = DoubleArray::<u8>::build(&[b"ab" as &[u8], b"ac", b"b"]);
+        let code_c = da.code_map.get(b'c');
+        assert_eq!(code_c, da.code_map.alphabet_size() - 1);
+        let bytes = da.code_map.as_bytes();
+        let table_len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let reverse_at = 12 + table_len * 4 + code_c as usize * 4;
+
+        // 'c' maps back to a value that is not a u8.
+        let mut out_of_range = bytes.clone();
+        out_of_range[reverse_at..reverse_at + 4].copy_from_slice(&300u32.to_le_bytes());
+        // 'c' has no reverse entry at all.
+        let mut truncated = bytes.clone();
+        truncated[4..8].copy_from_slice(&code_c.to_le_bytes());
+        truncated.truncate(reverse_at);
+
+        for corrupt in [out_of_range, truncated] {
+            let (code_map, _) = CodeMapper::from_bytes(&corrupt).unwrap();
+            let view = TrieView {
+                code_map: &code_map,
+                ..da.view()
+            };
+            assert_eq!(view.label_of(code_c), None);
+            // Reconstructing keys skips the unmappable subtree...
+            let keys: Vec<Vec<u8>> = view.predictive_search(b"").map(|m| m.key).collect();
+            assert_eq!(keys, vec![b"ab".to_vec(), b"b".to_vec()]);
+            assert_eq!(view.predictive_search(b"a").count(), 1);
+            // ...while lookups never reverse codes and still find the key.
+            assert_eq!(view.exact_match(b"ac"), Some(1));
+            assert_eq!(view.common_prefix_search(b"acd").count(), 1);
+            assert_eq!(view.probe(b"a").value, None);
+            assert!(view.probe(b"a").has_children);
         }
-        None
     }
 }